@@ -0,0 +1,127 @@
+// src/cli/message_options.rs
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde_json::{Map, Value};
+
+use crate::event_attributes::{LogLevel, MessageAttributes};
+
+/// Options shared by `tracer log` and `tracer alert`.
+#[derive(Args, Debug, Default)]
+pub struct MessageOptions {
+    /// Severity of the message
+    #[clap(long, value_enum)]
+    pub level: Option<LogLevel>,
+    /// Attribute to attach, numbers and booleans are sent as such. Can be repeated
+    #[clap(long = "attr", value_name = "KEY=VALUE", value_parser = parse_attribute)]
+    pub attributes: Vec<(String, Value)>,
+    /// JSON file with an object of attributes to attach, overridden by --attr
+    #[clap(long, value_name = "FILE")]
+    pub json: Option<PathBuf>,
+    /// Pipeline step the message is about
+    #[clap(long)]
+    pub step: Option<String>,
+}
+
+impl MessageOptions {
+    pub fn into_attributes(self) -> Result<MessageAttributes> {
+        let mut fields = match &self.json {
+            Some(path) => read_json_attributes(path)?,
+            None => Map::new(),
+        };
+        fields.extend(self.attributes);
+
+        Ok(MessageAttributes {
+            level: self.level,
+            step: self.step,
+            fields,
+        })
+    }
+}
+
+fn read_json_attributes(path: &Path) -> Result<Map<String, Value>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    match serde_json::from_str(&content)
+        .with_context(|| format!("Invalid JSON in {}", path.display()))?
+    {
+        Value::Object(fields) => Ok(fields),
+        _ => bail!("{} must contain a JSON object", path.display()),
+    }
+}
+
+/// Parses `key=value`, keeping the value a string unless it is a number or a boolean.
+pub(super) fn parse_attribute(attribute: &str) -> Result<(String, Value)> {
+    let Some((key, value)) = attribute.split_once('=') else {
+        bail!("Expected KEY=VALUE, got {}", attribute);
+    };
+    if key.is_empty() {
+        bail!("Empty attribute name in {}", attribute);
+    }
+
+    let value = match serde_json::from_str(value) {
+        Ok(typed @ (Value::Number(_) | Value::Bool(_))) => typed,
+        _ => Value::String(value.to_string()),
+    };
+
+    Ok((key.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_attribute() {
+        assert_eq!(
+            parse_attribute("samples=12").unwrap(),
+            ("samples".to_string(), json!(12))
+        );
+        assert_eq!(parse_attribute("ratio=0.5").unwrap().1, json!(0.5));
+        assert_eq!(parse_attribute("passed=true").unwrap().1, json!(true));
+        assert_eq!(parse_attribute("verdict=fail").unwrap().1, json!("fail"));
+        assert_eq!(parse_attribute("list=[1, 2]").unwrap().1, json!("[1, 2]"));
+        assert_eq!(parse_attribute("query=a=b").unwrap().1, json!("a=b"));
+        assert_eq!(parse_attribute("empty=").unwrap().1, json!(""));
+
+        assert!(parse_attribute("samples").is_err());
+        assert!(parse_attribute("=12").is_err());
+    }
+
+    #[test]
+    fn test_into_attributes() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let json_path = directory.path().join("qc.json");
+        std::fs::write(&json_path, r#"{"samples": 10, "verdict": "pass"}"#)?;
+
+        let options = MessageOptions {
+            level: Some(LogLevel::Warning),
+            attributes: vec![("samples".to_string(), json!(12))],
+            json: Some(json_path),
+            step: Some("qc".to_string()),
+        };
+        let attributes = options.into_attributes()?;
+
+        assert_eq!(attributes.level, Some(LogLevel::Warning));
+        assert_eq!(attributes.step.as_deref(), Some("qc"));
+        assert_eq!(
+            Value::Object(attributes.fields),
+            json!({ "samples": 12, "verdict": "pass" })
+        );
+
+        assert!(MessageOptions::default().into_attributes()?.is_empty());
+
+        let not_an_object = directory.path().join("list.json");
+        std::fs::write(&not_an_object, "[1, 2]")?;
+        let options = MessageOptions {
+            json: Some(not_an_object),
+            ..Default::default()
+        };
+        assert!(options.into_attributes().is_err());
+
+        Ok(())
+    }
+}
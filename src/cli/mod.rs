@@ -1,24 +1,31 @@
 // src/cli/mod.rs
 use crate::{
     config_manager::ConfigManager,
-    daemon_communication::client::{
-        send_alert_request, send_end_run_request, send_log_request,
-        send_log_short_lived_process_request, send_start_run_request, send_terminate_request,
-        send_update_tags_request, send_upload_file_request,
-    },
+    daemon_communication::client::DaemonClient,
+    event_attributes::{event_schema, RunDetails},
     process_watcher::ProcessWatcher,
-    run, start_daemon, SOCKET_PATH,
+    run,
+    run_scope::RunAnchor,
+    runtime_paths::RuntimePaths,
+    start_daemon,
 };
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use message_options::{parse_attribute, MessageOptions};
 use nondaemon_commands::{
-    clean_up_after_daemon, print_config_info_sync, setup_config, update_tracer,
+    begin_step_sync, clean_up_after_daemon, end_run_sync, end_step_sync, print_config_info_sync,
+    print_events_sync, print_processes_sync, print_status_sync, replay_archive_sync, run_step_sync,
+    setup_config, start_run_sync, update_tracer,
 };
+use serde_json::Value;
 
 use std::{env, fs::canonicalize};
 use sysinfo::System;
+mod message_options;
 mod nondaemon_commands;
 
+const DEFAULT_ARCHIVE_DIR: &str = "tracer-archive";
+
 #[derive(Parser)]
 #[clap(
     name = "tracer",
@@ -28,6 +35,14 @@ mod nondaemon_commands;
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
+    /// Directory of the daemon's pid file, socket, logs, spool and state, run one daemon per
+    /// directory [default: $XDG_RUNTIME_DIR/tracer, with the spool and state in
+    /// $XDG_STATE_HOME/tracer]
+    #[clap(long, global = true)]
+    pub runtime_dir: Option<String>,
+    /// Socket of the daemon to talk to, instead of the one in the runtime directory
+    #[clap(long, global = true)]
+    pub socket: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -49,13 +64,28 @@ pub enum Commands {
     },
 
     /// Log a message to the service
-    Log { message: String },
+    Log {
+        message: String,
+        #[clap(flatten)]
+        options: MessageOptions,
+    },
 
     /// Send an alert to the service, sending an e-mail
-    Alert { message: String },
+    Alert {
+        message: String,
+        #[clap(flatten)]
+        options: MessageOptions,
+    },
 
     /// Start the daemon
-    Init,
+    Init {
+        /// Run without contacting the service, writing everything to a local archive instead
+        #[clap(long)]
+        offline: bool,
+        /// Directory of the offline archive
+        #[clap(long, requires = "offline", default_value = DEFAULT_ARCHIVE_DIR)]
+        archive_dir: String,
+    },
 
     /// Stop the daemon
     Terminate,
@@ -66,14 +96,70 @@ pub enum Commands {
     /// Shows the current configuration and the daemon status
     Info,
 
+    /// Shows the health of the running daemon: current run, buffered events, submissions and errors
+    Status {
+        /// Print the status as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
     /// Update the daemon to the latest version
     Update,
 
-    /// Start a new pipeline run
-    Start,
+    /// Print the events the daemon recorded as JSON lines, starting with the ones not submitted yet
+    Events {
+        /// Keep printing new events as they are recorded
+        #[clap(long, short)]
+        follow: bool,
+        /// Only print events of this type, e.g. tool_execution. Can be repeated
+        #[clap(long = "type", value_name = "EVENT_TYPE")]
+        event_types: Vec<String>,
+    },
 
-    /// End the current pipeline run
-    End,
+    /// List the tool processes the daemon is tracking
+    Ps {
+        /// Print the processes as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Start a new pipeline run, printing the metadata it was assigned
+    Start {
+        /// Name of the run, the service picks one otherwise
+        #[clap(long)]
+        name: Option<String>,
+        /// Identifier of the run in another system, e.g. a LIMS batch
+        #[clap(long)]
+        external_id: Option<String>,
+        /// Tag of the run. Can be repeated
+        #[clap(long = "tag")]
+        tags: Vec<String>,
+        /// Pipeline parameter, numbers and booleans are sent as such. Can be repeated
+        #[clap(long = "param", value_name = "KEY=VALUE", value_parser = parse_attribute)]
+        params: Vec<(String, Value)>,
+        /// Description of the run
+        #[clap(long)]
+        description: Option<String>,
+        /// Scope the run to the tools of this shell or directory, alongside the daemon's run
+        #[clap(long, value_enum)]
+        scope: Option<RunScopeOption>,
+        /// Print the run as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// End the current pipeline run, printing its summary
+    End {
+        /// End the run scoped to this shell or directory instead
+        #[clap(long, value_enum)]
+        scope: Option<RunScopeOption>,
+    },
+
+    /// Mark a step of the current pipeline run, tagging the tools started during it
+    Step {
+        #[clap(subcommand)]
+        command: StepCommand,
+    },
 
     /// Test the configuration by sending a request to the service
     Test,
@@ -95,58 +181,182 @@ pub enum Commands {
 
     /// Shows the current version of the daemon
     Version,
+
+    /// Send an archive recorded with `tracer init --offline` to the service
+    Replay { archive: String },
+
+    /// Print the JSON Schema of the events the daemon sends and exports
+    Schema,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StepCommand {
+    /// Start a step, nested in the step already running
+    Begin {
+        name: String,
+        /// Mark the step in the run scoped to this shell or directory instead
+        #[clap(long, value_enum)]
+        scope: Option<RunScopeOption>,
+    },
+    /// End the step and the steps still running inside it
+    End {
+        name: String,
+        /// End the step in the run scoped to this shell or directory instead
+        #[clap(long, value_enum)]
+        scope: Option<RunScopeOption>,
+    },
+    /// Run a command inside a step, e.g. `tracer step run align -- bwa mem ref.fa reads.fq`
+    Run {
+        name: String,
+        /// Mark the step in the run scoped to this shell or directory instead
+        #[clap(long, value_enum)]
+        scope: Option<RunScopeOption>,
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+/// What `tracer start --scope` scopes a run to.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum RunScopeOption {
+    /// The shell running tracer, and every tool it starts
+    Shell,
+    /// The current directory, and every tool running in it or below it
+    Cwd,
+}
+
+impl RunScopeOption {
+    fn anchor(self) -> Result<RunAnchor> {
+        Ok(match self {
+            RunScopeOption::Shell => RunAnchor::RootPid(std::os::unix::process::parent_id()),
+            RunScopeOption::Cwd => {
+                RunAnchor::WorkingDirectory(env::current_dir()?.to_string_lossy().to_string())
+            }
+        })
+    }
 }
 
 pub fn process_cli() -> Result<()> {
     let cli = Cli::parse();
+    let paths = RuntimePaths::resolve(
+        cli.runtime_dir.as_deref(),
+        ConfigManager::load_config().runtime_dir.as_deref(),
+        cli.socket.as_deref(),
+    )?;
 
     match &cli.command {
-        Commands::Init => {
-            let test_result = ConfigManager::test_service_config_sync();
-            if test_result.is_err() {
-                print_config_info_sync()?;
-                return Ok(());
+        Commands::Init {
+            offline,
+            archive_dir,
+        } => {
+            if !offline {
+                let test_result = ConfigManager::test_service_config_sync();
+                if let Err(error) = test_result {
+                    print_config_info_sync(&paths)?;
+                    return Err(error.context("Failed to communicate with the API service"));
+                }
             }
             println!("Starting daemon...");
             let current_working_directory = env::current_dir()?;
-            let result = start_daemon();
-            if result.is_err() {
-                println!("Failed to start daemon. Maybe the daemon is already running? If it's not, run `tracer cleanup` to clean up the previous daemon files.");
-                return Ok(());
+            // The daemon changes its working directory, so resolve the archive path now
+            let offline_archive = offline.then(|| current_working_directory.join(archive_dir));
+            if let Some(archive) = &offline_archive {
+                println!("Running offline, writing to {}", archive.display());
             }
-            run(current_working_directory.to_str().unwrap().to_string())?;
-            clean_up_after_daemon()
+            start_daemon(*offline, &paths).context(
+                "Failed to start daemon. Maybe the daemon is already running? If it's not, run `tracer cleanup` to clean up the previous daemon files.",
+            )?;
+            run(
+                current_working_directory.to_str().unwrap().to_string(),
+                offline_archive,
+                paths.clone(),
+            )?;
+            clean_up_after_daemon(&paths)
+        }
+        Commands::Replay { archive } => replay_archive_sync(archive),
+        Commands::Schema => {
+            println!("{}", serde_json::to_string_pretty(&event_schema())?);
+            Ok(())
         }
         Commands::Test => {
-            let result = ConfigManager::test_service_config_sync();
-            if result.is_ok() {
-                println!("Tracer was able to successfully communicate with the API service.");
-            }
+            ConfigManager::test_service_config_sync()
+                .context("Failed to communicate with the API service")?;
+            println!("Tracer was able to successfully communicate with the API service.");
             Ok(())
         }
         Commands::Cleanup => {
-            let result = clean_up_after_daemon();
+            let result = clean_up_after_daemon(&paths);
             if result.is_ok() {
                 println!("Daemon files cleaned up successfully.");
             }
             result
         }
-        Commands::ApplyBashrc => ConfigManager::setup_aliases(),
-        Commands::Info => print_config_info_sync(),
-        _ => run_async_command(cli.command),
+        Commands::ApplyBashrc => ConfigManager::setup_aliases(&paths),
+        Commands::Info => print_config_info_sync(&paths),
+        Commands::Start {
+            name,
+            external_id,
+            tags,
+            params,
+            description,
+            scope,
+            json,
+        } => {
+            let details = RunDetails {
+                external_id: external_id.clone(),
+                tags: tags.clone(),
+                params: params.iter().cloned().collect(),
+                description: description.clone(),
+                anchor: scope.map(RunScopeOption::anchor).transpose()?,
+            };
+            start_run_sync(&paths, name.clone(), details, *json)
+        }
+        Commands::End { scope } => {
+            end_run_sync(&paths, scope.map(RunScopeOption::anchor).transpose()?)
+        }
+        Commands::Step { command } => match command {
+            StepCommand::Begin { name, scope } => {
+                begin_step_sync(&paths, name, scope.map(RunScopeOption::anchor).transpose()?)
+            }
+            StepCommand::End { name, scope } => {
+                end_step_sync(&paths, name, scope.map(RunScopeOption::anchor).transpose()?)
+            }
+            StepCommand::Run {
+                name,
+                scope,
+                command,
+            } => run_step_sync(
+                &paths,
+                name,
+                scope.map(RunScopeOption::anchor).transpose()?,
+                command,
+            ),
+        },
+        Commands::Status { json } => print_status_sync(&paths, *json),
+        Commands::Ps { json } => print_processes_sync(&paths, *json),
+        Commands::Events {
+            follow,
+            event_types,
+        } => print_events_sync(&paths, event_types, *follow),
+        _ => run_async_command(cli.command, &paths),
     }
 }
 
 #[tokio::main]
-pub async fn run_async_command(commands: Commands) -> Result<()> {
+pub async fn run_async_command(commands: Commands, paths: &RuntimePaths) -> Result<()> {
+    let client = DaemonClient::new(paths.socket.as_str());
     let result = match commands {
-        Commands::Log { message } => send_log_request(SOCKET_PATH, message).await,
-        Commands::Alert { message } => send_alert_request(SOCKET_PATH, message).await,
-        Commands::Terminate => send_terminate_request(SOCKET_PATH).await,
-        Commands::Start => send_start_run_request(SOCKET_PATH).await,
-        Commands::End => send_end_run_request(SOCKET_PATH).await,
+        Commands::Log { message, options } => match options.into_attributes() {
+            Ok(attributes) => client.log(message, attributes).await,
+            Err(error) => Err(error),
+        },
+        Commands::Alert { message, options } => match options.into_attributes() {
+            Ok(attributes) => client.alert(message, attributes).await,
+            Err(error) => Err(error),
+        },
+        Commands::Terminate => client.terminate().await,
         Commands::Update => update_tracer().await,
-        Commands::Tag { tags } => send_update_tags_request(SOCKET_PATH, &tags).await,
+        Commands::Tag { tags } => client.tag(&tags).await,
         Commands::Setup {
             api_key,
             service_url,
@@ -154,6 +364,7 @@ pub async fn run_async_command(commands: Commands) -> Result<()> {
             batch_submission_interval_ms,
         } => {
             setup_config(
+                paths,
                 &api_key,
                 &service_url,
                 &process_polling_interval_ms,
@@ -163,19 +374,18 @@ pub async fn run_async_command(commands: Commands) -> Result<()> {
         }
         Commands::LogShortLivedProcess { command } => {
             let data = ProcessWatcher::gather_short_lived_process_data(&System::new(), &command);
-            send_log_short_lived_process_request(SOCKET_PATH, data).await
+            client.log_short_lived_process(data).await
         }
         Commands::Upload { file_path } => {
-            let path = canonicalize(&file_path);
-            if let Err(e) = path {
-                println!(
-                    "Failed to find the file. Please provide the full path to the file. Error: {}",
-                    e
-                );
-                return Ok(());
+            match canonicalize(&file_path).with_context(|| {
+                format!(
+                    "Failed to find {}. Please provide the full path to the file",
+                    file_path
+                )
+            }) {
+                Ok(path) => client.upload(&path).await,
+                Err(error) => Err(error),
             }
-
-            send_upload_file_request(SOCKET_PATH, &path.unwrap()).await
         }
         _ => {
             println!("Command not implemented yet");
@@ -183,11 +393,9 @@ pub async fn run_async_command(commands: Commands) -> Result<()> {
         }
     };
 
-    if result.is_err() {
-        println!("Failed to send command to the daemon. Maybe the daemon is not running? If it's not, run `tracer init` to start the daemon.");
-    } else {
-        println!("Command sent successfully.")
-    }
+    // Returning the error makes the process exit with a non-zero status, so scripts can check it
+    result.context("Command failed")?;
+    println!("Command completed successfully.");
 
     Ok(())
 }
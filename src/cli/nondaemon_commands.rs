@@ -1,24 +1,38 @@
-use std::process::Command;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use std::result::Result::Ok;
 
 use crate::{
-    config_manager::{ConfigManager, INTERCEPTOR_STDOUT_FILE},
-    daemon_communication::client::{send_info_request, send_refresh_config_request},
-    FILE_CACHE_DIR, PID_FILE, REPO_NAME, REPO_OWNER, SOCKET_PATH, STDERR_FILE, STDOUT_FILE,
+    config_manager::ConfigManager,
+    daemon_communication::{
+        client::DaemonClient,
+        structs::{InfoResponse, StatusResponse},
+    },
+    event_attributes::RunDetails,
+    http_client::configure_http_client,
+    offline_archive,
+    process_watcher::TrackedProcess,
+    run_scope::RunAnchor,
+    run_summary::format_bytes,
+    runtime_paths::RuntimePaths,
+    REPO_NAME, REPO_OWNER,
 };
 
-pub fn clean_up_after_daemon() -> Result<()> {
-    std::fs::remove_file(PID_FILE).context("Failed to remove pid file")?;
-    std::fs::remove_file(STDOUT_FILE).context("Failed to remove stdout file")?;
-    std::fs::remove_file(STDERR_FILE).context("Failed to remove stderr file")?;
-    let _ = std::fs::remove_file(INTERCEPTOR_STDOUT_FILE).context("Failed to remove stdout file");
-    std::fs::remove_dir_all(FILE_CACHE_DIR).context("Failed to remove cache directory")?;
+pub fn clean_up_after_daemon(paths: &RuntimePaths) -> Result<()> {
+    std::fs::remove_file(&paths.pid_file).context("Failed to remove pid file")?;
+    std::fs::remove_file(&paths.stdout_file).context("Failed to remove stdout file")?;
+    std::fs::remove_file(&paths.stderr_file).context("Failed to remove stderr file")?;
+    let _ = std::fs::remove_file(&paths.interceptor_stdout_file)
+        .context("Failed to remove stdout file");
+    std::fs::remove_dir_all(&paths.file_cache_dir).context("Failed to remove cache directory")?;
     Ok(())
 }
 
-pub async fn print_config_info() -> Result<()> {
+pub async fn print_config_info(paths: &RuntimePaths) -> Result<()> {
     let config = ConfigManager::load_config();
     println!("Service URL: {}", config.service_url);
     println!("API Key: {}", config.api_key);
@@ -31,7 +45,10 @@ pub async fn print_config_info() -> Result<()> {
         config.batch_submission_interval_ms
     );
     println!("Daemon version: {}", env!("CARGO_PKG_VERSION"));
-    let daemon_status = send_info_request(SOCKET_PATH).await;
+    println!("Runtime directory: {}", paths.directory);
+    println!("State directory: {}", paths.state_directory);
+    println!("Socket: {}", paths.socket);
+    let daemon_status = DaemonClient::new(paths.socket.as_str()).info().await;
     if let Ok(info) = daemon_status {
         if !info.run_name.is_empty() {
             println!("Run name: {}", info.run_name);
@@ -45,13 +62,397 @@ pub async fn print_config_info() -> Result<()> {
     Ok(())
 }
 
-pub fn print_config_info_sync() -> Result<()> {
+pub fn print_config_info_sync(paths: &RuntimePaths) -> Result<()> {
     let runtime = tokio::runtime::Runtime::new().unwrap();
-    runtime.block_on(print_config_info())?;
+    runtime.block_on(print_config_info(paths))?;
     Ok(())
 }
 
+fn format_timestamp(timestamp: Option<DateTime<Utc>>) -> String {
+    timestamp
+        .map(|timestamp| timestamp.to_rfc3339())
+        .unwrap_or_else(|| "never".to_string())
+}
+
+fn format_status_table(status: &StatusResponse) -> String {
+    let mut rows = vec![
+        ("Daemon version", status.version.clone()),
+        ("Instance ID", status.instance_id.clone()),
+        ("Started at", status.started_at.to_rfc3339()),
+        ("Uptime", format!("{} s", status.uptime_seconds)),
+    ];
+
+    match &status.run {
+        Some(run) => rows.extend([
+            ("Run name", run.run_name.clone()),
+            ("Run ID", run.run_id.clone()),
+            ("Service name", run.service_name.clone()),
+            ("Run started at", run.started_at.to_rfc3339()),
+        ]),
+        None => rows.push(("Run", "none".to_string())),
+    }
+    for run in &status.scoped_runs {
+        let scope = run
+            .anchor
+            .as_ref()
+            .map(|anchor| format!(" for {}", anchor.describe()))
+            .unwrap_or_default();
+        rows.push((
+            "Scoped run",
+            format!("{} ({}){}", run.run_name, run.run_id, scope),
+        ));
+    }
+
+    rows.extend([
+        ("Tracked processes", status.tracked_processes.to_string()),
+        ("Buffered events", status.buffered_events.to_string()),
+        ("Spooled batches", status.spooled_batches.to_string()),
+        ("Dropped batches", status.dropped_batches.to_string()),
+        (
+            "Pending file uploads",
+            status.pending_file_uploads.to_string(),
+        ),
+        (
+            "Last successful submission",
+            format_timestamp(status.last_successful_submission),
+        ),
+        (
+            "Last failed submission",
+            format_timestamp(status.last_failed_submission),
+        ),
+        (
+            "Submission failures",
+            status.submission_failures.to_string(),
+        ),
+        (
+            "File upload failures",
+            status.file_upload_failures.to_string(),
+        ),
+    ]);
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(label, value)| format!("{:<width$}  {}\n", label, value, width = width))
+        .collect()
+}
+
+fn format_started_run(run: &InfoResponse) -> String {
+    let mut rows = vec![
+        ("Run ID", run.run_id.clone()),
+        ("Service name", run.service_name.clone()),
+    ];
+
+    let details = &run.details;
+    if let Some(external_id) = &details.external_id {
+        rows.push(("External ID", external_id.clone()));
+    }
+    if !details.tags.is_empty() {
+        rows.push(("Tags", details.tags.join(", ")));
+    }
+    if let Some(description) = &details.description {
+        rows.push(("Description", description.clone()));
+    }
+    if let Some(anchor) = &details.anchor {
+        rows.push(("Scope", anchor.describe()));
+    }
+    for (key, value) in &details.params {
+        let value = value
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| value.to_string());
+        rows.push(("Param", format!("{}={}", key, value)));
+    }
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    let rows: String = rows
+        .iter()
+        .map(|(label, value)| format!("{:<width$}  {}\n", label, value, width = width))
+        .collect();
+
+    format!("Started a new run with name: {}\n{}", run.run_name, rows)
+}
+
+/// Starts a run and prints what it was assigned, as JSON for scripts with `json`.
+pub async fn start_run(
+    paths: &RuntimePaths,
+    name: Option<String>,
+    details: RunDetails,
+    json: bool,
+) -> Result<()> {
+    let run = DaemonClient::new(paths.socket.as_str())
+        .start_run(name, details)
+        .await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&run)?);
+    } else {
+        print!("{}", format_started_run(&run));
+    }
+    Ok(())
+}
+
+pub fn start_run_sync(
+    paths: &RuntimePaths,
+    name: Option<String>,
+    details: RunDetails,
+    json: bool,
+) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(start_run(paths, name, details, json))
+}
+
+/// Ends a run and prints its summary.
+pub async fn end_run(paths: &RuntimePaths, anchor: Option<RunAnchor>) -> Result<()> {
+    let summary = DaemonClient::new(paths.socket.as_str())
+        .end_run(anchor)
+        .await?;
+
+    if let Some(summary) = summary {
+        print!("{}", summary.to_markdown());
+    }
+    Ok(())
+}
+
+pub fn end_run_sync(paths: &RuntimePaths, anchor: Option<RunAnchor>) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(end_run(paths, anchor))
+}
+
+pub async fn begin_step(paths: &RuntimePaths, name: &str, anchor: Option<RunAnchor>) -> Result<()> {
+    let step = DaemonClient::new(paths.socket.as_str())
+        .begin_step(name, anchor)
+        .await?;
+
+    println!("Started step {}", step.path);
+    Ok(())
+}
+
+pub fn begin_step_sync(paths: &RuntimePaths, name: &str, anchor: Option<RunAnchor>) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(begin_step(paths, name, anchor))
+}
+
+pub async fn end_step(paths: &RuntimePaths, name: &str, anchor: Option<RunAnchor>) -> Result<()> {
+    let steps = DaemonClient::new(paths.socket.as_str())
+        .end_step(name, anchor)
+        .await?;
+
+    for step in steps {
+        println!("Ended step {}", step.path);
+    }
+    Ok(())
+}
+
+pub fn end_step_sync(paths: &RuntimePaths, name: &str, anchor: Option<RunAnchor>) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(end_step(paths, name, anchor))
+}
+
+/// Runs `command` inside a step, ending the step once the command exits even when it failed
+/// or was interrupted. Exits with the command's status when it failed, so pipelines see the
+/// failure.
+pub async fn run_step(
+    paths: &RuntimePaths,
+    name: &str,
+    anchor: Option<RunAnchor>,
+    command: &[String],
+) -> Result<()> {
+    let client = DaemonClient::new(paths.socket.as_str());
+
+    // The command's status matters more to the pipeline than the step's bookkeeping, so it
+    // runs even when the step could not be started
+    let step_started = match client.begin_step(name, anchor.clone()).await {
+        Ok(_) => true,
+        Err(error) => {
+            eprintln!("Failed to start step {}: {:#}", name, error);
+            false
+        }
+    };
+
+    let status = run_forwarding_signals(command).await;
+
+    if step_started {
+        if let Err(error) = client.end_step(name, anchor).await {
+            eprintln!("Failed to end step {}: {:#}", name, error);
+        }
+    }
+
+    let status = status.with_context(|| format!("Failed to run {}", command[0]))?;
+    if !status.success() {
+        std::process::exit(exit_code(status));
+    }
+    Ok(())
+}
+
+/// Runs `command`, passing on the signals meant for it instead of dying from them, so the
+/// step can still be ended after the command exits.
+async fn run_forwarding_signals(command: &[String]) -> std::io::Result<ExitStatus> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .spawn()?;
+
+    loop {
+        let signal = tokio::select! {
+            status = child.wait() => return status,
+            _ = interrupt.recv() => libc::SIGINT,
+            _ = terminate.recv() => libc::SIGTERM,
+            _ = hangup.recv() => libc::SIGHUP,
+        };
+
+        if let Some(pid) = child.id() {
+            unsafe { libc::kill(pid as libc::pid_t, signal) };
+        }
+    }
+}
+
+/// Exit code of a command the way shells report it, 128 plus the signal that killed it.
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+pub fn run_step_sync(
+    paths: &RuntimePaths,
+    name: &str,
+    anchor: Option<RunAnchor>,
+    command: &[String],
+) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(run_step(paths, name, anchor, command))
+}
+
+pub async fn print_status(paths: &RuntimePaths, json: bool) -> Result<()> {
+    let status = DaemonClient::new(paths.socket.as_str()).status().await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print!("{}", format_status_table(&status));
+    }
+    Ok(())
+}
+
+pub fn print_status_sync(paths: &RuntimePaths, json: bool) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(print_status(paths, json))
+}
+
+fn format_process_table(processes: &[TrackedProcess]) -> String {
+    let missing = || "-".to_string();
+    let header = [
+        "PID", "NAME", "TARGET", "STARTED", "CPU%", "RSS", "READ", "WRITTEN", "STATUS",
+    ]
+    .map(str::to_string);
+
+    let mut rows = vec![header];
+    for process in processes {
+        rows.push([
+            process.pid.to_string(),
+            process.display_name.clone(),
+            process.target.clone().unwrap_or_else(missing),
+            process.start_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            process
+                .cpu_utilization
+                .map(|cpu| format!("{:.1}", cpu))
+                .unwrap_or_else(missing),
+            process.memory_rss.map(format_bytes).unwrap_or_else(missing),
+            process
+                .disk_read_total
+                .map(format_bytes)
+                .unwrap_or_else(missing),
+            process
+                .disk_write_total
+                .map(format_bytes)
+                .unwrap_or_else(missing),
+            process
+                .status
+                .clone()
+                .unwrap_or_else(|| "Exited".to_string()),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+pub async fn print_processes(paths: &RuntimePaths, json: bool) -> Result<()> {
+    let processes = DaemonClient::new(paths.socket.as_str()).ps().await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&processes)?);
+    } else if processes.is_empty() {
+        println!("No tool processes are being tracked.");
+    } else {
+        print!("{}", format_process_table(&processes));
+    }
+    Ok(())
+}
+
+pub fn print_processes_sync(paths: &RuntimePaths, json: bool) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(print_processes(paths, json))
+}
+
+pub async fn print_events(
+    paths: &RuntimePaths,
+    event_types: &[String],
+    follow: bool,
+) -> Result<()> {
+    let mut lines = DaemonClient::new(paths.socket.as_str())
+        .events(event_types, follow)
+        .await?;
+
+    while let Some(line) = lines.next_line().await? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+pub fn print_events_sync(paths: &RuntimePaths, event_types: &[String], follow: bool) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(print_events(paths, event_types, follow))
+}
+
+pub async fn replay_archive(archive: &str) -> Result<()> {
+    let config = ConfigManager::load_config();
+    configure_http_client(&config)?;
+
+    let replayed =
+        offline_archive::replay_archive(Path::new(archive), &config.service_url, &config.api_key)
+            .await
+            .context("Failed to replay the archive, run `tracer replay` again to resume")?;
+
+    println!("Replayed {} archived entries to the service.", replayed);
+    Ok(())
+}
+
+pub fn replay_archive_sync(archive: &str) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(replay_archive(archive))
+}
+
 pub async fn setup_config(
+    paths: &RuntimePaths,
     api_key: &Option<String>,
     service_url: &Option<String>,
     process_polling_interval_ms: &Option<u64>,
@@ -64,8 +465,10 @@ pub async fn setup_config(
         batch_submission_interval_ms,
     )?;
 
-    let _ = send_refresh_config_request(SOCKET_PATH).await;
-    print_config_info().await?;
+    let _ = DaemonClient::new(paths.socket.as_str())
+        .refresh_config()
+        .await;
+    print_config_info(paths).await?;
     Ok(())
 }
 
@@ -96,3 +499,164 @@ pub async fn update_tracer() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon_communication::structs::RunStatus;
+    use crate::run_scope::RunAnchor;
+
+    #[test]
+    fn test_format_started_run() {
+        let mut run = InfoResponse {
+            run_name: "batch-117".to_string(),
+            run_id: "run-id".to_string(),
+            service_name: "service".to_string(),
+            details: RunDetails::default(),
+        };
+        assert_eq!(
+            format_started_run(&run),
+            "Started a new run with name: batch-117\nRun ID        run-id\nService name  service\n"
+        );
+
+        run.details = RunDetails {
+            external_id: Some("LIMS-117".to_string()),
+            tags: vec!["rna-seq".to_string(), "qc".to_string()],
+            params: serde_json::json!({ "genome": "GRCh38", "threads": 8 })
+                .as_object()
+                .unwrap()
+                .clone(),
+            description: None,
+            anchor: Some(RunAnchor::RootPid(4242)),
+        };
+        let output = format_started_run(&run);
+        assert!(output.contains("External ID   LIMS-117\n"));
+        assert!(output.contains("Tags          rna-seq, qc\n"));
+        assert!(output.contains("Param         genome=GRCh38\n"));
+        assert!(output.contains("Param         threads=8\n"));
+        assert!(output.contains("Scope         pid 4242\n"));
+    }
+
+    #[test]
+    fn test_format_status_table() {
+        let started_at = DateTime::parse_from_rfc3339("2024-08-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut status = StatusResponse {
+            version: "0.0.130".to_string(),
+            instance_id: "0123456789abcdef".to_string(),
+            started_at,
+            uptime_seconds: 90,
+            run: None,
+            scoped_runs: vec![],
+            tracked_processes: 2,
+            buffered_events: 14,
+            spooled_batches: 1,
+            dropped_batches: 0,
+            pending_file_uploads: 2,
+            last_successful_submission: Some(started_at),
+            last_failed_submission: None,
+            submission_failures: 3,
+            file_upload_failures: 0,
+        };
+
+        let table = format_status_table(&status);
+        assert!(table.contains("Run                         none\n"));
+        assert!(table.contains("Buffered events             14\n"));
+        assert!(table.contains("Last successful submission  2024-08-01T10:00:00+00:00\n"));
+        assert!(table.contains("Last failed submission      never\n"));
+
+        status.run = Some(RunStatus {
+            run_name: "run".to_string(),
+            run_id: "run-id".to_string(),
+            service_name: "service".to_string(),
+            started_at,
+            anchor: None,
+        });
+        assert!(format_status_table(&status).contains("Run ID                      run-id\n"));
+
+        status.scoped_runs = vec![RunStatus {
+            run_name: "sample-1".to_string(),
+            run_id: "scoped-id".to_string(),
+            service_name: "service".to_string(),
+            started_at,
+            anchor: Some(RunAnchor::WorkingDirectory("/data/sample-1".to_string())),
+        }];
+        assert!(format_status_table(&status).contains(
+            "Scoped run                  sample-1 (scoped-id) for directory /data/sample-1\n"
+        ));
+    }
+
+    #[test]
+    fn test_format_process_table() {
+        let start_time = DateTime::parse_from_rfc3339("2024-08-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let running = TrackedProcess {
+            pid: 42,
+            name: "STAR".to_string(),
+            display_name: "STAR".to_string(),
+            target: Some("process name STAR".to_string()),
+            start_time,
+            cpu_utilization: Some(97.5),
+            memory_rss: Some(3 * 1024 * 1024 * 1024),
+            disk_read_total: Some(1536),
+            disk_write_total: Some(0),
+            status: Some("Run".to_string()),
+        };
+        let exited = TrackedProcess {
+            pid: 7,
+            display_name: "fastqc".to_string(),
+            target: None,
+            cpu_utilization: None,
+            memory_rss: None,
+            disk_read_total: None,
+            disk_write_total: None,
+            status: None,
+            ..running.clone()
+        };
+
+        let table = format_process_table(&[running, exited]);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "PID  NAME    TARGET             STARTED              CPU%  RSS      READ     WRITTEN  STATUS"
+        );
+        assert_eq!(
+            lines[1],
+            "42   STAR    process name STAR  2024-08-01 10:00:00  97.5  3.0 GiB  1.5 KiB  0 B      Run"
+        );
+        assert_eq!(
+            lines[2],
+            "7    fastqc  -                  2024-08-01 10:00:00  -     -        -        -        Exited"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_step_command_exit_codes() -> Result<()> {
+        let shell = |script: &str| ["sh".to_string(), "-c".to_string(), script.to_string()];
+
+        let status = run_forwarding_signals(&shell("exit 3")).await?;
+        assert_eq!(exit_code(status), 3);
+
+        // Killed by SIGTERM, as shells report it
+        let status = run_forwarding_signals(&shell("kill -TERM $$")).await?;
+        assert_eq!(exit_code(status), 143);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_command_runs_without_daemon() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let paths = RuntimePaths::new(directory.path());
+        let marker = directory.path().join("ran");
+        let command = ["touch".to_string(), marker.to_string_lossy().to_string()];
+
+        run_step(&paths, "align", None, &command).await?;
+        assert!(marker.exists());
+
+        Ok(())
+    }
+}
@@ -7,13 +7,18 @@ use std::{
 
 use crate::config_manager::target_process::target_matching::TargetMatch;
 use crate::config_manager::target_process::Target;
+use crate::runtime_paths::RuntimePaths;
 
 const INTERCEPTOR_BASHRC_PATH: &str = ".config/tracer/.bashrc";
 const INTERCEPTOR_SOURCE_COMMAND: &str = "source ~/.config/tracer/.bashrc";
-pub const INTERCEPTOR_STDOUT_FILE: &str = "/tmp/tracerd-stdout";
-pub const INTERCEPTOR_STDERR_FILE: &str = "/tmp/tracerd-stderr";
-const INTERCEPTOR_STDOUT_COMMAND: &str = "exec &> >(tee >(awk 'system(\"[ ! -f /tmp/tracerd.pid ]\") == 1' >> \"/tmp/tracerd-stdout\"))\n";
-const INTERCEPTOR_STDERR_COMMAND: &str = "exec 2> >(tee >(awk 'system(\"[ ! -f /tmp/tracerd.pid ]\") == 1' >> \"/tmp/tracerd-stderr\"))\n";
+
+/// Shell line copying the output of `descriptors` to `output_file` while the daemon runs.
+fn get_output_interceptor(descriptors: &str, pid_file: &str, output_file: &str) -> String {
+    format!(
+        "exec {}> >(tee >(awk 'system(\"[ ! -f {} ]\") == 1' >> \"{}\"))\n",
+        descriptors, pid_file, output_file
+    )
+}
 
 pub fn get_command_interceptor(
     current_tracer_exe_path: PathBuf,
@@ -31,6 +36,7 @@ pub fn get_command_interceptor(
 
 pub fn rewrite_interceptor_bashrc_file(
     current_tracer_exe_path: PathBuf,
+    paths: &RuntimePaths,
     targets: Vec<&Target>,
 ) -> Result<()> {
     let path = homedir::get_my_home()?.unwrap();
@@ -60,11 +66,15 @@ pub fn rewrite_interceptor_bashrc_file(
     }
 
     bashrc_file
-        .write_all(INTERCEPTOR_STDOUT_COMMAND.as_bytes())
+        .write_all(
+            get_output_interceptor("&", &paths.pid_file, &paths.interceptor_stdout_file).as_bytes(),
+        )
         .unwrap();
 
     bashrc_file
-        .write_all(INTERCEPTOR_STDERR_COMMAND.as_bytes())
+        .write_all(
+            get_output_interceptor("2", &paths.pid_file, &paths.interceptor_stderr_file).as_bytes(),
+        )
         .unwrap();
 
     Ok(())
@@ -10,6 +10,10 @@ use crate::{
         target_process::target_matching::TargetMatch,
     },
     events::send_daemon_start_event,
+    exporters::ExporterConfig,
+    http_client::RequestCompression,
+    run_detection::RunDetection,
+    runtime_paths::RuntimePaths,
 };
 
 use crate::config_manager::target_process::Target;
@@ -24,6 +28,17 @@ const BATCH_SUBMISSION_INTERVAL_MS: u64 = 10000;
 const NEW_RUN_PAUSE_MS: u64 = 10 * 60 * 1000;
 const PROCESS_METRICS_SEND_INTERVAL_MS: u64 = 10000;
 const FILE_SIZE_NOT_CHANGING_PERIOD_MS: u64 = 1000 * 60;
+const HTTP_TIMEOUT_MS: u64 = 30 * 1000;
+const HTTP_CONNECT_TIMEOUT_MS: u64 = 10 * 1000;
+const HTTP_MAX_RETRIES: u32 = 3;
+const HTTP_RETRY_BASE_DELAY_MS: u64 = 500;
+const HTTP_RETRY_MAX_DELAY_MS: u64 = 30 * 1000;
+const CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
+const CIRCUIT_BREAKER_RESET_MS: u64 = 60 * 1000;
+const MAX_BATCH_BYTES: usize = 1024 * 1024;
+const MAX_BATCH_EVENTS: usize = 1000;
+const SPOOL_MAX_BYTES: u64 = 512 * 1024 * 1024;
+const SPOOL_MAX_AGE_MS: u64 = 7 * 24 * 60 * 60 * 1000;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ConfigFile {
@@ -32,8 +47,25 @@ pub struct ConfigFile {
     pub process_polling_interval_ms: Option<u64>,
     pub batch_submission_interval_ms: Option<u64>,
     pub new_run_pause_ms: Option<u64>,
+    pub run_detection: Option<RunDetection>,
     pub file_size_not_changing_period_ms: Option<u64>,
     pub process_metrics_send_interval_ms: Option<u64>,
+    pub http_timeout_ms: Option<u64>,
+    pub http_connect_timeout_ms: Option<u64>,
+    pub http_max_retries: Option<u32>,
+    pub http_retry_base_delay_ms: Option<u64>,
+    pub http_retry_max_delay_ms: Option<u64>,
+    pub circuit_breaker_threshold: Option<u32>,
+    pub circuit_breaker_reset_ms: Option<u64>,
+    pub http_compression: Option<RequestCompression>,
+    pub max_batch_bytes: Option<usize>,
+    pub max_batch_events: Option<usize>,
+    pub spool_max_bytes: Option<u64>,
+    pub spool_max_age_ms: Option<u64>,
+    pub exporters: Option<Vec<ExporterConfig>>,
+    pub prometheus_listen_address: Option<String>,
+    pub runtime_dir: Option<String>,
+    pub socket_allowed_gids: Option<Vec<u32>>,
     pub targets: Option<Vec<Target>>,
 }
 
@@ -46,6 +78,34 @@ pub struct Config {
     pub file_size_not_changing_period_ms: u64,
     pub service_url: String,
     pub new_run_pause_ms: u64,
+    /// How runs are started and ended without `tracer start`, `new_run_pause_ms` is the
+    /// inactivity after which a run ends
+    pub run_detection: RunDetection,
+    pub http_timeout_ms: u64,
+    pub http_connect_timeout_ms: u64,
+    pub http_max_retries: u32,
+    pub http_retry_base_delay_ms: u64,
+    pub http_retry_max_delay_ms: u64,
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_reset_ms: u64,
+    /// Encoding of request bodies, only enable it when the service accepts it
+    pub http_compression: RequestCompression,
+    /// Batches above either cap are split into several requests
+    pub max_batch_bytes: usize,
+    pub max_batch_events: usize,
+    /// Limits of the spool of undelivered batches, the oldest batches are dropped past them
+    pub spool_max_bytes: u64,
+    pub spool_max_age_ms: u64,
+    pub exporters: Vec<ExporterConfig>,
+    /// Address of the optional Prometheus scrape endpoint, e.g. `127.0.0.1:9464`
+    pub prometheus_listen_address: Option<String>,
+    /// Directory of the pid file, socket and other daemon files, see `RuntimePaths`
+    pub runtime_dir: Option<String>,
+    /// Groups whose members may use the daemon socket besides its owner, matched against
+    /// the primary and supplementary groups of the connecting process. The runtime directory
+    /// and the socket are handed to the first group. `$XDG_RUNTIME_DIR` is private, so shared
+    /// daemons need a `runtime_dir` the group can reach.
+    pub socket_allowed_gids: Vec<u32>,
     pub targets: Vec<Target>,
 }
 
@@ -79,12 +139,41 @@ impl ConfigManager {
                 .service_url
                 .unwrap_or(DEFAULT_SERVICE_URL.to_string()),
             new_run_pause_ms: config.new_run_pause_ms.unwrap_or(NEW_RUN_PAUSE_MS),
+            run_detection: config.run_detection.unwrap_or_default(),
             process_metrics_send_interval_ms: config
                 .process_metrics_send_interval_ms
                 .unwrap_or(PROCESS_METRICS_SEND_INTERVAL_MS),
             file_size_not_changing_period_ms: config
                 .file_size_not_changing_period_ms
                 .unwrap_or(FILE_SIZE_NOT_CHANGING_PERIOD_MS),
+            http_timeout_ms: config.http_timeout_ms.unwrap_or(HTTP_TIMEOUT_MS),
+            http_connect_timeout_ms: config
+                .http_connect_timeout_ms
+                .unwrap_or(HTTP_CONNECT_TIMEOUT_MS),
+            http_max_retries: config.http_max_retries.unwrap_or(HTTP_MAX_RETRIES),
+            http_retry_base_delay_ms: config
+                .http_retry_base_delay_ms
+                .unwrap_or(HTTP_RETRY_BASE_DELAY_MS),
+            http_retry_max_delay_ms: config
+                .http_retry_max_delay_ms
+                .unwrap_or(HTTP_RETRY_MAX_DELAY_MS),
+            circuit_breaker_threshold: config
+                .circuit_breaker_threshold
+                .unwrap_or(CIRCUIT_BREAKER_THRESHOLD),
+            circuit_breaker_reset_ms: config
+                .circuit_breaker_reset_ms
+                .unwrap_or(CIRCUIT_BREAKER_RESET_MS),
+            http_compression: config.http_compression.unwrap_or_default(),
+            max_batch_bytes: config.max_batch_bytes.unwrap_or(MAX_BATCH_BYTES),
+            max_batch_events: config.max_batch_events.unwrap_or(MAX_BATCH_EVENTS),
+            spool_max_bytes: config.spool_max_bytes.unwrap_or(SPOOL_MAX_BYTES),
+            spool_max_age_ms: config.spool_max_age_ms.unwrap_or(SPOOL_MAX_AGE_MS),
+            exporters: config
+                .exporters
+                .unwrap_or_else(|| vec![ExporterConfig::Http]),
+            prometheus_listen_address: config.prometheus_listen_address,
+            runtime_dir: config.runtime_dir,
+            socket_allowed_gids: config.socket_allowed_gids.unwrap_or_default(),
             targets: config
                 .targets
                 .unwrap_or_else(|| targets_list::TARGETS.to_vec()),
@@ -97,10 +186,27 @@ impl ConfigManager {
             process_polling_interval_ms: PROCESS_POLLING_INTERVAL_MS,
             batch_submission_interval_ms: BATCH_SUBMISSION_INTERVAL_MS,
             new_run_pause_ms: NEW_RUN_PAUSE_MS,
+            run_detection: RunDetection::Manual,
             file_size_not_changing_period_ms: FILE_SIZE_NOT_CHANGING_PERIOD_MS,
             service_url: DEFAULT_SERVICE_URL.to_string(),
             targets: targets_list::TARGETS.to_vec(),
             process_metrics_send_interval_ms: PROCESS_METRICS_SEND_INTERVAL_MS,
+            http_timeout_ms: HTTP_TIMEOUT_MS,
+            http_connect_timeout_ms: HTTP_CONNECT_TIMEOUT_MS,
+            http_max_retries: HTTP_MAX_RETRIES,
+            http_retry_base_delay_ms: HTTP_RETRY_BASE_DELAY_MS,
+            http_retry_max_delay_ms: HTTP_RETRY_MAX_DELAY_MS,
+            circuit_breaker_threshold: CIRCUIT_BREAKER_THRESHOLD,
+            circuit_breaker_reset_ms: CIRCUIT_BREAKER_RESET_MS,
+            http_compression: RequestCompression::None,
+            max_batch_bytes: MAX_BATCH_BYTES,
+            max_batch_events: MAX_BATCH_EVENTS,
+            spool_max_bytes: SPOOL_MAX_BYTES,
+            spool_max_age_ms: SPOOL_MAX_AGE_MS,
+            exporters: vec![ExporterConfig::Http],
+            prometheus_listen_address: None,
+            runtime_dir: None,
+            socket_allowed_gids: vec![],
         }
     }
 
@@ -133,10 +239,11 @@ impl ConfigManager {
         config
     }
 
-    pub fn setup_aliases() -> Result<()> {
+    pub fn setup_aliases(paths: &RuntimePaths) -> Result<()> {
         let config = ConfigManager::load_config();
         rewrite_interceptor_bashrc_file(
             env::current_exe()?,
+            paths,
             config
                 .targets
                 .iter()
@@ -161,11 +268,28 @@ impl ConfigManager {
             api_key: config.api_key.clone(),
             service_url: Some(config.service_url.clone()),
             new_run_pause_ms: Some(config.new_run_pause_ms),
+            run_detection: Some(config.run_detection),
             file_size_not_changing_period_ms: Some(config.file_size_not_changing_period_ms),
             process_polling_interval_ms: Some(config.process_polling_interval_ms),
             batch_submission_interval_ms: Some(config.batch_submission_interval_ms),
             targets: Some(config.targets.clone()),
             process_metrics_send_interval_ms: Some(config.process_metrics_send_interval_ms),
+            http_timeout_ms: Some(config.http_timeout_ms),
+            http_connect_timeout_ms: Some(config.http_connect_timeout_ms),
+            http_max_retries: Some(config.http_max_retries),
+            http_retry_base_delay_ms: Some(config.http_retry_base_delay_ms),
+            http_retry_max_delay_ms: Some(config.http_retry_max_delay_ms),
+            circuit_breaker_threshold: Some(config.circuit_breaker_threshold),
+            circuit_breaker_reset_ms: Some(config.circuit_breaker_reset_ms),
+            http_compression: Some(config.http_compression),
+            max_batch_bytes: Some(config.max_batch_bytes),
+            max_batch_events: Some(config.max_batch_events),
+            spool_max_bytes: Some(config.spool_max_bytes),
+            spool_max_age_ms: Some(config.spool_max_age_ms),
+            exporters: Some(config.exporters.clone()),
+            prometheus_listen_address: config.prometheus_listen_address.clone(),
+            runtime_dir: config.runtime_dir.clone(),
+            socket_allowed_gids: Some(config.socket_allowed_gids.clone()),
         };
         let config = toml::to_string(&config_out)?;
         std::fs::write(config_file_location, config)?;
@@ -240,5 +364,22 @@ mod tests {
             PROCESS_METRICS_SEND_INTERVAL_MS
         );
         assert!(!config.targets.is_empty());
+        assert_eq!(config.run_detection, RunDetection::Manual);
+    }
+
+    #[test]
+    fn test_load_run_detection_from_file() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("tracer.toml");
+        std::fs::write(
+            &path,
+            "api_key = \"key\"\nrun_detection = \"parent_process\"\nnew_run_pause_ms = 1000\n",
+        )?;
+
+        let config = ConfigManager::load_config_from_file(&path)?;
+        assert_eq!(config.run_detection, RunDetection::ParentProcess);
+        assert_eq!(config.new_run_pause_ms, 1000);
+
+        Ok(())
     }
 }
@@ -4,12 +4,6 @@ pub mod targets_list;
 use serde::{Deserialize, Serialize};
 use target_matching::{matches_target, TargetMatch};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct CommandContainsStruct {
-    pub process_name: Option<String>,
-    pub command_content: String,
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum DisplayName {
     Name(String),
@@ -17,7 +17,29 @@ pub enum TargetMatch {
     BinPathLastComponent(String),
 }
 
-pub fn to_lowercase(s: &str) -> Cow<str> {
+impl TargetMatch {
+    /// Short human readable form, e.g. for `tracer ps`.
+    pub fn describe(&self) -> String {
+        match self {
+            TargetMatch::ProcessName(name) => format!("process name {}", name),
+            TargetMatch::ShortLivedProcessExecutable(name) => {
+                format!("short-lived executable {}", name)
+            }
+            TargetMatch::CommandContains(CommandContainsStruct {
+                process_name: Some(process_name),
+                command_content,
+            }) => format!("{} command contains {}", process_name, command_content),
+            TargetMatch::CommandContains(CommandContainsStruct {
+                process_name: None,
+                command_content,
+            }) => format!("command contains {}", command_content),
+            TargetMatch::BinPathStartsWith(prefix) => format!("binary path starts with {}", prefix),
+            TargetMatch::BinPathLastComponent(name) => format!("binary name {}", name),
+        }
+    }
+}
+
+pub fn to_lowercase(s: &str) -> Cow<'_, str> {
     if s.chars().any(|c| c.is_uppercase()) {
         Cow::Owned(s.to_lowercase())
     } else {
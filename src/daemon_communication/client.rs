@@ -1,219 +1,257 @@
-use std::path::PathBuf;
+// src/daemon_communication/client.rs
+use std::path::Path;
 
-// src/cli.rs
-use anyhow::Result;
-use serde::Deserialize;
-use serde_json::{from_str, json};
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
 
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines},
     net::UnixStream,
 };
 
 use crate::debug_log::Logger;
-use crate::process_watcher::ShortLivedProcessLog;
-
-use super::structs::InfoResponse;
-
-pub async fn send_log_request(socket_path: &str, message: String) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
-
-    let log_request = json!({
-            "command": "log",
-            "message": message
-    });
-    let log_request_json =
-        serde_json::to_string(&log_request).expect("Failed to serialize log request");
-    socket.write_all(log_request_json.as_bytes()).await?;
-
-    Ok(())
-}
-
-pub async fn send_alert_request(socket_path: &str, message: String) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
-    let alert_request: serde_json::Value = json!({
-            "command": "alert",
-            "message": message
-    });
-    let alert_request_json =
-        serde_json::to_string(&alert_request).expect("Failed to serialize alrt request");
-    socket.write_all(alert_request_json.as_bytes()).await?;
-
-    Ok(())
-}
-
-pub async fn send_terminate_request(socket_path: &str) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
-
-    let terminate_request = json!({
-            "command": "terminate"
-    });
-
-    let terminate_request_json =
-        serde_json::to_string(&terminate_request).expect("Failed to serialize terminate request");
-
-    socket.write_all(terminate_request_json.as_bytes()).await?;
+use crate::event_attributes::{MessageAttributes, RunDetails};
+use crate::process_watcher::{ShortLivedProcessLog, TrackedProcess};
+use crate::run_scope::RunAnchor;
+use crate::run_summary::RunSummary;
+use crate::steps::ActiveStep;
+
+use super::structs::{
+    InfoResponse, PsResponse, Request, Response, StatusResponse, PROTOCOL_VERSION,
+};
 
-    Ok(())
+/// Typed client for the daemon's control socket.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use tracer::DaemonClient;
+///
+/// let client = DaemonClient::new("/run/user/1000/tracer/tracerd.sock");
+/// let run = client.start_run(None, Default::default()).await?;
+/// client.tag(&["rna-seq".to_string()]).await?;
+/// client
+///     .log(format!("Started {}", run.run_name), Default::default())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DaemonClient {
+    socket_path: String,
 }
 
-pub async fn send_start_run_request(socket_path: &str) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
-
-    let start_request = json!({
-            "command": "start"
-    });
-
-    let start_request_json =
-        serde_json::to_string(&start_request).expect("Failed to serialize start request");
-
-    socket.write_all(start_request_json.as_bytes()).await?;
-
-    socket.shutdown().await?;
-
-    #[derive(Deserialize)]
-    struct StartRunResponse {
-        run_name: String,
+impl DaemonClient {
+    pub fn new(socket_path: impl Into<String>) -> Self {
+        DaemonClient {
+            socket_path: socket_path.into(),
+        }
     }
 
-    let mut buffer = [0; 1024];
-    let n = socket.read(&mut buffer).await?;
-    let response = std::str::from_utf8(&buffer[..n])?;
-    let response: StartRunResponse = from_str(response)?;
-
-    println!("Started a new run with name: {}", response.run_name);
-
-    Ok(())
-}
-
-pub async fn send_end_run_request(socket_path: &str) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
-
-    let end_request = json!({
-            "command": "end"
-    });
-
-    let end_request_json =
-        serde_json::to_string(&end_request).expect("Failed to serialize start request");
-
-    socket.write_all(end_request_json.as_bytes()).await?;
-
-    Ok(())
-}
-
-pub async fn send_info_request(socket_path: &str) -> Result<InfoResponse> {
-    let mut socket = UnixStream::connect(socket_path).await?;
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
 
-    let ping_request = json!({
-            "command": "info"
-    });
+    /// Connects to the daemon and writes the whole request.
+    async fn write_request(&self, request: &Request) -> Result<UnixStream> {
+        let mut socket = UnixStream::connect(&self.socket_path).await.with_context(|| {
+            format!(
+                "Failed to connect to the daemon at {}. Maybe the daemon is not running? If it's not, run `tracer init` to start the daemon.",
+                self.socket_path
+            )
+        })?;
 
-    let info_request_json =
-        serde_json::to_string(&ping_request).expect("Failed to serialize info request");
+        let mut request_json = serde_json::to_value(request)?;
+        request_json["version"] = json!(PROTOCOL_VERSION);
 
-    socket.write_all(info_request_json.as_bytes()).await?;
+        socket
+            .write_all(serde_json::to_string(&request_json)?.as_bytes())
+            .await?;
+        socket.shutdown().await?;
 
-    socket.shutdown().await?;
+        Ok(socket)
+    }
 
-    let mut buffer = [0; 1024];
-    let n = socket.read(&mut buffer).await?;
-    let response = std::str::from_utf8(&buffer[..n])?;
-    let response: InfoResponse = from_str(response)?;
+    /// Sends a request to the daemon and waits for its response, returning the payload.
+    /// Fails when the daemon can't be reached or reports that the command failed.
+    pub async fn send(&self, request: &Request) -> Result<Option<Value>> {
+        let mut socket = self.write_request(request).await?;
 
-    Ok(response)
-}
+        let mut response = String::new();
+        socket
+            .read_to_string(&mut response)
+            .await
+            .context("Failed to read the daemon's response")?;
 
-pub async fn send_refresh_config_request(socket_path: &str) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
+        let response: Response = serde_json::from_str(&response)
+            .with_context(|| format!("Invalid response from the daemon: {}", response))?;
 
-    let setup_request = json!({
-            "command": "refresh_config"
-    });
+        response.into_result()
+    }
 
-    let setup_request_json =
-        serde_json::to_string(&setup_request).expect("Failed to serialize setup request");
+    /// Sends a request whose response carries a payload of type `T`.
+    async fn query<T: DeserializeOwned>(&self, request: &Request) -> Result<T> {
+        let payload = self.send(request).await?;
+        serde_json::from_value(payload.unwrap_or_default())
+            .with_context(|| format!("Invalid {} response", request.command()))
+    }
 
-    socket.write_all(setup_request_json.as_bytes()).await?;
+    pub async fn log(&self, message: String, attributes: MessageAttributes) -> Result<()> {
+        self.send(&Request::Log {
+            message,
+            attributes,
+        })
+        .await?;
+        Ok(())
+    }
 
-    Ok(())
-}
+    pub async fn alert(&self, message: String, attributes: MessageAttributes) -> Result<()> {
+        self.send(&Request::Alert {
+            message,
+            attributes,
+        })
+        .await?;
+        Ok(())
+    }
 
-pub async fn send_update_tags_request(socket_path: &str, tags: &Vec<String>) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
+    pub async fn terminate(&self) -> Result<()> {
+        self.send(&Request::Terminate).await?;
+        Ok(())
+    }
 
-    let tag_request = json!({
-            "command": "tag",
-            "tags": tags
-    });
+    /// Starts a new run, ending the current one, and returns it. The service names the run
+    /// unless `name` is given.
+    pub async fn start_run(
+        &self,
+        name: Option<String>,
+        details: RunDetails,
+    ) -> Result<InfoResponse> {
+        self.query(&Request::Start { name, details }).await
+    }
 
-    let tag_request_json =
-        serde_json::to_string(&tag_request).expect("Failed to serialize tag request");
+    /// Ends the run scoped to `anchor`, or the daemon's current run without one, and returns
+    /// its summary. `None` when there was no run to end.
+    pub async fn end_run(&self, anchor: Option<RunAnchor>) -> Result<Option<RunSummary>> {
+        self.query(&Request::End { anchor }).await
+    }
 
-    socket.write_all(tag_request_json.as_bytes()).await?;
+    /// Starts a step nested in the running one, in the run scoped to `anchor` or the daemon's
+    /// current run without one.
+    pub async fn begin_step(&self, name: &str, anchor: Option<RunAnchor>) -> Result<ActiveStep> {
+        self.query(&Request::BeginStep {
+            name: name.to_string(),
+            anchor,
+        })
+        .await
+    }
 
-    Ok(())
-}
+    /// Ends the innermost running step called `name` and the steps still running inside it,
+    /// returning them innermost first.
+    pub async fn end_step(&self, name: &str, anchor: Option<RunAnchor>) -> Result<Vec<ActiveStep>> {
+        self.query(&Request::EndStep {
+            name: name.to_string(),
+            anchor,
+        })
+        .await
+    }
 
-pub async fn send_log_short_lived_process_request(
-    socket_path: &str,
-    log: ShortLivedProcessLog,
-) -> Result<()> {
-    let mut socket = UnixStream::connect(socket_path).await?;
+    pub async fn info(&self) -> Result<InfoResponse> {
+        self.query(&Request::Info).await
+    }
 
-    let log_request = json!({
-            "command": "log_short_lived_process",
-            "log": log
-    });
+    pub async fn status(&self) -> Result<StatusResponse> {
+        self.query(&Request::Status).await
+    }
 
-    let log_request_json =
-        serde_json::to_string(&log_request).expect("Failed to serialize log request");
+    pub async fn ps(&self) -> Result<Vec<TrackedProcess>> {
+        let response: PsResponse = self.query(&Request::Ps).await?;
+        Ok(response.processes)
+    }
 
-    socket.write_all(log_request_json.as_bytes()).await?;
+    /// Subscribes to the events the daemon records, returning their JSON lines. They start
+    /// with the events not submitted yet and, when following, go on until the daemon stops.
+    pub async fn events(
+        &self,
+        event_types: &[String],
+        follow: bool,
+    ) -> Result<Lines<BufReader<UnixStream>>> {
+        let request = Request::Events {
+            follow,
+            event_types: (!event_types.is_empty()).then(|| event_types.to_vec()),
+        };
+
+        let socket = self.write_request(&request).await?;
+        let mut lines = BufReader::new(socket).lines();
+
+        let response = lines
+            .next_line()
+            .await
+            .context("Failed to read the daemon's response")?
+            .context("The daemon closed the connection without answering")?;
+        let response: Response = serde_json::from_str(&response)
+            .with_context(|| format!("Invalid response from the daemon: {}", response))?;
+        response.into_result()?;
+
+        Ok(lines)
+    }
 
-    Ok(())
-}
+    pub async fn refresh_config(&self) -> Result<()> {
+        self.send(&Request::RefreshConfig).await?;
+        Ok(())
+    }
 
-pub async fn send_upload_file_request(socket_path: &str, file_path: &PathBuf) -> Result<()> {
-    let logger = Logger::new();
-    logger
-        .log(
-            "send_upload_file_request",
-            Some(&json!({
-                "file_path": file_path,
-                "socket_path": &socket_path
+    pub async fn tag(&self, tags: &[String]) -> Result<()> {
+        self.send(&Request::Tag {
+            tags: tags.to_vec(),
+        })
+        .await?;
+        Ok(())
+    }
 
-            })),
-        )
-        .await;
+    pub async fn log_short_lived_process(&self, log: ShortLivedProcessLog) -> Result<()> {
+        self.send(&Request::LogShortLivedProcess { log: Box::new(log) })
+            .await?;
+        Ok(())
+    }
 
-    let mut socket = UnixStream::connect(socket_path).await?;
+    pub async fn upload(&self, file_path: &Path) -> Result<()> {
+        let logger = Logger::new();
+        logger
+            .log(
+                "send_upload_file_request",
+                Some(&json!({
+                    "file_path": file_path,
+                    "socket_path": &self.socket_path
 
-    let upload_request = json!({
-        "command": "upload",
-        "file_path": file_path
-    });
+                })),
+            )
+            .await;
 
-    let upload_request_json =
-        serde_json::to_string(&upload_request).expect("Failed to serialize upload request");
+        let upload_request = Request::Upload {
+            file_path: file_path.to_string_lossy().to_string(),
+        };
 
-    socket.write_all(upload_request_json.as_bytes()).await?;
+        self.send(&upload_request).await?;
 
-    logger
-        .log(
-            "send_upload_file_request//socket.write_all",
-            Some(&upload_request),
-        )
-        .await;
+        logger
+            .log(
+                "send_upload_file_request//send_request",
+                Some(&json!(upload_request)),
+            )
+            .await;
 
-    Ok(())
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::SOCKET_PATH;
     use serial_test::serial;
-    use tokio::{io::AsyncReadExt, net::UnixListener};
+    use std::path::PathBuf;
+    use tokio::net::UnixListener;
+
+    const SOCKET_PATH: &str = "/tmp/tracerd-client-test.sock";
 
     fn setup_test_unix_listener() -> UnixListener {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -224,94 +262,226 @@ mod tests {
         UnixListener::bind(SOCKET_PATH).expect("Failed to bind to unix socket")
     }
 
-    async fn check_listener_value(listener: &UnixListener, expected_value: &str) {
+    /// Checks the next request against `expected_value` and answers it with `response`.
+    async fn check_listener_value(
+        listener: &UnixListener,
+        mut expected_value: Value,
+        response: Response,
+    ) {
         let (mut stream, _) = listener.accept().await.unwrap();
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer).await.unwrap();
-        let received = std::str::from_utf8(&buffer[..n]).unwrap();
-        assert_eq!(received, expected_value);
+        let mut received = String::new();
+        stream.read_to_string(&mut received).await.unwrap();
+
+        expected_value["version"] = json!(PROTOCOL_VERSION);
+        assert_eq!(
+            serde_json::from_str::<Value>(&received).unwrap(),
+            expected_value
+        );
+
+        stream
+            .write_all(serde_json::to_string(&response).unwrap().as_bytes())
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
     #[serial]
     async fn test_send_log_request() -> Result<()> {
         let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
         let message = "Test Message".to_string();
 
-        send_log_request(SOCKET_PATH, message.clone()).await?;
-
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "log",
-                "message": message
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
-
-        Ok(())
+        let (result, _) = tokio::join!(
+            client.log(message.clone(), MessageAttributes::default()),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "log",
+                    "message": message
+                }),
+                Response::ok(None),
+            )
+        );
+
+        result
     }
 
     #[tokio::test]
     #[serial]
     async fn test_send_alert_request() -> Result<()> {
         let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
         let message = "Test Message".to_string();
 
-        send_alert_request(SOCKET_PATH, message.clone()).await?;
-
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "alert",
-                "message": message
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
-
-        Ok(())
+        let (result, _) = tokio::join!(
+            client.alert(message.clone(), MessageAttributes::default()),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "alert",
+                    "message": message
+                }),
+                Response::ok(None),
+            )
+        );
+
+        result
     }
 
     #[tokio::test]
     #[serial]
     async fn test_send_terminate_request() -> Result<()> {
         let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+
+        let (result, _) = tokio::join!(
+            client.terminate(),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "terminate"
+                }),
+                Response::ok(None),
+            )
+        );
+
+        result
+    }
 
-        send_terminate_request(SOCKET_PATH).await?;
+    #[tokio::test]
+    #[serial]
+    async fn test_send_end_run_request() -> Result<()> {
+        let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+
+        let (result, _) = tokio::join!(
+            client.end_run(None),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "end"
+                }),
+                Response::ok(None),
+            )
+        );
+
+        assert_eq!(result?, None);
+        Ok(())
+    }
 
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "terminate"
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
+    #[tokio::test]
+    #[serial]
+    async fn test_send_status_request() -> Result<()> {
+        let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+        let payload = json!({
+            "version": "0.0.130",
+            "started_at": "2024-08-01T10:00:00Z",
+            "uptime_seconds": 90,
+            "run": null,
+            "tracked_processes": 2,
+            "buffered_events": 14,
+            "spooled_batches": 1,
+            "last_successful_submission": "2024-08-01T10:01:00Z",
+            "last_failed_submission": null,
+            "submission_failures": 0,
+            "file_upload_failures": 0,
+        });
+
+        let (result, _) = tokio::join!(
+            client.status(),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "status"
+                }),
+                Response::ok(Some(payload)),
+            )
+        );
+
+        let status = result?;
+        assert_eq!(status.buffered_events, 14);
+        assert!(status.run.is_none());
+        assert!(status.last_failed_submission.is_none());
 
         Ok(())
     }
 
     #[tokio::test]
     #[serial]
-    async fn test_send_end_run_request() -> Result<()> {
+    async fn test_send_ps_request() -> Result<()> {
         let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+        let payload = json!({
+            "processes": [{
+                "pid": 42,
+                "name": "STAR",
+                "display_name": "STAR",
+                "target": "process name STAR",
+                "start_time": "2024-08-01T10:00:00Z",
+                "cpu_utilization": 97.5,
+                "memory_rss": 1048576,
+                "disk_read_total": 4096,
+                "disk_write_total": 0,
+                "status": "Run",
+            }]
+        });
+
+        let (result, _) = tokio::join!(
+            client.ps(),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "ps"
+                }),
+                Response::ok(Some(payload)),
+            )
+        );
+
+        let processes = result?;
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].pid, 42);
+        assert_eq!(processes[0].memory_rss, Some(1048576));
 
-        send_end_run_request(SOCKET_PATH).await?;
+        Ok(())
+    }
 
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "end"
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
+    #[tokio::test]
+    #[serial]
+    async fn test_send_events_request() -> Result<()> {
+        let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+        let event_types = vec!["tool_execution".to_string()];
+
+        let daemon = async {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+            assert_eq!(
+                serde_json::from_str::<Value>(&received).unwrap(),
+                json!({
+                    "command": "events",
+                    "follow": true,
+                    "event_types": ["tool_execution"],
+                    "version": PROTOCOL_VERSION
+                })
+            );
+
+            let lines = format!(
+                "{}\n{}\n{}\n",
+                serde_json::to_string(&Response::ok(None)).unwrap(),
+                json!({ "message": "first" }),
+                json!({ "message": "second" })
+            );
+            stream.write_all(lines.as_bytes()).await.unwrap();
+        };
+
+        let (result, _) = tokio::join!(client.events(&event_types, true), daemon);
+
+        let mut lines = result?;
+        assert_eq!(lines.next_line().await?.unwrap(), r#"{"message":"first"}"#);
+        assert_eq!(lines.next_line().await?.unwrap(), r#"{"message":"second"}"#);
+        assert_eq!(lines.next_line().await?, None);
 
         Ok(())
     }
@@ -320,63 +490,122 @@ mod tests {
     #[serial]
     async fn test_send_refresh_config_request() -> Result<()> {
         let listener = setup_test_unix_listener();
-
-        send_refresh_config_request(SOCKET_PATH).await?;
-
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "refresh_config"
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
-
-        Ok(())
+        let client = DaemonClient::new(SOCKET_PATH);
+
+        let (result, _) = tokio::join!(
+            client.refresh_config(),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "refresh_config"
+                }),
+                Response::ok(None),
+            )
+        );
+
+        result
     }
 
     #[tokio::test]
     #[serial]
     async fn test_send_update_tags_request() -> Result<()> {
         let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
         let tags = vec!["tag1".to_string(), "tag2".to_string(), "tag3".to_string()];
 
-        send_update_tags_request(SOCKET_PATH, &tags).await?;
-
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "tag",
-                "tags": tags
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
-
-        Ok(())
+        let (result, _) = tokio::join!(
+            client.tag(&tags),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "tag",
+                    "tags": tags
+                }),
+                Response::ok(None),
+            )
+        );
+
+        result
     }
 
     #[tokio::test]
     #[serial]
     async fn test_send_upload_file_request() -> Result<()> {
         let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
         let file_path = PathBuf::from("log_outgoing_http_calls.txt".to_string());
 
-        send_upload_file_request(SOCKET_PATH, &file_path).await?;
+        let (result, _) = tokio::join!(
+            client.upload(&file_path),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "upload",
+                    "file_path": file_path.clone()
+                }),
+                Response::ok(None),
+            )
+        );
+
+        result
+    }
 
-        check_listener_value(
-            &listener,
-            json!({
-                "command": "upload",
-                "file_path": file_path.clone()
-            })
-            .to_string()
-            .as_str(),
-        )
-        .await;
+    #[tokio::test]
+    #[serial]
+    async fn test_send_start_run_request_reads_payload() -> Result<()> {
+        let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+
+        let (run, _) = tokio::join!(
+            client.start_run(
+                Some("run".to_string()),
+                RunDetails {
+                    external_id: Some("LIMS-117".to_string()),
+                    ..Default::default()
+                }
+            ),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "start",
+                    "name": "run",
+                    "external_id": "LIMS-117",
+                }),
+                Response::ok(Some(json!({
+                    "run_name": "run",
+                    "run_id": "id",
+                    "service_name": "service",
+                    "external_id": "LIMS-117",
+                }))),
+            )
+        );
+
+        let run = run?;
+        assert_eq!(run.run_name, "run");
+        assert_eq!(run.details.external_id.as_deref(), Some("LIMS-117"));
+        Ok(())
+    }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_failed_command_is_an_error() -> Result<()> {
+        let listener = setup_test_unix_listener();
+        let client = DaemonClient::new(SOCKET_PATH);
+        let tags = vec![];
+
+        let (result, _) = tokio::join!(
+            client.tag(&tags),
+            check_listener_value(
+                &listener,
+                json!({
+                    "command": "tag",
+                    "tags": []
+                }),
+                Response::error("Failed to update tags".to_string()),
+            )
+        );
+
+        assert_eq!(result.unwrap_err().to_string(), "Failed to update tags");
         Ok(())
     }
 }
@@ -1,132 +1,190 @@
-use anyhow::{Ok, Result};
-use core::panic;
+use anyhow::{bail, Context, Ok, Result};
+use chrono::Utc;
 use serde_json::{json, Value};
-use std::{future::Future, pin::Pin, sync::Arc};
+use std::os::unix::fs::PermissionsExt;
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{UnixListener, UnixStream},
-    sync::{Mutex, RwLock},
+    sync::{broadcast::error::RecvError, Mutex, RwLock},
+    time::timeout,
 };
 use tokio_util::sync::CancellationToken;
 
+use super::structs::{
+    InfoResponse, Request, Response, RunStatus, StatusResponse, PROTOCOL_VERSION,
+};
 use crate::{
     config_manager::{Config, ConfigManager},
     debug_log::Logger,
+    event_attributes::{MessageAttributes, RunDetails},
+    event_recorder::{Event, EventType},
     events::{send_alert_event, send_log_event, send_update_tags_event},
     process_watcher::ShortLivedProcessLog,
-    tracer_client::TracerClient,
+    run_scope::RunAnchor,
+    runtime_paths::{current_uid, socket_group},
+    tracer_client::{RunMetadata, TracerClient},
     upload::upload_from_file_path,
 };
 
+/// Time a client gets to send its request and close its end of the socket.
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Upper bound for running a command, uploads being the slowest.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Socket mode when only the owner may connect, and when allowed groups may too.
+const OWNER_SOCKET_MODE: u32 = 0o600;
+const GROUP_SOCKET_MODE: u32 = 0o660;
+
+/// Future running a command, resolving to the response payload.
 type ProcessOutput<'a> =
-    Option<Pin<Box<dyn Future<Output = Result<String, anyhow::Error>> + 'a + Send>>>;
+    Pin<Box<dyn Future<Output = Result<Option<Value>, anyhow::Error>> + 'a + Send>>;
 
 pub fn process_log_command<'a>(
     service_url: &'a str,
     api_key: &'a str,
-    object: &serde_json::Map<String, serde_json::Value>,
+    message: String,
+    attributes: MessageAttributes,
 ) -> ProcessOutput<'a> {
-    if !object.contains_key("message") {
-        return None;
-    };
-
-    let message = object.get("message").unwrap().as_str().unwrap().to_string();
-    Some(Box::pin(send_log_event(service_url, api_key, message)))
+    Box::pin(async move {
+        send_log_event(service_url, api_key, message, &attributes).await?;
+        Ok(None)
+    })
 }
 
 pub fn process_alert_command<'a>(
     service_url: &'a str,
     api_key: &'a str,
-    object: &serde_json::Map<String, serde_json::Value>,
+    message: String,
+    attributes: MessageAttributes,
 ) -> ProcessOutput<'a> {
-    if !object.contains_key("message") {
-        return None;
-    };
-
-    let message = object.get("message").unwrap().as_str().unwrap().to_string();
-    Some(Box::pin(send_alert_event(service_url, api_key, message)))
+    Box::pin(async move {
+        send_alert_event(service_url, api_key, message, &attributes).await?;
+        Ok(None)
+    })
 }
 
-pub fn process_start_run_command<'a>(
-    tracer_client: &'a Arc<Mutex<TracerClient>>,
-    stream: &'a mut UnixStream,
-) -> ProcessOutput<'a> {
-    async fn fun<'a>(
-        tracer_client: &'a Arc<Mutex<TracerClient>>,
-        stream: &'a mut UnixStream,
-    ) -> Result<String, anyhow::Error> {
-        tracer_client.lock().await.start_new_run(None).await?;
-
-        let info = tracer_client.lock().await.get_run_metadata();
+fn run_metadata_payload(run: Option<RunMetadata>) -> Result<Value> {
+    let info = match run {
+        Some(run) => InfoResponse {
+            run_name: run.name,
+            run_id: run.id,
+            service_name: run.service_name,
+            details: run.details,
+        },
+        None => InfoResponse {
+            run_name: String::new(),
+            run_id: String::new(),
+            service_name: String::new(),
+            details: RunDetails::default(),
+        },
+    };
 
-        let output = if let Some(info) = info {
-            json!({
-                "run_name": info.name,
-                "run_id": info.id,
-                "service_name": info.service_name,
-            })
-        } else {
-            json!({
-                "run_name": "",
-                "run_id": "",
-                "service_name": "",
-            })
-        };
+    Ok(serde_json::to_value(info)?)
+}
 
-        stream
-            .write_all(serde_json::to_string(&output)?.as_bytes())
-            .await?;
+pub fn process_start_run_command(
+    tracer_client: &Arc<Mutex<TracerClient>>,
+    name: Option<String>,
+    details: RunDetails,
+) -> ProcessOutput<'_> {
+    Box::pin(async move {
+        let mut tracer_client = tracer_client.lock().await;
+        let run = tracer_client.start_new_run(None, name, details).await?;
+        Ok(Some(run_metadata_payload(Some(run))?))
+    })
+}
 
-        stream.flush().await?;
+pub fn process_info_command(tracer_client: &Arc<Mutex<TracerClient>>) -> ProcessOutput<'_> {
+    Box::pin(async move {
+        let tracer_client = tracer_client.lock().await;
+        Ok(Some(run_metadata_payload(
+            tracer_client.get_run_metadata(),
+        )?))
+    })
+}
 
-        Ok("".to_string())
+fn run_status(run: &RunMetadata) -> RunStatus {
+    RunStatus {
+        run_name: run.name.clone(),
+        run_id: run.id.clone(),
+        service_name: run.service_name.clone(),
+        started_at: run.start_time,
+        anchor: run.details.anchor.clone(),
     }
-
-    Some(Box::pin(fun(tracer_client, stream)))
 }
 
-pub fn process_info_command<'a>(
-    tracer_client: &'a Arc<Mutex<TracerClient>>,
-    stream: &'a mut UnixStream,
-) -> ProcessOutput<'a> {
-    async fn fun<'a>(
-        tracer_client: &'a Arc<Mutex<TracerClient>>,
-        stream: &'a mut UnixStream,
-    ) -> Result<String, anyhow::Error> {
-        let out = tracer_client.lock().await.get_run_metadata();
-
-        let output = if let Some(out) = out {
-            json!({
-                "run_name": out.name,
-                "run_id": out.id,
-                "service_name": out.service_name,
-            })
-        } else {
-            json!({
-                "run_name": "",
-                "run_id": "",
-                "service_name": "",
-            })
-        };
+fn status_payload(tracer_client: &TracerClient) -> StatusResponse {
+    let stats = tracer_client.get_stats();
+
+    StatusResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        instance_id: tracer_client.logs.instance_id().to_string(),
+        started_at: stats.started_at,
+        uptime_seconds: (Utc::now() - stats.started_at).num_seconds().max(0) as u64,
+        run: tracer_client.get_run_metadata().as_ref().map(run_status),
+        scoped_runs: tracer_client.scoped_runs().iter().map(run_status).collect(),
+        tracked_processes: tracer_client.tracked_process_count(),
+        buffered_events: tracer_client.buffered_event_count(),
+        spooled_batches: tracer_client.spooled_batch_count(),
+        dropped_batches: tracer_client.dropped_batch_count(),
+        pending_file_uploads: tracer_client.pending_file_upload_count(),
+        last_successful_submission: stats.last_successful_submission,
+        last_failed_submission: stats.last_failed_submission,
+        submission_failures: stats.submission_failures,
+        file_upload_failures: stats.file_upload_failures,
+    }
+}
 
-        stream
-            .write_all(serde_json::to_string(&output)?.as_bytes())
-            .await?;
+pub fn process_status_command(tracer_client: &Arc<Mutex<TracerClient>>) -> ProcessOutput<'_> {
+    Box::pin(async move {
+        let tracer_client = tracer_client.lock().await;
+        Ok(Some(serde_json::to_value(status_payload(&tracer_client))?))
+    })
+}
 
-        stream.flush().await?;
+pub fn process_ps_command(tracer_client: &Arc<Mutex<TracerClient>>) -> ProcessOutput<'_> {
+    Box::pin(async move {
+        let tracer_client = tracer_client.lock().await;
+        Ok(Some(
+            json!({ "processes": tracer_client.tracked_processes() }),
+        ))
+    })
+}
 
-        Ok("".to_string())
-    }
+pub fn process_end_run_command(
+    tracer_client: &Arc<Mutex<TracerClient>>,
+    anchor: Option<RunAnchor>,
+) -> ProcessOutput<'_> {
+    Box::pin(async move {
+        let mut tracer_client = tracer_client.lock().await;
+        let summary = tracer_client.end_run(anchor.as_ref()).await?;
+        Ok(summary.map(serde_json::to_value).transpose()?)
+    })
+}
 
-    Some(Box::pin(fun(tracer_client, stream)))
+pub fn process_begin_step_command(
+    tracer_client: &Arc<Mutex<TracerClient>>,
+    name: String,
+    anchor: Option<RunAnchor>,
+) -> ProcessOutput<'_> {
+    Box::pin(async move {
+        let mut tracer_client = tracer_client.lock().await;
+        let step = tracer_client.begin_step(&name, anchor.as_ref())?;
+        Ok(Some(serde_json::to_value(step)?))
+    })
 }
 
-pub fn process_end_run_command(tracer_client: &Arc<Mutex<TracerClient>>) -> ProcessOutput<'_> {
-    Some(Box::pin(async move {
+pub fn process_end_step_command(
+    tracer_client: &Arc<Mutex<TracerClient>>,
+    name: String,
+    anchor: Option<RunAnchor>,
+) -> ProcessOutput<'_> {
+    Box::pin(async move {
         let mut tracer_client = tracer_client.lock().await;
-        tracer_client.stop_run().await?;
-        Ok("".to_string())
-    }))
+        let steps = tracer_client.end_step(&name, anchor.as_ref())?;
+        Ok(Some(serde_json::to_value(steps)?))
+    })
 }
 
 pub fn process_refresh_config_command<'a>(
@@ -139,77 +197,367 @@ pub fn process_refresh_config_command<'a>(
         tracer_client: &'a Arc<Mutex<TracerClient>>,
         config: &'a Arc<RwLock<Config>>,
         config_file: crate::config_manager::Config,
-    ) -> Result<String, anyhow::Error> {
+    ) -> Result<Option<Value>, anyhow::Error> {
         tracer_client.lock().await.reload_config_file(&config_file);
         config.write().await.clone_from(&config_file);
-        Ok("".to_string())
+        Ok(None)
     }
 
-    Some(Box::pin(fun(tracer_client, config, config_file)))
+    Box::pin(fun(tracer_client, config, config_file))
 }
 
 pub fn process_tag_command<'a>(
     service_url: &'a str,
     api_key: &'a str,
-    object: &serde_json::Map<String, serde_json::Value>,
+    tags: Vec<String>,
 ) -> ProcessOutput<'a> {
-    if !object.contains_key("tags") {
-        return None;
-    };
-
-    let tags_json = object.get("tags").unwrap().as_array().unwrap();
-
-    let tags: Vec<String> = tags_json
-        .iter()
-        .map(|tag| tag.as_str().unwrap().to_string())
-        .collect();
-
-    Some(Box::pin(send_update_tags_event(service_url, api_key, tags)))
+    Box::pin(async move {
+        send_update_tags_event(service_url, api_key, tags).await?;
+        Ok(None)
+    })
 }
 
 pub fn process_log_short_lived_process_command<'a>(
     tracer_client: &'a Arc<Mutex<TracerClient>>,
-    object: &serde_json::Map<String, serde_json::Value>,
+    log: ShortLivedProcessLog,
 ) -> ProcessOutput<'a> {
-    if !object.contains_key("log") {
-        return None;
-    };
-
-    let log: ShortLivedProcessLog =
-        serde_json::from_value(object.get("log").unwrap().clone()).unwrap();
-
-    Some(Box::pin(async move {
+    Box::pin(async move {
         let mut tracer_client = tracer_client.lock().await;
         tracer_client.fill_logs_with_short_lived_process(log)?;
-        Ok("".to_string())
-    }))
+        Ok(None)
+    })
 }
 
 pub fn process_upload_command<'a>(
     service_url: &'a str,
     api_key: &'a str,
-    object: &'a serde_json::Map<String, serde_json::Value>,
+    file_path: String,
 ) -> ProcessOutput<'a> {
-    if !object.contains_key("file_path") {
-        return None;
-    };
-
-    Some(Box::pin(async move {
+    Box::pin(async move {
         let logger = Logger::new();
 
         logger.log("server.rs//process_upload_command", None).await;
 
-        upload_from_file_path(
-            service_url,
-            api_key,
-            object.get("file_path").unwrap().as_str().unwrap(),
-            None,
-        )
-        .await?;
+        upload_from_file_path(service_url, api_key, &file_path, None).await?;
 
         logger.log("process_upload_command completed", None).await;
-        Ok("Upload command processed".to_string())
-    }))
+        Ok(None)
+    })
+}
+
+/// Checks the envelope of a request and reads the command.
+fn parse_request(message: &str) -> Result<Request> {
+    let parsed: Value = serde_json::from_str(message).context("Invalid JSON")?;
+
+    let Value::Object(object) = parsed else {
+        bail!("Invalid request, expected a JSON object");
+    };
+
+    // Requests from clients predating the versioned protocol have no version
+    let version = object
+        .get("version")
+        .map(|version| version.as_u64().unwrap_or(0))
+        .unwrap_or(PROTOCOL_VERSION as u64);
+    if version != PROTOCOL_VERSION as u64 {
+        bail!(
+            "Unsupported protocol version {}, the daemon speaks version {}",
+            version,
+            PROTOCOL_VERSION
+        );
+    }
+
+    serde_json::from_value(Value::Object(object)).context("Invalid request")
+}
+
+/// Whether a process running as `uid` in `gids`, its primary and supplementary groups, may
+/// send commands.
+fn is_peer_allowed(uid: u32, gids: &[u32], owner_uid: u32, allowed_gids: &[u32]) -> bool {
+    uid == owner_uid || gids.iter().any(|gid| allowed_gids.contains(gid))
+}
+
+/// Supplementary groups of a process, which the socket credentials leave out. Empty when the
+/// process is gone or its status can't be read.
+fn supplementary_groups(pid: Option<i32>) -> Vec<u32> {
+    pid.and_then(|pid| std::fs::read_to_string(format!("/proc/{}/status", pid)).ok())
+        .map(|status| parse_status_groups(&status))
+        .unwrap_or_default()
+}
+
+fn parse_status_groups(status: &str) -> Vec<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| {
+            groups
+                .split_whitespace()
+                .filter_map(|gid| gid.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn write_response(stream: &mut UnixStream, response: &Response) {
+    let result = async {
+        stream
+            .write_all(serde_json::to_string(response)?.as_bytes())
+            .await?;
+        stream.flush().await?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(error) = result {
+        eprintln!("[{}] Failed to write response: {}", Utc::now(), error);
+    }
+}
+
+fn check_event_types(event_types: &[String]) -> Result<()> {
+    for event_type in event_types {
+        if !EventType::ALL
+            .iter()
+            .any(|known| known.as_str() == event_type)
+        {
+            bail!("Unknown event type: {}", event_type);
+        }
+    }
+    Ok(())
+}
+
+async fn write_line<T: serde::Serialize>(stream: &mut UnixStream, value: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    Ok(())
+}
+
+/// Answers an `events` request with an envelope line followed by one line per event: the
+/// events buffered since the last submission, then, when following, every new one until
+/// the client goes away or the daemon stops.
+async fn stream_events(
+    mut stream: UnixStream,
+    event_types: Option<Vec<String>>,
+    follow: bool,
+    tracer_client: &Arc<Mutex<TracerClient>>,
+    cancellation_token: &CancellationToken,
+) -> Result<()> {
+    if let Err(error) = check_event_types(event_types.as_deref().unwrap_or_default()) {
+        write_response(&mut stream, &Response::error(error.to_string())).await;
+        return Ok(());
+    }
+    let is_selected = |event: &Event| {
+        event_types
+            .as_ref()
+            .is_none_or(|event_types| event_types.contains(&event.process_status))
+    };
+
+    let (buffered, mut receiver) = tracer_client.lock().await.subscribe_events();
+
+    write_line(&mut stream, &Response::ok(None)).await?;
+    for event in buffered.iter().filter(|event| is_selected(event)) {
+        write_line(&mut stream, event).await?;
+    }
+
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        let event = tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            received = receiver.recv() => match received {
+                Result::Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    eprintln!("[{}] Event subscriber fell behind, skipped {} events", Utc::now(), missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        if is_selected(&event) {
+            write_line(&mut stream, &event).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a parsed request, turning the outcome into the response envelope.
+async fn process_request(
+    request: Request,
+    tracer_client: &Arc<Mutex<TracerClient>>,
+    config: &Arc<RwLock<Config>>,
+) -> Response {
+    let command = request.command();
+
+    // Only hold the client's lock long enough to copy the credentials, so a slow upload
+    // doesn't block the daemon's monitoring loop
+    let (service_url, api_key) = {
+        let tracer_client = tracer_client.lock().await;
+        let service_url = tracer_client.get_service_url().to_owned();
+        let api_key = tracer_client.get_api_key().to_owned();
+        (service_url, api_key)
+    };
+
+    let future = match request {
+        // The connection handler stops the daemon once the response is written
+        Request::Terminate => return Response::ok(None),
+        // The connection handler streams events itself
+        Request::Events { .. } => return Response::error("Events must be streamed".to_string()),
+        Request::Log {
+            message,
+            attributes,
+        } => process_log_command(&service_url, &api_key, message, attributes),
+        Request::Alert {
+            message,
+            attributes,
+        } => process_alert_command(&service_url, &api_key, message, attributes),
+        Request::Start { name, details } => process_start_run_command(tracer_client, name, details),
+        Request::End { anchor } => process_end_run_command(tracer_client, anchor),
+        Request::BeginStep { name, anchor } => {
+            process_begin_step_command(tracer_client, name, anchor)
+        }
+        Request::EndStep { name, anchor } => process_end_step_command(tracer_client, name, anchor),
+        Request::RefreshConfig => process_refresh_config_command(tracer_client, config),
+        Request::Tag { tags } => process_tag_command(&service_url, &api_key, tags),
+        Request::LogShortLivedProcess { log } => {
+            process_log_short_lived_process_command(tracer_client, *log)
+        }
+        Request::Info => process_info_command(tracer_client),
+        Request::Status => process_status_command(tracer_client),
+        Request::Ps => process_ps_command(tracer_client),
+        Request::Upload { file_path } => process_upload_command(&service_url, &api_key, file_path),
+    };
+
+    command_response(command, future.await)
+}
+
+fn command_response(command: &str, result: Result<Option<Value>>) -> Response {
+    match result {
+        Result::Ok(payload) => Response::ok(payload),
+        Err(error) => {
+            eprintln!("[{}] Command {} failed: {:?}", Utc::now(), command, error);
+            Response::error(format!("{:#}", error))
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: UnixStream,
+    tracer_client: Arc<Mutex<TracerClient>>,
+    config: Arc<RwLock<Config>>,
+    cancellation_token: CancellationToken,
+) {
+    let peer_allowed = match stream.peer_cred() {
+        Result::Ok(credentials) => {
+            let allowed_gids = config.read().await.socket_allowed_gids.clone();
+            let mut gids = vec![credentials.gid()];
+            if !allowed_gids.is_empty() {
+                gids.extend(supplementary_groups(credentials.pid()));
+            }
+            let allowed = is_peer_allowed(credentials.uid(), &gids, current_uid(), &allowed_gids);
+            if !allowed {
+                eprintln!(
+                    "[{}] Rejected control socket connection from uid {} gid {} pid {:?}",
+                    Utc::now(),
+                    credentials.uid(),
+                    credentials.gid(),
+                    credentials.pid()
+                );
+            }
+            allowed
+        }
+        Err(error) => {
+            eprintln!(
+                "[{}] Rejected control socket connection without credentials: {}",
+                Utc::now(),
+                error
+            );
+            false
+        }
+    };
+    if !peer_allowed {
+        write_response(
+            &mut stream,
+            &Response::error("Permission denied".to_string()),
+        )
+        .await;
+        return;
+    }
+
+    let logger = Logger::new();
+    let mut message = String::new();
+
+    match timeout(REQUEST_READ_TIMEOUT, stream.read_to_string(&mut message)).await {
+        Result::Ok(Result::Ok(_)) => {}
+        Result::Ok(Err(error)) => {
+            eprintln!("Error reading from socket: {}", error);
+            return;
+        }
+        Err(_) => {
+            let response = Response::error("Timed out waiting for the request".to_string());
+            write_response(&mut stream, &response).await;
+            return;
+        }
+    }
+
+    let request = match parse_request(&message) {
+        Result::Ok(request) => request,
+        Err(error) => {
+            eprintln!("{:#}: {}", error, message);
+            write_response(&mut stream, &Response::error(format!("{:#}", error))).await;
+            return;
+        }
+    };
+
+    let command = request.command();
+
+    logger
+        .log(&format!("Received command: {}, {}", command, message), None)
+        .await;
+
+    // Streams for as long as the client listens, so it isn't bound by the command timeout
+    if let Request::Events {
+        event_types,
+        follow,
+    } = request
+    {
+        let result = stream_events(
+            stream,
+            event_types,
+            follow,
+            &tracer_client,
+            &cancellation_token,
+        )
+        .await;
+        if let Err(error) = result {
+            // Usually the client disconnecting
+            logger
+                .log(&format!("Event stream ended: {}", error), None)
+                .await;
+        }
+        return;
+    }
+
+    let response = match timeout(
+        COMMAND_TIMEOUT,
+        process_request(request, &tracer_client, &config),
+    )
+    .await
+    {
+        Result::Ok(response) => response,
+        Err(_) => Response::error(format!(
+            "Command {} timed out after {} seconds",
+            command,
+            COMMAND_TIMEOUT.as_secs()
+        )),
+    };
+
+    write_response(&mut stream, &response).await;
+
+    if command == Request::Terminate.command() {
+        cancellation_token.cancel();
+    }
 }
 
 pub async fn run_server(
@@ -219,82 +567,260 @@ pub async fn run_server(
     config: Arc<RwLock<Config>>,
 ) -> Result<(), anyhow::Error> {
     if std::fs::metadata(socket_path).is_ok() {
-        std::fs::remove_file(socket_path)
-            .unwrap_or_else(|_| panic!("Failed to remove existing socket file"));
+        std::fs::remove_file(socket_path).context("Failed to remove existing socket file")?;
+    }
+
+    // Connections are checked against the allowlist too, the mode keeps other users out early
+    let socket_group = socket_group(&config.read().await.socket_allowed_gids);
+    let socket_mode = match socket_group {
+        Some(_) => GROUP_SOCKET_MODE,
+        None => OWNER_SOCKET_MODE,
+    };
+
+    // The socket lives in the runtime directory, which only its owner and the socket group
+    // may enter, so nobody else can connect before the mode is set
+    let listener = UnixListener::bind(socket_path).context("Failed to bind to unix socket")?;
+
+    if let Some(gid) = socket_group {
+        std::os::unix::fs::chown(socket_path, None, Some(gid))
+            .with_context(|| format!("Failed to give group {} the socket", gid))?;
     }
-    let listener = UnixListener::bind(socket_path).expect("Failed to bind to unix socket");
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(socket_mode))
+        .context("Failed to set the socket's mode")?;
+
     loop {
-        let (mut stream, _) = listener.accept().await.unwrap();
+        let stream = tokio::select! {
+            _ = cancellation_token.cancelled() => return Ok(()),
+            accepted = listener.accept() => match accepted {
+                Result::Ok((stream, _)) => stream,
+                Err(error) => {
+                    eprintln!("[{}] Failed to accept connection: {}", Utc::now(), error);
+                    continue;
+                }
+            },
+        };
 
-        let mut message = String::new();
+        // Every connection gets its own task, so a slow command doesn't hold up the others
+        // and a failing one can't take the listener down
+        tokio::spawn(handle_connection(
+            stream,
+            tracer_client.clone(),
+            config.clone(),
+            cancellation_token.clone(),
+        ));
+    }
+}
 
-        let logger = Logger::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
+    use crate::process_watcher::ProcessProperties;
+    use crate::runtime_paths::RuntimePaths;
+    use tempfile::{tempdir, TempDir};
+    use tokio::io::AsyncBufReadExt;
+
+    async fn send_raw(socket_path: &str, message: &str) -> Response {
+        let mut stream = UnixStream::connect(socket_path).await.unwrap();
+        stream.write_all(message.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
 
-        let result = stream.read_to_string(&mut message).await;
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(r#"{"version": 1, "command": "info"}"#).unwrap();
+        assert_eq!(request, Request::Info);
 
-        if result.is_err() {
-            eprintln!("Error reading from socket: {}", result.err().unwrap());
-            continue;
-        }
+        // Clients predating the versioned protocol
+        assert!(parse_request(r#"{"command": "info"}"#).is_ok());
 
-        let json_parse_result = serde_json::from_str(&message);
+        let error = parse_request(r#"{"version": 2, "command": "info"}"#).unwrap_err();
+        assert!(error.to_string().contains("Unsupported protocol version 2"));
 
-        if json_parse_result.is_err() {
-            eprintln!("Error parsing JSON: {}", json_parse_result.err().unwrap());
-            continue;
-        }
+        assert!(parse_request(r#"{"version": 1}"#).is_err());
+        assert!(parse_request(r#"{"version": 1, "command": "log"}"#).is_err());
+        assert!(parse_request(r#"{"version": 1, "command": "unknown"}"#).is_err());
+        assert!(parse_request("[]").is_err());
+        assert!(parse_request("not json").is_err());
+    }
 
-        let parsed: Value = json_parse_result.unwrap();
+    /// Daemon socket served by a real `TracerClient` talking to the mock service.
+    struct TestServer {
+        service: MockService,
+        socket_path: String,
+        cancellation_token: CancellationToken,
+        server: tokio::task::JoinHandle<Result<()>>,
+        _directory: TempDir,
+    }
 
-        if !parsed.is_object() {
-            eprintln!("Invalid JSON received: {}", message);
-            continue;
-        }
+    impl TestServer {
+        async fn start() -> Result<TestServer> {
+            let service = MockService::start().await;
+            let mut config = ConfigManager::load_default_config();
+            config.service_url = service.url().to_string();
+            config.api_key = MOCK_API_KEY.to_string();
+
+            let directory = tempdir()?;
+            let paths = RuntimePaths::new(directory.path());
+            let socket_path = paths.socket.clone();
+
+            let tracer_client = Arc::new(Mutex::new(
+                TracerClient::new(
+                    config.clone(),
+                    directory.path().to_str().unwrap().to_string(),
+                    &paths,
+                )
+                .await?,
+            ));
+            let cancellation_token = CancellationToken::new();
+            let server = tokio::spawn({
+                let socket_path = socket_path.clone();
+                let cancellation_token = cancellation_token.clone();
+                async move {
+                    run_server(
+                        tracer_client,
+                        &socket_path,
+                        cancellation_token,
+                        Arc::new(RwLock::new(config)),
+                    )
+                    .await
+                }
+            });
+            while UnixStream::connect(&socket_path).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
 
-        let object = parsed.as_object().unwrap();
+            Ok(TestServer {
+                service,
+                socket_path,
+                cancellation_token,
+                server,
+                _directory: directory,
+            })
+        }
 
-        if !object.contains_key("command") {
-            eprintln!("Invalid JSON, no command field, received: {}", message);
-            continue;
+        async fn stop(self) -> Result<()> {
+            self.cancellation_token.cancel();
+            self.server.await?
         }
+    }
 
-        let command = object.get("command").unwrap().as_str().unwrap();
+    #[tokio::test]
+    async fn test_server_survives_bad_requests_and_slow_clients() -> Result<()> {
+        let server = TestServer::start().await?;
+        let socket_path = server.socket_path.clone();
 
-        let (service_url, api_key) = {
-            let tracer_client = tracer_client.lock().await;
-            let service_url = tracer_client.get_service_url().to_owned();
-            let api_key = tracer_client.get_api_key().to_owned();
-            (service_url, api_key)
-        };
+        let socket_mode = std::fs::metadata(&socket_path)?.permissions().mode();
+        assert_eq!(socket_mode & 0o777, OWNER_SOCKET_MODE);
 
-        logger
-            .log(&format!("Received command: {}, {}", command, message), None)
-            .await;
+        // A client that never finishes its request doesn't hold up the others
+        let _slow_client = UnixStream::connect(&socket_path).await?;
 
-        let result = match command {
-            "terminate" => {
-                cancellation_token.cancel();
-                return Ok(());
-            }
-            "log" => process_log_command(&service_url, &api_key, object),
-            "alert" => process_alert_command(&service_url, &api_key, object),
-            "start" => process_start_run_command(&tracer_client, &mut stream),
-            "end" => process_end_run_command(&tracer_client),
-            "refresh_config" => process_refresh_config_command(&tracer_client, &config),
-            "tag" => process_tag_command(&service_url, &api_key, object),
-            "log_short_lived_process" => {
-                process_log_short_lived_process_command(&tracer_client, object)
-            }
-            "info" => process_info_command(&tracer_client, &mut stream),
-            "upload" => process_upload_command(&service_url, &api_key, object),
-            _ => {
-                eprintln!("Invalid command: {}", command);
-                None
-            }
-        };
+        let response = send_raw(&socket_path, "not json").await;
+        assert!(response.error.unwrap().contains("Invalid JSON"));
 
-        if let Some(future) = result {
-            future.await?;
-        }
+        let response = send_raw(&socket_path, r#"{"version": 1, "command": 42}"#).await;
+        assert!(response.into_result().is_err());
+
+        let response = send_raw(&socket_path, r#"{"version": 1, "command": "log"}"#).await;
+        assert!(response.error.unwrap().contains("missing field `message`"));
+
+        let response = send_raw(&socket_path, r#"{"version": 1, "command": "info"}"#).await;
+        assert_eq!(response.into_result()?.unwrap()["run_id"], "");
+
+        let response = send_raw(&socket_path, r#"{"version": 1, "command": "status"}"#).await;
+        let status: StatusResponse = serde_json::from_value(response.into_result()?.unwrap())?;
+        assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+        assert!(status.run.is_none());
+
+        let response = send_raw(&socket_path, r#"{"version": 1, "command": "ps"}"#).await;
+        assert_eq!(response.into_result()?.unwrap()["processes"], json!([]));
+
+        let response = send_raw(
+            &socket_path,
+            r#"{"version": 1, "command": "log", "message": "still serving"}"#,
+        )
+        .await;
+        assert!(response.into_result().is_ok());
+        assert_eq!(server.service.events()[0]["message"], "still serving");
+
+        server.stop().await
+    }
+
+    #[tokio::test]
+    async fn test_events_stream_follows_new_events() -> Result<()> {
+        let server = TestServer::start().await?;
+
+        let response = send_raw(
+            &server.socket_path,
+            r#"{"version": 1, "command": "events", "event_types": ["no_such_event"]}"#,
+        )
+        .await;
+        assert!(response.error.unwrap().contains("Unknown event type"));
+
+        let mut stream = UnixStream::connect(&server.socket_path).await?;
+        stream
+            .write_all(
+                json!({
+                    "version": 1,
+                    "command": "events",
+                    "follow": true,
+                    "event_types": ["tool_execution"],
+                })
+                .to_string()
+                .as_bytes(),
+            )
+            .await?;
+        stream.shutdown().await?;
+        let mut lines = tokio::io::BufReader::new(stream).lines();
+
+        let response: Response = serde_json::from_str(&lines.next_line().await?.unwrap())?;
+        assert!(response.into_result()?.is_none());
+
+        let log = json!({
+            "command": "short-tool",
+            "timestamp": "2024-08-01 10:00:00 UTC",
+            "properties": ProcessProperties::default(),
+        });
+        let response = send_raw(
+            &server.socket_path,
+            &json!({ "version": 1, "command": "log_short_lived_process", "log": log }).to_string(),
+        )
+        .await;
+        assert!(response.into_result().is_ok());
+
+        let event: Value = serde_json::from_str(&lines.next_line().await?.unwrap())?;
+        assert_eq!(event["process_status"], "tool_execution");
+        assert!(event["message"].as_str().unwrap().contains("short-tool"));
+
+        // Stopping the daemon ends the stream
+        server.stop().await?;
+        assert_eq!(lines.next_line().await?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_peer_allowed() {
+        assert!(is_peer_allowed(1000, &[1000], 1000, &[]));
+        assert!(!is_peer_allowed(1001, &[1001], 1000, &[]));
+        assert!(is_peer_allowed(1001, &[2000], 1000, &[2000]));
+        assert!(!is_peer_allowed(1001, &[1001], 1000, &[2000]));
+        // Supplementary groups count as well as the primary group
+        assert!(is_peer_allowed(1001, &[1001, 3000, 2000], 1000, &[2000]));
+        // Root gets no special treatment
+        assert!(!is_peer_allowed(0, &[0], 1000, &[]));
+    }
+
+    #[test]
+    fn test_parse_status_groups() {
+        let status =
+            "Name:\tbash\nUid:\t1001\t1001\t1001\t1001\nGroups:\t4 27 2000 \nVmPeak:\t0 kB\n";
+        assert_eq!(parse_status_groups(status), vec![4, 27, 2000]);
+        assert_eq!(parse_status_groups("Groups:\n"), Vec::<u32>::new());
     }
 }
@@ -1,8 +1,321 @@
-use serde::Deserialize;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Deserialize)]
+use crate::event_attributes::{MessageAttributes, RunDetails};
+use crate::process_watcher::{ShortLivedProcessLog, TrackedProcess};
+use crate::run_scope::RunAnchor;
+
+/// Version of the control socket protocol, sent with every request and response.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Commands of the control socket. Serializes to the request object, e.g.
+/// `{"command": "tag", "tags": ["a"]}`, the client adds the protocol version.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Log {
+        message: String,
+        #[serde(default, skip_serializing_if = "MessageAttributes::is_empty")]
+        attributes: MessageAttributes,
+    },
+    Alert {
+        message: String,
+        #[serde(default, skip_serializing_if = "MessageAttributes::is_empty")]
+        attributes: MessageAttributes,
+    },
+    /// Ends the current run and starts a new one
+    Start {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(flatten)]
+        details: RunDetails,
+    },
+    /// Ends the run scoped to `anchor`, or the current run without one
+    End {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        anchor: Option<RunAnchor>,
+    },
+    /// Starts a step nested in the running one, in the run scoped to `anchor` or the current run
+    BeginStep {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        anchor: Option<RunAnchor>,
+    },
+    /// Ends the innermost running step called `name` and the steps running inside it
+    EndStep {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        anchor: Option<RunAnchor>,
+    },
+    Terminate,
+    RefreshConfig,
+    Tag {
+        tags: Vec<String>,
+    },
+    LogShortLivedProcess {
+        log: Box<ShortLivedProcessLog>,
+    },
+    Info,
+    Status,
+    Ps,
+    Upload {
+        file_path: String,
+    },
+    /// Answered with JSON lines instead of a single response
+    Events {
+        #[serde(default)]
+        follow: bool,
+        /// Only events with one of these `process_status`, every event when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        event_types: Option<Vec<String>>,
+    },
+}
+
+impl Request {
+    pub fn command(&self) -> &'static str {
+        match self {
+            Request::Log { .. } => "log",
+            Request::Alert { .. } => "alert",
+            Request::Start { .. } => "start",
+            Request::End { .. } => "end",
+            Request::BeginStep { .. } => "begin_step",
+            Request::EndStep { .. } => "end_step",
+            Request::Terminate => "terminate",
+            Request::RefreshConfig => "refresh_config",
+            Request::Tag { .. } => "tag",
+            Request::LogShortLivedProcess { .. } => "log_short_lived_process",
+            Request::Info => "info",
+            Request::Status => "status",
+            Request::Ps => "ps",
+            Request::Upload { .. } => "upload",
+            Request::Events { .. } => "events",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct InfoResponse {
     pub run_name: String,
     pub run_id: String,
     pub service_name: String,
+    #[serde(flatten)]
+    pub details: RunDetails,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunStatus {
+    pub run_name: String,
+    pub run_id: String,
+    pub service_name: String,
+    pub started_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<RunAnchor>,
+}
+
+/// Health of the daemon, answered to `status`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatusResponse {
+    pub version: String,
+    /// Stamped on the events this daemon records, new every time the daemon starts
+    #[serde(default)]
+    pub instance_id: String,
+    pub started_at: DateTime<Utc>,
+    pub uptime_seconds: u64,
+    pub run: Option<RunStatus>,
+    /// Runs started with `tracer start --scope`, alongside the current run
+    #[serde(default)]
+    pub scoped_runs: Vec<RunStatus>,
+    pub tracked_processes: usize,
+    /// Events recorded since the last submission
+    pub buffered_events: usize,
+    /// Batches spooled after a failed submission, waiting to be sent again
+    pub spooled_batches: usize,
+    /// Spooled batches dropped to keep the spool within its size and age limits
+    #[serde(default)]
+    pub dropped_batches: u64,
+    /// Watched files waiting to be uploaded
+    #[serde(default)]
+    pub pending_file_uploads: usize,
+    pub last_successful_submission: Option<DateTime<Utc>>,
+    pub last_failed_submission: Option<DateTime<Utc>>,
+    pub submission_failures: u64,
+    pub file_upload_failures: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PsResponse {
+    pub processes: Vec<TrackedProcess>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Ok,
+    Error,
+}
+
+/// Envelope the daemon answers every request with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    pub version: u32,
+    pub status: ResponseStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+}
+
+impl Response {
+    pub fn ok(payload: Option<Value>) -> Self {
+        Response {
+            version: PROTOCOL_VERSION,
+            status: ResponseStatus::Ok,
+            error: None,
+            payload,
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        Response {
+            version: PROTOCOL_VERSION,
+            status: ResponseStatus::Error,
+            error: Some(message),
+            payload: None,
+        }
+    }
+
+    /// Turns an error response into an error, returning the payload otherwise.
+    pub fn into_result(self) -> Result<Option<Value>> {
+        match self.status {
+            ResponseStatus::Ok => Ok(self.payload),
+            ResponseStatus::Error => bail!(
+                "{}",
+                self.error
+                    .unwrap_or_else(|| "The daemon failed the command".to_string())
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_response_wire_format() {
+        let response = Response::ok(Some(json!({ "run_name": "run" })));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({ "version": 1, "status": "ok", "payload": { "run_name": "run" } })
+        );
+
+        let response = Response::error("Unknown command: foo".to_string());
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({ "version": 1, "status": "error", "error": "Unknown command: foo" })
+        );
+    }
+
+    #[test]
+    fn test_request_wire_format() {
+        let requests = [
+            (
+                Request::Start {
+                    name: None,
+                    details: RunDetails::default(),
+                },
+                json!({ "command": "start" }),
+            ),
+            (
+                Request::Start {
+                    name: Some("batch-117".to_string()),
+                    details: RunDetails {
+                        external_id: Some("LIMS-117".to_string()),
+                        tags: vec!["rna-seq".to_string()],
+                        ..Default::default()
+                    },
+                },
+                json!({
+                    "command": "start",
+                    "name": "batch-117",
+                    "external_id": "LIMS-117",
+                    "tags": ["rna-seq"],
+                }),
+            ),
+            (
+                Request::Log {
+                    message: "hello".to_string(),
+                    attributes: MessageAttributes::default(),
+                },
+                json!({ "command": "log", "message": "hello" }),
+            ),
+            (
+                Request::Alert {
+                    message: "QC failed".to_string(),
+                    attributes: MessageAttributes {
+                        step: Some("qc".to_string()),
+                        ..Default::default()
+                    },
+                },
+                json!({ "command": "alert", "message": "QC failed", "attributes": { "step": "qc" } }),
+            ),
+            (
+                Request::RefreshConfig,
+                json!({ "command": "refresh_config" }),
+            ),
+            (Request::End { anchor: None }, json!({ "command": "end" })),
+            (
+                Request::End {
+                    anchor: Some(RunAnchor::WorkingDirectory("/data".to_string())),
+                },
+                json!({ "command": "end", "anchor": { "working_directory": "/data" } }),
+            ),
+            (
+                Request::BeginStep {
+                    name: "align".to_string(),
+                    anchor: None,
+                },
+                json!({ "command": "begin_step", "name": "align" }),
+            ),
+            (
+                Request::EndStep {
+                    name: "align".to_string(),
+                    anchor: Some(RunAnchor::RootPid(42)),
+                },
+                json!({ "command": "end_step", "name": "align", "anchor": { "root_pid": 42 } }),
+            ),
+            (
+                Request::Tag {
+                    tags: vec!["a".to_string()],
+                },
+                json!({ "command": "tag", "tags": ["a"] }),
+            ),
+            (
+                Request::Events {
+                    follow: true,
+                    event_types: None,
+                },
+                json!({ "command": "events", "follow": true }),
+            ),
+        ];
+
+        for (request, wire) in requests {
+            assert_eq!(serde_json::to_value(&request).unwrap(), wire);
+            assert_eq!(wire["command"], request.command());
+            assert_eq!(serde_json::from_value::<Request>(wire).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn test_into_result() {
+        assert_eq!(Response::ok(None).into_result().unwrap(), None);
+
+        let error = Response::error("Failed to start run".to_string())
+            .into_result()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Failed to start run");
+    }
 }
@@ -0,0 +1,118 @@
+// src/daemon_state.rs
+//! State kept across daemon restarts, so updating the daemon or recovering from a crash in the
+//! middle of a pipeline continues its runs instead of starting new ones, announcing the running
+//! tools again and uploading the same files twice.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::event_attributes::RunDetails;
+use crate::file_watcher::WatchedFileInfo;
+use crate::process_watcher::PersistedProcess;
+use crate::run_summary::RunTally;
+use crate::steps::StepStack;
+
+const TEMPORARY_FILE_EXTENSION: &str = "tmp";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PersistedRun {
+    pub name: String,
+    pub id: String,
+    pub service_name: String,
+    pub parent_pid: Option<u32>,
+    pub start_time: DateTime<Utc>,
+    #[serde(default)]
+    pub details: RunDetails,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct DaemonState {
+    pub current_run: Option<PersistedRun>,
+    #[serde(default)]
+    pub scoped_runs: Vec<PersistedRun>,
+    /// Tracked tools, restored when they are still running
+    #[serde(default)]
+    pub processes: Vec<PersistedProcess>,
+    #[serde(default)]
+    pub watched_files: Vec<WatchedFileInfo>,
+    /// Summaries of the runs in progress, by run id
+    #[serde(default)]
+    pub run_tallies: HashMap<String, RunTally>,
+    /// Steps still running, by run id
+    #[serde(default)]
+    pub steps: HashMap<String, StepStack>,
+}
+
+impl DaemonState {
+    /// Reads the state saved by a previous daemon, `None` when there is none.
+    pub fn load(path: &Path) -> Result<Option<DaemonState>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(path)
+            .with_context(|| format!("Failed to read daemon state {}", path.display()))?;
+        let state = serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse daemon state {}", path.display()))?;
+
+        Ok(Some(state))
+    }
+
+    /// Replaces the saved state, never leaving a partially written file behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary_path = path.with_extension(TEMPORARY_FILE_EXTENSION);
+
+        fs::write(&temporary_path, serde_json::to_vec(self)?)
+            .context("Failed to write daemon state")?;
+        fs::rename(&temporary_path, path).context("Failed to commit daemon state")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let directory = tempdir()?;
+        let path = directory.path().join("tracerd_state.json");
+
+        assert!(DaemonState::load(&path)?.is_none());
+
+        let run = PersistedRun {
+            name: "batch-117".to_string(),
+            id: "run-id".to_string(),
+            service_name: "service".to_string(),
+            parent_pid: Some(42),
+            start_time: Utc::now(),
+            details: RunDetails {
+                external_id: Some("LIMS-117".to_string()),
+                ..Default::default()
+            },
+        };
+        DaemonState {
+            current_run: Some(run.clone()),
+            run_tallies: HashMap::from([("run-id".to_string(), RunTally::default())]),
+            ..Default::default()
+        }
+        .save(&path)?;
+
+        let state = DaemonState::load(&path)?.unwrap();
+        assert_eq!(state.current_run, Some(run));
+        assert!(state.scoped_runs.is_empty());
+        assert!(state.run_tallies.contains_key("run-id"));
+        assert!(!path.with_extension(TEMPORARY_FILE_EXTENSION).exists());
+
+        fs::write(&path, "{")?;
+        assert!(DaemonState::load(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -1,17 +1,42 @@
+use std::path::PathBuf;
+
 use anyhow::Context;
 use chrono::Utc;
+use lazy_static::lazy_static;
 use serde_json::Value;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 
+lazy_static! {
+    static ref LOG_DIRECTORY: PathBuf = default_log_directory();
+}
+
+#[cfg(not(test))]
+fn default_log_directory() -> PathBuf {
+    PathBuf::from(".")
+}
+
+/// Keeps the test suite from writing debug and http call logs into the working tree.
+#[cfg(test)]
+fn default_log_directory() -> PathBuf {
+    tempfile::tempdir()
+        .expect("Failed to create test log directory")
+        .into_path()
+}
+
+/// Resolves a log file name against the directory debug logs are written to.
+pub fn log_file_path(file_name: &str) -> PathBuf {
+    LOG_DIRECTORY.join(file_name)
+}
+
 pub struct Logger {
-    log_file_path: String,
+    log_file_path: PathBuf,
 }
 
 impl Logger {
     pub fn new() -> Self {
         Self {
-            log_file_path: "debug.log".to_string(),
+            log_file_path: log_file_path("debug.log"),
         }
     }
 
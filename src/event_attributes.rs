@@ -0,0 +1,397 @@
+// src/event_attributes.rs
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::event_recorder::EventType;
+use crate::process_watcher::{InputFile, ProcessProperties};
+use crate::run_scope::RunAnchor;
+
+/// Version of the event payloads. Bump it whenever a field is renamed, removed or changes type.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ToolExecutionAttributes {
+    #[serde(flatten)]
+    pub properties: ProcessProperties,
+    /// Files from the command line the file watcher knows about. Absent for short-lived
+    /// processes reported through `tracer log-short-lived-process`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_files: Option<Vec<InputFile>>,
+    /// Path of the step running when the tool started, e.g. `align/index`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct StepAttributes {
+    pub step_name: String,
+    /// Names of the step and the steps it is nested in, joined with `/`
+    pub step_path: String,
+    /// Time the step ran, in milliseconds. Only set once it finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct FinishedToolAttributes {
+    pub tool_name: String,
+    pub tool_pid: String,
+    /// Time between the tool being seen and exiting, in milliseconds
+    pub duration: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DiskStatistic {
+    pub disk_total_space: u64,
+    pub disk_used_space: u64,
+    pub disk_available_space: u64,
+    pub disk_utilization: f64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SystemMetricAttributes {
+    pub events_name: String,
+    pub system_memory_total: u64,
+    pub system_memory_used: u64,
+    pub system_memory_available: u64,
+    pub system_memory_utilization: f64,
+    pub system_memory_swap_total: u64,
+    pub system_memory_swap_used: u64,
+    pub system_cpu_utilization: f32,
+    /// Keyed by disk name
+    pub system_disk_io: HashMap<String, DiskStatistic>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SyslogErrorAttributes {
+    pub system_metrics: SystemMetricAttributes,
+    pub error_display_name: String,
+    pub error_id: String,
+    pub error_line: String,
+    pub file_line_number: usize,
+    pub file_previous_logs: Vec<String>,
+}
+
+/// Metadata a run is started with through `tracer start`, e.g. to match it with a LIMS batch.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq)]
+pub struct RunDetails {
+    /// Identifier of the run in another system
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Parameters of the pipeline, e.g. its version or reference genome
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Part of the system the run is scoped to, alongside the daemon's own run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<RunAnchor>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct NewRunAttributes {
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub arch: Option<String>,
+    pub num_cpus: usize,
+    pub hostname: Option<String>,
+    pub total_memory: u64,
+    pub total_swap: u64,
+    pub uptime: u64,
+    pub aws_metadata: Value,
+    pub is_aws_instance: bool,
+    pub system_disk_io: HashMap<String, DiskStatistic>,
+    /// Run id generated by the daemon when it was started with `--offline`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline_run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline_run_name: Option<String>,
+    /// Name asked for with `tracer start --name`, the service names the run otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_name: Option<String>,
+    #[serde(flatten)]
+    pub details: RunDetails,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+/// Attributes of the messages sent with `tracer log` and `tracer alert`.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq)]
+pub struct MessageAttributes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    /// Pipeline step the message is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    /// Values given by the pipeline, e.g. sample counts or QC verdicts
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+}
+
+impl MessageAttributes {
+    pub fn is_empty(&self) -> bool {
+        self == &MessageAttributes::default()
+    }
+}
+
+/// Payload of an event, one variant per event type. Serializes to the bare attribute
+/// object, so the wire format does not carry the variant name.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum EventAttributes {
+    ToolExecution(ToolExecutionAttributes),
+    ToolMetric(ProcessProperties),
+    FinishedTool(FinishedToolAttributes),
+    SystemMetric(SystemMetricAttributes),
+    SyslogError(SyslogErrorAttributes),
+    NewRun(Box<NewRunAttributes>),
+    Message(MessageAttributes),
+    Step(StepAttributes),
+    /// Attributes of event types without a schema, or that didn't match theirs
+    Other(Value),
+}
+
+impl EventAttributes {
+    /// Reads attributes of an event with the given `process_status`. Payloads written by
+    /// other versions that don't fit the typed variant are kept as they are.
+    pub fn from_value(process_status: &str, value: Value) -> EventAttributes {
+        fn parse<T: for<'de> Deserialize<'de>>(
+            value: &Value,
+            variant: fn(T) -> EventAttributes,
+        ) -> Option<EventAttributes> {
+            T::deserialize(value).ok().map(variant)
+        }
+
+        let typed = match process_status {
+            "tool_execution" => parse(&value, EventAttributes::ToolExecution),
+            "tool_metric_event" => parse(&value, EventAttributes::ToolMetric),
+            "finished_tool_execution" => parse(&value, EventAttributes::FinishedTool),
+            "metric_event" => parse(&value, EventAttributes::SystemMetric),
+            "syslog_event" => parse(&value, EventAttributes::SyslogError),
+            "new_run" => parse(&value, |attributes| {
+                EventAttributes::NewRun(Box::new(attributes))
+            }),
+            RUN_STATUS_MESSAGE | ALERT => parse(&value, EventAttributes::Message),
+            "step_started" | "step_finished" => parse(&value, EventAttributes::Step),
+            _ => None,
+        };
+
+        typed.unwrap_or(EventAttributes::Other(value))
+    }
+
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// `process_status` of the messages sent with `tracer log` and `tracer alert`, which go to the
+/// service directly instead of through the recorder
+pub const RUN_STATUS_MESSAGE: &str = "run_status_message";
+pub const ALERT: &str = "alert";
+
+/// JSON Schema of the events the daemon exports, with the attributes schema picked by
+/// `process_status`.
+pub fn event_schema() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+
+    let typed_attributes = [
+        (
+            EventType::ToolExecution.as_str(),
+            generator.subschema_for::<ToolExecutionAttributes>(),
+        ),
+        (
+            EventType::ToolMetricEvent.as_str(),
+            generator.subschema_for::<ProcessProperties>(),
+        ),
+        (
+            EventType::FinishedToolExecution.as_str(),
+            generator.subschema_for::<FinishedToolAttributes>(),
+        ),
+        (
+            EventType::MetricEvent.as_str(),
+            generator.subschema_for::<SystemMetricAttributes>(),
+        ),
+        (
+            EventType::SyslogEvent.as_str(),
+            generator.subschema_for::<SyslogErrorAttributes>(),
+        ),
+        (
+            EventType::NewRun.as_str(),
+            generator.subschema_for::<NewRunAttributes>(),
+        ),
+        (
+            RUN_STATUS_MESSAGE,
+            generator.subschema_for::<MessageAttributes>(),
+        ),
+        (ALERT, generator.subschema_for::<MessageAttributes>()),
+        (
+            EventType::StepStarted.as_str(),
+            generator.subschema_for::<StepAttributes>(),
+        ),
+        (
+            EventType::StepFinished.as_str(),
+            generator.subschema_for::<StepAttributes>(),
+        ),
+    ];
+
+    let conditions: Vec<Value> = typed_attributes
+        .iter()
+        .map(|(process_status, schema)| {
+            json!({
+                "if": { "properties": { "process_status": { "const": process_status } } },
+                "then": { "properties": { "attributes": schema } },
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Tracer event",
+        "version": EVENT_SCHEMA_VERSION,
+        "type": "object",
+        "required": ["timestamp", "message", "event_type", "process_type", "process_status"],
+        "properties": {
+            "schema_version": { "type": "integer", "const": EVENT_SCHEMA_VERSION },
+            "timestamp": { "type": "integer", "description": "Unix timestamp in seconds" },
+            "message": { "type": "string" },
+            "event_type": { "type": "string" },
+            "process_type": { "type": "string" },
+            "process_status": { "type": "string" },
+            "attributes": { "type": ["object", "null"] },
+            "run_id": { "type": "string", "description": "Run the event was recorded in" },
+            "run_name": { "type": "string" },
+            "instance_id": {
+                "type": "string",
+                "description": "Daemon that recorded the event, new every time it starts",
+            },
+        },
+        "allOf": conditions,
+        "definitions": generator.definitions(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system_metrics() -> Value {
+        json!({
+            "events_name": "global_system_metrics",
+            "system_memory_total": 1024,
+            "system_memory_used": 512,
+            "system_memory_available": 512,
+            "system_memory_utilization": 50.0,
+            "system_memory_swap_total": 0,
+            "system_memory_swap_used": 0,
+            "system_cpu_utilization": 12.5,
+            "system_disk_io": {
+                "/dev/sda1": {
+                    "disk_total_space": 100,
+                    "disk_used_space": 40,
+                    "disk_available_space": 60,
+                    "disk_utilization": 40.0,
+                }
+            },
+        })
+    }
+
+    #[test]
+    fn test_typed_attributes_round_trip_unchanged() {
+        let metrics = system_metrics();
+        let attributes = EventAttributes::from_value("metric_event", metrics.clone());
+
+        assert!(matches!(attributes, EventAttributes::SystemMetric(_)));
+        assert_eq!(attributes.to_value(), metrics);
+
+        let finished = json!({ "tool_name": "STAR", "tool_pid": "42", "duration": 1500 });
+        let attributes = EventAttributes::from_value("finished_tool_execution", finished.clone());
+
+        assert!(matches!(attributes, EventAttributes::FinishedTool(_)));
+        assert_eq!(attributes.to_value(), finished);
+
+        let step = json!({ "step_name": "index", "step_path": "align/index", "duration": 1200 });
+        let attributes = EventAttributes::from_value("step_finished", step.clone());
+
+        assert!(matches!(attributes, EventAttributes::Step(_)));
+        assert_eq!(attributes.to_value(), step);
+    }
+
+    #[test]
+    fn test_tool_execution_input_files_are_optional() {
+        let mut properties = serde_json::to_value(ProcessProperties::default()).unwrap();
+        let attributes = EventAttributes::from_value("tool_execution", properties.clone());
+
+        assert_eq!(attributes.to_value(), properties);
+
+        properties["input_files"] = json!([]);
+        let attributes = EventAttributes::from_value("tool_execution", properties.clone());
+
+        assert_eq!(attributes.to_value(), properties);
+    }
+
+    #[test]
+    fn test_message_attributes() {
+        let attributes = json!({ "level": "warning", "fields": { "samples": 12 } });
+        let parsed = EventAttributes::from_value("alert", attributes.clone());
+
+        let EventAttributes::Message(message) = &parsed else {
+            panic!("Expected message attributes, got {:?}", parsed);
+        };
+        assert_eq!(message.level, Some(LogLevel::Warning));
+        assert_eq!(message.step, None);
+        assert_eq!(parsed.to_value(), attributes);
+
+        assert!(MessageAttributes::default().is_empty());
+        assert_eq!(
+            serde_json::to_value(MessageAttributes::default()).unwrap(),
+            json!({})
+        );
+    }
+
+    #[test]
+    fn test_mismatched_attributes_are_kept() {
+        let value = json!({ "key": "value" });
+
+        assert_eq!(
+            EventAttributes::from_value("metric_event", value.clone()),
+            EventAttributes::Other(value.clone())
+        );
+        assert_eq!(
+            EventAttributes::from_value("test_event", value.clone()),
+            EventAttributes::Other(value)
+        );
+    }
+
+    #[test]
+    fn test_event_schema() {
+        let schema = event_schema();
+
+        assert_eq!(schema["version"], EVENT_SCHEMA_VERSION);
+        assert!(schema["properties"]["run_id"].is_object());
+        assert_eq!(schema["allOf"].as_array().unwrap().len(), 10);
+        assert_eq!(
+            schema["allOf"][3]["if"]["properties"]["process_status"]["const"],
+            "metric_event"
+        );
+
+        let definitions = &schema["definitions"];
+        assert!(definitions["SystemMetricAttributes"]["properties"]["system_disk_io"].is_object());
+        assert!(definitions["ProcessProperties"]["properties"]["tool_pid"].is_object());
+        assert!(definitions["MessageAttributes"]["properties"]["fields"].is_object());
+    }
+}
@@ -2,20 +2,94 @@ use chrono::serde::ts_seconds;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::event_attributes::{EventAttributes, EVENT_SCHEMA_VERSION};
+
+/// Events a slow subscriber may fall behind by before it starts missing some.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+const INSTANCE_ID_CHARSET: &str = "0123456789abcdef";
+const INSTANCE_ID_LENGTH: usize = 16;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "RawEvent")]
 pub struct Event {
+    pub schema_version: u32,
     #[serde(with = "ts_seconds")]
-    timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
     message: String,
     pub event_type: String,
     process_type: String,
+    pub process_status: String,
+    pub attributes: Option<EventAttributes>,
+    /// Run the event was recorded in, absent outside of runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_name: Option<String>,
+    /// Daemon that recorded the event, new every time the daemon starts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+}
+
+/// Wire form of an event, the attributes are typed once the process status is known.
+#[derive(Deserialize)]
+struct RawEvent {
+    // Events spooled before the schema was versioned
+    #[serde(default = "first_schema_version")]
+    schema_version: u32,
+    #[serde(with = "ts_seconds")]
+    timestamp: DateTime<Utc>,
+    message: String,
+    event_type: String,
+    process_type: String,
     process_status: String,
-    pub attributes: Option<Value>,
+    attributes: Option<Value>,
+    // Events spooled before they were stamped with their run
+    #[serde(default)]
+    run_id: Option<String>,
+    #[serde(default)]
+    run_name: Option<String>,
+    #[serde(default)]
+    instance_id: Option<String>,
+}
+
+fn first_schema_version() -> u32 {
+    1
+}
+
+impl From<RawEvent> for Event {
+    fn from(raw: RawEvent) -> Self {
+        Event {
+            attributes: raw
+                .attributes
+                .map(|value| EventAttributes::from_value(&raw.process_status, value)),
+            schema_version: raw.schema_version,
+            timestamp: raw.timestamp,
+            message: raw.message,
+            event_type: raw.event_type,
+            process_type: raw.process_type,
+            process_status: raw.process_status,
+            run_id: raw.run_id,
+            run_name: raw.run_name,
+            instance_id: raw.instance_id,
+        }
+    }
+}
+
+/// Run the recorded events are stamped with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordingRun {
+    pub id: String,
+    pub name: String,
 }
 
 pub struct EventRecorder {
     events: Vec<Event>,
+    subscribers: broadcast::Sender<Event>,
+    instance_id: String,
+    run: Option<RecordingRun>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
@@ -27,10 +101,25 @@ pub enum EventType {
     ToolMetricEvent,
     MetricEvent,
     SyslogEvent,
+    StepStarted,
+    StepFinished,
     TestEvent, // Added TestEvent variant
 }
 
 impl EventType {
+    pub const ALL: [EventType; 10] = [
+        EventType::NewRun,
+        EventType::FinishedRun,
+        EventType::ToolExecution,
+        EventType::FinishedToolExecution,
+        EventType::ToolMetricEvent,
+        EventType::MetricEvent,
+        EventType::SyslogEvent,
+        EventType::StepStarted,
+        EventType::StepFinished,
+        EventType::TestEvent,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             EventType::NewRun => "new_run",
@@ -40,6 +129,8 @@ impl EventType {
             EventType::MetricEvent => "metric_event",
             EventType::SyslogEvent => "syslog_event",
             EventType::ToolMetricEvent => "tool_metric_event",
+            EventType::StepStarted => "step_started",
+            EventType::StepFinished => "step_finished",
             EventType::TestEvent => "test_event", // Handle TestEvent
         }
     }
@@ -47,24 +138,66 @@ impl EventType {
 
 impl EventRecorder {
     pub fn new() -> Self {
-        EventRecorder { events: Vec::new() }
+        let (subscribers, _) = broadcast::channel(SUBSCRIBER_BUFFER);
+        EventRecorder {
+            events: Vec::new(),
+            subscribers,
+            instance_id: random_string::generate(INSTANCE_ID_LENGTH, INSTANCE_ID_CHARSET),
+            run: None,
+        }
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Stamps the events recorded from now on with `run`. Events already recorded keep the
+    /// run they were recorded in, even when they are submitted after the switch.
+    pub fn set_run(&mut self, run: Option<RecordingRun>) {
+        self.run = run;
+    }
+
+    /// Receives every event recorded from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.subscribers.subscribe()
     }
 
     pub fn record_event(
         &mut self,
         event_type: EventType,
         message: String,
-        attributes: Option<Value>,
+        attributes: Option<EventAttributes>,
         timestamp: Option<DateTime<Utc>>,
     ) {
+        self.record_scoped_event(None, event_type, message, attributes, timestamp);
+    }
+
+    /// Records an event in the scoped run `scope` when given, in the current run otherwise.
+    pub fn record_scoped_event(
+        &mut self,
+        scope: Option<&RecordingRun>,
+        event_type: EventType,
+        message: String,
+        attributes: Option<EventAttributes>,
+        timestamp: Option<DateTime<Utc>>,
+    ) {
+        let run = scope.or(self.run.as_ref());
         let event = Event {
+            schema_version: EVENT_SCHEMA_VERSION,
             timestamp: timestamp.unwrap_or_else(Utc::now),
             message,
             event_type: "process_status".to_owned(),
             process_type: "pipeline".to_owned(),
             process_status: event_type.as_str().to_owned(),
             attributes,
+            run_id: run.map(|run| run.id.clone()),
+            run_name: run.map(|run| run.name.clone()),
+            instance_id: Some(self.instance_id.clone()),
         };
+        if self.subscribers.receiver_count() > 0 {
+            // Only fails when every subscriber went away in the meantime
+            let _ = self.subscribers.send(event.clone());
+        }
         self.events.push(event);
     }
 
@@ -81,7 +214,6 @@ impl EventRecorder {
         self.events.is_empty()
     }
 
-    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.events.len()
     }
@@ -102,7 +234,7 @@ mod tests {
     fn test_record_event() {
         let mut recorder = EventRecorder::new();
         let message = "[event_recorder.rs]Test event".to_string();
-        let attributes = Some(json!({"key": "value"}));
+        let attributes = Some(EventAttributes::Other(json!({"key": "value"})));
 
         recorder.record_event(
             EventType::ToolExecution,
@@ -136,11 +268,27 @@ mod tests {
         assert!(recorder.is_empty());
     }
 
+    #[test]
+    fn test_subscribers_receive_new_events() {
+        let mut recorder = EventRecorder::new();
+        recorder.record_event(EventType::TestEvent, "Before".to_string(), None, None);
+
+        let mut subscriber = recorder.subscribe();
+        recorder.record_event(EventType::ToolExecution, "After".to_string(), None, None);
+        recorder.clear();
+
+        let event = subscriber.try_recv().unwrap();
+        assert_eq!(event.message, "After");
+        assert_eq!(event.process_status, "tool_execution");
+        assert!(subscriber.try_recv().is_err());
+    }
+
     #[test]
     fn test_event_type_as_str() {
         assert_eq!(EventType::FinishedRun.as_str(), "finished_run");
         assert_eq!(EventType::ToolExecution.as_str(), "tool_execution");
         assert_eq!(EventType::MetricEvent.as_str(), "metric_event");
+        assert_eq!(EventType::StepFinished.as_str(), "step_finished");
         assert_eq!(EventType::TestEvent.as_str(), "test_event");
     }
 
@@ -148,7 +296,7 @@ mod tests {
     fn test_record_test_event() {
         let mut recorder = EventRecorder::new();
         let message = "Test event for testing".to_string();
-        let attributes = Some(json!({"test_key": "test_value"}));
+        let attributes = Some(EventAttributes::Other(json!({"test_key": "test_value"})));
 
         recorder.record_event(
             EventType::TestEvent,
@@ -166,4 +314,97 @@ mod tests {
         assert_eq!(event.process_status, "test_event");
         assert_eq!(event.attributes, attributes);
     }
+
+    #[test]
+    fn test_event_wire_format() {
+        let mut recorder = EventRecorder::new();
+        let attributes = json!({ "tool_name": "STAR", "tool_pid": "42", "duration": 1500 });
+        recorder.record_event(
+            EventType::FinishedToolExecution,
+            "STAR exited".to_string(),
+            Some(EventAttributes::from_value(
+                "finished_tool_execution",
+                attributes.clone(),
+            )),
+            None,
+        );
+
+        let value = serde_json::to_value(&recorder.get_events()[0]).unwrap();
+        assert_eq!(value["schema_version"], EVENT_SCHEMA_VERSION);
+        assert_eq!(value["process_status"], "finished_tool_execution");
+        assert_eq!(value["attributes"], attributes);
+
+        let event: Event = serde_json::from_value(value).unwrap();
+        assert!(matches!(
+            event.attributes,
+            Some(EventAttributes::FinishedTool(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_unversioned_event() {
+        let event: Event = serde_json::from_value(json!({
+            "timestamp": 1700000000,
+            "message": "old event",
+            "event_type": "process_status",
+            "process_type": "pipeline",
+            "process_status": "test_event",
+            "attributes": null,
+        }))
+        .unwrap();
+
+        assert_eq!(event.schema_version, 1);
+        assert_eq!(event.attributes, None);
+        assert_eq!(event.run_id, None);
+        assert_eq!(event.instance_id, None);
+    }
+
+    #[test]
+    fn test_events_are_stamped_with_their_run() {
+        let mut recorder = EventRecorder::new();
+        recorder.record_event(EventType::TestEvent, "Outside".to_string(), None, None);
+
+        recorder.set_run(Some(RecordingRun {
+            id: "run-1".to_string(),
+            name: "first".to_string(),
+        }));
+        recorder.record_event(EventType::TestEvent, "First".to_string(), None, None);
+
+        recorder.set_run(Some(RecordingRun {
+            id: "run-2".to_string(),
+            name: "second".to_string(),
+        }));
+        recorder.record_event(EventType::TestEvent, "Second".to_string(), None, None);
+
+        let scoped = RecordingRun {
+            id: "run-3".to_string(),
+            name: "scoped".to_string(),
+        };
+        recorder.record_scoped_event(
+            Some(&scoped),
+            EventType::TestEvent,
+            "Scoped".to_string(),
+            None,
+            None,
+        );
+
+        let events = recorder.get_events();
+        assert_eq!(events[0].run_id, None);
+        assert_eq!(events[1].run_id.as_deref(), Some("run-1"));
+        assert_eq!(events[1].run_name.as_deref(), Some("first"));
+        assert_eq!(events[2].run_id.as_deref(), Some("run-2"));
+        assert_eq!(events[3].run_id.as_deref(), Some("run-3"));
+        assert_eq!(events[3].run_name.as_deref(), Some("scoped"));
+        assert!(events
+            .iter()
+            .all(|event| event.instance_id.as_deref() == Some(recorder.instance_id())));
+
+        let value = serde_json::to_value(&events[1]).unwrap();
+        assert_eq!(value["run_id"], "run-1");
+        assert_eq!(value["instance_id"], recorder.instance_id());
+        assert!(serde_json::to_value(&events[0])
+            .unwrap()
+            .get("run_id")
+            .is_none());
+    }
 }
@@ -0,0 +1,292 @@
+// src/event_spool.rs
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::warn;
+
+use crate::config_manager::Config;
+use crate::event_recorder::Event;
+
+const BATCH_FILE_EXTENSION: &str = "json";
+const TEMPORARY_FILE_EXTENSION: &str = "tmp";
+const CORRUPT_FILE_EXTENSION: &str = "corrupt";
+
+/// How much the spool keeps through a long outage, the oldest batches are dropped first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpoolLimits {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+impl SpoolLimits {
+    pub fn from_config(config: &Config) -> Self {
+        SpoolLimits {
+            max_bytes: config.spool_max_bytes,
+            max_age: Duration::from_millis(config.spool_max_age_ms),
+        }
+    }
+}
+
+/// Persistent queue of event batches that have not been delivered yet.
+///
+/// Every batch is written to its own file before it is sent and the file is only
+/// removed once the service acknowledged it, so batches survive failed requests
+/// and daemon restarts. File names sort in the order the batches were written.
+pub struct EventSpool {
+    directory: PathBuf,
+    sequence: u64,
+    limits: SpoolLimits,
+    dropped_batches: u64,
+}
+
+impl EventSpool {
+    pub fn new(directory: &str, limits: SpoolLimits) -> Result<Self> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create spool directory {}", directory))?;
+
+        let mut spool = EventSpool {
+            directory: PathBuf::from(directory),
+            sequence: 0,
+            limits,
+            dropped_batches: 0,
+        };
+        // A previous daemon may have left a spool behind from an outage
+        spool.enforce_limits()?;
+
+        Ok(spool)
+    }
+
+    pub fn write_batch(&mut self, events: &[Event]) -> Result<PathBuf> {
+        let file_name = format!("{:013}-{:06}", Utc::now().timestamp_millis(), self.sequence);
+        self.sequence += 1;
+
+        let temporary_path = self
+            .directory
+            .join(&file_name)
+            .with_extension(TEMPORARY_FILE_EXTENSION);
+        let batch_path = self
+            .directory
+            .join(&file_name)
+            .with_extension(BATCH_FILE_EXTENSION);
+
+        fs::write(&temporary_path, serde_json::to_vec(events)?)
+            .context("Failed to write spooled batch")?;
+        fs::rename(&temporary_path, &batch_path).context("Failed to commit spooled batch")?;
+        self.enforce_limits()?;
+
+        Ok(batch_path)
+    }
+
+    /// Drops the batches older than the age limit, then the oldest ones until the spool fits
+    /// in the size limit. The newest batch is always kept.
+    fn enforce_limits(&mut self) -> Result<()> {
+        let now = Utc::now().timestamp_millis();
+        let max_age = i64::try_from(self.limits.max_age.as_millis()).unwrap_or(i64::MAX);
+
+        let mut batches = vec![];
+        let mut total_bytes = 0;
+        for path in self.pending_batches()? {
+            let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            total_bytes += size;
+            batches.push((path, size));
+        }
+
+        let mut dropped = 0;
+        for (path, size) in &batches[..batches.len().saturating_sub(1)] {
+            let too_old = batch_timestamp(path).is_some_and(|written| now - written > max_age);
+            if !too_old && total_bytes <= self.limits.max_bytes {
+                break;
+            }
+
+            self.remove_batch(path)?;
+            total_bytes -= size;
+            dropped += 1;
+        }
+
+        if dropped > 0 {
+            warn!(
+                "Dropped {} spooled batches over the spool's size or age limit",
+                dropped
+            );
+            self.dropped_batches += dropped;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the spooled batches, oldest first.
+    pub fn pending_batches(&self) -> Result<Vec<PathBuf>> {
+        let mut batches = vec![];
+
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(BATCH_FILE_EXTENSION) {
+                batches.push(path);
+            }
+        }
+
+        batches.sort();
+        Ok(batches)
+    }
+
+    pub fn read_batch(&self, path: &Path) -> Result<Vec<Event>> {
+        let content = fs::read(path)?;
+        let events = serde_json::from_slice(&content);
+
+        if events.is_err() {
+            // Keep unreadable batches around for inspection, but stop replaying them
+            fs::rename(path, path.with_extension(CORRUPT_FILE_EXTENSION))?;
+        }
+
+        events.with_context(|| format!("Failed to parse spooled batch {:?}", path))
+    }
+
+    pub fn remove_batch(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path).with_context(|| format!("Failed to remove spooled batch {:?}", path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending_batches().map(|b| b.len()).unwrap_or(0)
+    }
+
+    /// Takes effect with the next batch written.
+    pub fn set_limits(&mut self, limits: SpoolLimits) {
+        self.limits = limits;
+    }
+
+    /// Batches dropped to stay within the limits since the spool was opened.
+    pub fn dropped_batches(&self) -> u64 {
+        self.dropped_batches
+    }
+}
+
+/// Milliseconds timestamp the batch file name starts with.
+fn batch_timestamp(path: &Path) -> Option<i64> {
+    path.file_stem()?.to_str()?.split('-').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_recorder::{EventRecorder, EventType};
+    use tempfile::tempdir;
+
+    const UNLIMITED: SpoolLimits = SpoolLimits {
+        max_bytes: u64::MAX,
+        max_age: Duration::MAX,
+    };
+
+    fn record_events(count: usize) -> EventRecorder {
+        let mut recorder = EventRecorder::new();
+        for i in 0..count {
+            recorder.record_event(
+                EventType::TestEvent,
+                format!("[event_spool.rs] Test event {}", i),
+                None,
+                None,
+            );
+        }
+        recorder
+    }
+
+    #[test]
+    fn test_write_and_read_batches_in_order() -> Result<()> {
+        let directory = tempdir()?;
+        let mut spool = EventSpool::new(directory.path().to_str().unwrap(), UNLIMITED)?;
+
+        let first = spool.write_batch(record_events(2).get_events())?;
+        let second = spool.write_batch(record_events(3).get_events())?;
+
+        assert_eq!(
+            spool.pending_batches()?,
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(spool.read_batch(&first)?.len(), 2);
+        assert_eq!(spool.read_batch(&second)?.len(), 3);
+
+        spool.remove_batch(&first)?;
+        assert_eq!(spool.pending_batches()?, vec![second]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches_survive_reopening() -> Result<()> {
+        let directory = tempdir()?;
+        let directory_path = directory.path().to_str().unwrap();
+
+        {
+            let mut spool = EventSpool::new(directory_path, UNLIMITED)?;
+            spool.write_batch(record_events(1).get_events())?;
+        }
+
+        let spool = EventSpool::new(directory_path, UNLIMITED)?;
+        assert_eq!(spool.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oldest_batches_are_dropped_over_the_size_limit() -> Result<()> {
+        let directory = tempdir()?;
+        let batch_size = serde_json::to_vec(record_events(1).get_events())?.len() as u64;
+        let mut spool = EventSpool::new(
+            directory.path().to_str().unwrap(),
+            SpoolLimits {
+                // Room for two batches, their sizes vary by a few bytes
+                max_bytes: batch_size * 5 / 2,
+                max_age: Duration::MAX,
+            },
+        )?;
+
+        spool.write_batch(record_events(1).get_events())?;
+        let second = spool.write_batch(record_events(1).get_events())?;
+        let third = spool.write_batch(record_events(1).get_events())?;
+
+        assert_eq!(spool.pending_batches()?, vec![second, third]);
+        assert_eq!(spool.dropped_batches(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches_over_the_age_limit_are_dropped_on_open() -> Result<()> {
+        let directory = tempdir()?;
+        let directory_path = directory.path().to_str().unwrap();
+        fs::write(directory.path().join("0000000000000-000000.json"), "[]")?;
+        fs::write(directory.path().join("0000000000001-000001.json"), "[]")?;
+
+        let mut spool = EventSpool::new(
+            directory_path,
+            SpoolLimits {
+                max_bytes: u64::MAX,
+                max_age: Duration::from_secs(60),
+            },
+        )?;
+        // The newest batch is kept even when it is too old
+        assert_eq!(spool.len(), 1);
+
+        let written = spool.write_batch(record_events(1).get_events())?;
+        assert_eq!(spool.pending_batches()?, vec![written]);
+        assert_eq!(spool.dropped_batches(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_batch_is_set_aside() -> Result<()> {
+        let directory = tempdir()?;
+        let spool = EventSpool::new(directory.path().to_str().unwrap(), UNLIMITED)?;
+        let path = directory.path().join("0000000000000-000000.json");
+        fs::write(&path, "not json")?;
+
+        assert!(spool.read_batch(&path).is_err());
+        assert_eq!(spool.len(), 0);
+        assert!(path.with_extension(CORRUPT_FILE_EXTENSION).exists());
+
+        Ok(())
+    }
+}
@@ -3,6 +3,9 @@ use std::time::Duration;
 // src/events/mod.rs
 use crate::{
     debug_log::Logger,
+    event_attributes::{
+        MessageAttributes, NewRunAttributes, RunDetails, ALERT, RUN_STATUS_MESSAGE,
+    },
     http_client::{send_http_event, send_http_get},
     metrics::SystemMetricsCollector,
 };
@@ -14,8 +17,8 @@ use sysinfo::System;
 use tracing::info;
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum EventStatus {
-    #[allow(dead_code)]
     NewRun,
 }
 
@@ -31,28 +34,42 @@ impl std::fmt::Display for EventStatus {
     }
 }
 
-pub async fn send_log_event(service_url: &str, api_key: &str, message: String) -> Result<String> {
-    let log_entry = json!({
+fn message_entry(message: String, process_status: &str, attributes: &MessageAttributes) -> Value {
+    let mut entry = json!({
         "message": message,
         "process_type": "pipeline",
-        "process_status": "run_status_message",
+        "process_status": process_status,
         "event_type": "process_status",
         "timestamp": Utc::now().timestamp_millis() as f64 / 1000.,
     });
 
+    if !attributes.is_empty() {
+        entry["attributes"] = json!(attributes);
+    }
+
+    entry
+}
+
+pub async fn send_log_event(
+    service_url: &str,
+    api_key: &str,
+    message: String,
+    attributes: &MessageAttributes,
+) -> Result<String> {
+    let log_entry = message_entry(message, RUN_STATUS_MESSAGE, attributes);
+
     send_http_event(service_url, api_key, &log_entry)
         .await
         .context("Failed to send HTTP event")
 }
 
-pub async fn send_alert_event(service_url: &str, api_key: &str, message: String) -> Result<String> {
-    let alert_entry = json!({
-        "message": message,
-        "process_type": "pipeline",
-        "process_status": "alert",
-        "event_type": "process_status",
-        "timestamp": Utc::now().timestamp_millis() as f64 / 1000.,
-    });
+pub async fn send_alert_event(
+    service_url: &str,
+    api_key: &str,
+    message: String,
+    attributes: &MessageAttributes,
+) -> Result<String> {
+    let alert_entry = message_entry(message, ALERT, attributes);
 
     send_http_event(service_url, api_key, &alert_entry)
         .await
@@ -65,6 +82,9 @@ pub struct RunEventOut {
     pub service_name: String,
 }
 
+const OFFLINE_RUN_ID_CHARSET: &str = "0123456789abcdef";
+const OFFLINE_SERVICE_NAME: &str = "offline";
+
 const AWS_METADATA_URL: &str = "http://169.254.169.254/latest/meta-data/";
 
 async fn get_aws_instance_metadata() -> Result<Value> {
@@ -77,33 +97,86 @@ async fn get_aws_instance_metadata() -> Result<Value> {
     ))
 }
 
-async fn gather_system_properties(system: &System) -> Value {
+async fn gather_system_properties(system: &System) -> NewRunAttributes {
     let aws_metadata = get_aws_instance_metadata().await.unwrap_or(json!(null));
 
+    system_properties(system, aws_metadata)
+}
+
+fn system_properties(system: &System, aws_metadata: Value) -> NewRunAttributes {
     let disk_metadata = SystemMetricsCollector::gather_disk_data();
 
-    json!(
-        {
-            "os": System::name(),
-            "os_version": System::os_version(),
-            "kernel_version": System::kernel_version(),
-            "arch": System::cpu_arch(),
-            "num_cpus": system.cpus().len(),
-            "hostname": System::host_name(),
-            "total_memory": system.total_memory(),
-            "total_swap": system.total_swap(),
-            "uptime": System::uptime(),
-            "aws_metadata": aws_metadata,
-            "is_aws_instance": !aws_metadata.is_null(),
-            "system_disk_io": disk_metadata,
-        }
-    )
+    NewRunAttributes {
+        os: System::name(),
+        os_version: System::os_version(),
+        kernel_version: System::kernel_version(),
+        arch: System::cpu_arch(),
+        num_cpus: system.cpus().len(),
+        hostname: System::host_name(),
+        total_memory: system.total_memory(),
+        total_swap: system.total_swap(),
+        uptime: System::uptime(),
+        is_aws_instance: !aws_metadata.is_null(),
+        aws_metadata,
+        system_disk_io: disk_metadata,
+        offline_run_id: None,
+        offline_run_name: None,
+        run_name: None,
+        details: RunDetails::default(),
+    }
 }
 
+fn start_run_entry(attributes: NewRunAttributes) -> Value {
+    json!({
+        "message": "[CLI] Starting new pipeline run",
+        "process_type": "pipeline",
+        "process_status": "new_run",
+        "event_type": "process_status",
+        "timestamp": Utc::now().timestamp_millis() as f64 / 1000.,
+        "attributes": attributes,
+    })
+}
+
+/// Starts a run without asking the service, for the offline mode. The run gets a local id
+/// that travels with the new_run event, so it can be matched up after a replay.
+pub async fn record_offline_start_run_event(
+    service_url: &str,
+    api_key: &str,
+    system: &System,
+    run_name: Option<String>,
+    details: &RunDetails,
+) -> Result<RunEventOut> {
+    let run = RunEventOut {
+        run_name: run_name
+            .clone()
+            .unwrap_or_else(|| format!("offline-run-{}", Utc::now().format("%Y%m%d-%H%M%S"))),
+        run_id: format!(
+            "offline-{}",
+            random_string::generate(16, OFFLINE_RUN_ID_CHARSET)
+        ),
+        service_name: OFFLINE_SERVICE_NAME.to_string(),
+    };
+
+    // Offline hosts often have no route to the instance metadata endpoint, and waiting for it
+    // to time out would stall `tracer init --offline`
+    let mut attributes = system_properties(system, json!(null));
+    attributes.offline_run_id = Some(run.run_id.clone());
+    attributes.offline_run_name = Some(run.run_name.clone());
+    attributes.run_name = run_name;
+    attributes.details = details.clone();
+
+    send_http_event(service_url, api_key, &start_run_entry(attributes)).await?;
+
+    Ok(run)
+}
+
+/// Starts a run on the service, which assigns its id, and its name unless `run_name` is given.
 pub async fn send_start_run_event(
     service_url: &str,
     api_key: &str,
     system: &System,
+    run_name: Option<String>,
+    details: &RunDetails,
 ) -> Result<RunEventOut> {
     info!("Starting new pipeline...");
 
@@ -126,20 +199,16 @@ pub async fn send_start_run_event(
         result: Vec<RunLogOut>,
     }
 
-    let system_properties = gather_system_properties(system).await;
+    let mut attributes = gather_system_properties(system).await;
+    attributes.run_name = run_name;
+    attributes.details = details.clone();
 
-    let init_entry = json!({
-        "message": "[CLI] Starting new pipeline run",
-        "process_type": "pipeline",
-        "process_status": "new_run",
-        "event_type": "process_status",
-        "timestamp": Utc::now().timestamp_millis() as f64 / 1000.,
-        "attributes": system_properties,
-    });
+    let init_entry = start_run_entry(attributes);
 
     let result = send_http_event(service_url, api_key, &init_entry).await?;
 
-    let value: RunLogResult = serde_json::from_str(&result).unwrap();
+    let value: RunLogResult =
+        serde_json::from_str(&result).context("Invalid response from server")?;
 
     logger
         .log(
@@ -178,11 +247,17 @@ pub async fn send_start_run_event(
     })
 }
 
-pub async fn send_end_run_event(service_url: &str, api_key: &str) -> Result<String> {
+pub async fn send_end_run_event(
+    service_url: &str,
+    api_key: &str,
+    run_id: &str,
+    message: &str,
+) -> Result<String> {
     info!("Finishing pipeline run...");
 
     let end_entry = json!({
-        "message": "[CLI] Finishing pipeline run",
+        "message": message,
+        "run_id": run_id,
         "process_type": "pipeline",
         "process_status": "finished_run",
         "event_type": "process_status",
@@ -227,19 +302,127 @@ pub async fn send_update_tags_event(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config_manager::ConfigManager;
+    use crate::event_attributes::LogLevel;
+    use crate::mock_service::{MockService, MOCK_API_KEY, MOCK_RUN_ID, MOCK_RUN_NAME};
+    use crate::run_scope::RunAnchor;
     use anyhow::Error;
 
     #[tokio::test]
     async fn test_event_log() -> Result<(), Error> {
-        let config = ConfigManager::load_default_config();
+        let service = MockService::start().await;
         send_log_event(
-            &config.service_url.clone(),
-            &config.api_key.clone(),
+            service.url(),
+            MOCK_API_KEY,
             "Test".to_string(),
+            &MessageAttributes::default(),
+        )
+        .await?;
+
+        let events = service.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["message"], "Test");
+        assert_eq!(events[0]["process_status"], "run_status_message");
+        assert!(events[0].get("attributes").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_alert_event_attributes() -> Result<(), Error> {
+        let service = MockService::start().await;
+        let attributes = MessageAttributes {
+            level: Some(LogLevel::Error),
+            step: Some("qc".to_string()),
+            fields: json!({ "samples": 12, "verdict": "fail" })
+                .as_object()
+                .unwrap()
+                .clone(),
+        };
+        send_alert_event(
+            service.url(),
+            MOCK_API_KEY,
+            "QC failed".to_string(),
+            &attributes,
         )
         .await?;
 
+        let events = service.events();
+        assert_eq!(events[0]["process_status"], "alert");
+        assert_eq!(
+            events[0]["attributes"],
+            json!({ "level": "error", "step": "qc", "fields": { "samples": 12, "verdict": "fail" } })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_run_event() -> Result<(), Error> {
+        let service = MockService::start().await;
+        let run = send_start_run_event(
+            service.url(),
+            MOCK_API_KEY,
+            &System::new(),
+            None,
+            &RunDetails::default(),
+        )
+        .await?;
+
+        assert_eq!(run.run_name, MOCK_RUN_NAME);
+        assert_eq!(run.run_id, MOCK_RUN_ID);
+        assert_eq!(service.events()[0]["process_status"], "new_run");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_run_event_with_unexpected_response() {
+        let service = MockService::start().await;
+        // A success status with a body that is not the created run, e.g. from a proxy
+        service.respond_with_statuses("/data-collector-api", &[200]);
+
+        let result = send_start_run_event(
+            service.url(),
+            MOCK_API_KEY,
+            &System::new(),
+            None,
+            &RunDetails::default(),
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_start_run_event_with_metadata() -> Result<(), Error> {
+        let service = MockService::start().await;
+        let details = RunDetails {
+            external_id: Some("LIMS-2024-117".to_string()),
+            tags: vec!["rna-seq".to_string()],
+            params: json!({ "genome": "GRCh38" }).as_object().unwrap().clone(),
+            description: Some("Batch 117".to_string()),
+            anchor: Some(RunAnchor::RootPid(4242)),
+        };
+        let run = send_start_run_event(
+            service.url(),
+            MOCK_API_KEY,
+            &System::new(),
+            Some("batch-117".to_string()),
+            &details,
+        )
+        .await?;
+
+        assert_eq!(run.run_name, "batch-117");
+        assert_eq!(run.run_id, MOCK_RUN_ID);
+
+        let attributes = &service.events()[0]["attributes"];
+        assert_eq!(attributes["run_name"], "batch-117");
+        assert_eq!(attributes["external_id"], "LIMS-2024-117");
+        assert_eq!(attributes["tags"], json!(["rna-seq"]));
+        assert_eq!(attributes["params"]["genome"], "GRCh38");
+        assert_eq!(attributes["description"], "Batch 117");
+        assert_eq!(attributes["anchor"], json!({ "root_pid": 4242 }));
+
         Ok(())
     }
 }
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use tokio::fs::{create_dir_all, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use super::{EventExporter, ExportContext, ExportOutput};
+use crate::event_recorder::Event;
+
+/// Appends every event as one JSON object per line.
+pub struct JsonLinesFileExporter {
+    path: PathBuf,
+}
+
+impl JsonLinesFileExporter {
+    pub fn new(path: PathBuf) -> Self {
+        JsonLinesFileExporter { path }
+    }
+}
+
+impl EventExporter for JsonLinesFileExporter {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn export<'a>(
+        &'a mut self,
+        events: &'a [Event],
+        _context: &'a ExportContext<'a>,
+    ) -> ExportOutput<'a> {
+        Box::pin(async move {
+            let mut lines = Vec::new();
+            for event in events {
+                serde_json::to_writer(&mut lines, event)?;
+                lines.push(b'\n');
+            }
+
+            if let Some(directory) = self.path.parent() {
+                create_dir_all(directory).await?;
+            }
+
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .with_context(|| format!("Failed to open events file {:?}", self.path))?;
+
+            file.write_all(&lines).await?;
+            file.flush().await?;
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_recorder::{EventRecorder, EventType};
+    use anyhow::Result;
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_events_are_appended_as_json_lines() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("nested").join("events.jsonl");
+        let mut exporter = JsonLinesFileExporter::new(path.clone());
+
+        let mut recorder = EventRecorder::new();
+        recorder.record_event(EventType::TestEvent, "first".to_string(), None, None);
+        recorder.record_event(EventType::TestEvent, "second".to_string(), None, None);
+
+        let process_tree = HashMap::new();
+        let context = ExportContext {
+            run: None,
+            process_tree: &process_tree,
+        };
+
+        exporter.export(recorder.get_events(), &context).await?;
+        exporter
+            .export(&recorder.get_events()[..1], &context)
+            .await?;
+
+        let content = std::fs::read_to_string(path)?;
+        let lines: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["message"], "first");
+        assert_eq!(lines[1]["message"], "second");
+        assert_eq!(lines[2]["process_status"], "test_event");
+
+        Ok(())
+    }
+}
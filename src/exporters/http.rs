@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+
+use super::{EventExporter, ExportContext, ExportOutput};
+use crate::config_manager::Config;
+use crate::event_recorder::Event;
+use crate::event_spool::{EventSpool, SpoolLimits};
+use crate::http_client::split_event_batches;
+use crate::submit_batched_data::submit_spooled_batches;
+
+/// Sends batches to the hosted service, going through the on-disk spool so nothing is
+/// lost while the service is unreachable.
+pub struct HttpExporter {
+    service_url: String,
+    api_key: String,
+    spool: EventSpool,
+}
+
+impl HttpExporter {
+    pub fn new(
+        service_url: &str,
+        api_key: &str,
+        spool_directory: &str,
+        spool_limits: SpoolLimits,
+    ) -> Result<Self> {
+        Ok(HttpExporter {
+            service_url: service_url.to_string(),
+            api_key: api_key.to_string(),
+            spool: EventSpool::new(spool_directory, spool_limits)?,
+        })
+    }
+}
+
+impl EventExporter for HttpExporter {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn export<'a>(
+        &'a mut self,
+        events: &'a [Event],
+        _context: &'a ExportContext<'a>,
+    ) -> ExportOutput<'a> {
+        Box::pin(async move {
+            // Spooled in batches the service takes in one request, so a batch it rejected
+            // halfway is not resent in full
+            for batch in split_event_batches(events)? {
+                self.spool
+                    .write_batch(batch)
+                    .context("Failed to spool batched events")?;
+            }
+
+            self.flush().await
+        })
+    }
+
+    fn flush(&mut self) -> ExportOutput<'_> {
+        Box::pin(submit_spooled_batches(
+            &self.service_url,
+            &self.api_key,
+            &mut self.spool,
+        ))
+    }
+
+    fn reconfigure(&mut self, config: &Config) {
+        self.service_url.clone_from(&config.service_url);
+        self.api_key.clone_from(&config.api_key);
+        self.spool.set_limits(SpoolLimits::from_config(config));
+    }
+
+    fn pending_batches(&self) -> usize {
+        self.spool.len()
+    }
+
+    fn dropped_batches(&self) -> u64 {
+        self.spool.dropped_batches()
+    }
+}
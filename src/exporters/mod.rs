@@ -0,0 +1,268 @@
+mod file;
+mod http;
+mod otlp;
+mod stdout;
+
+use std::{collections::HashMap, future::Future, path::Path, pin::Pin};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sysinfo::Pid;
+
+use crate::config_manager::Config;
+use crate::event_recorder::Event;
+use crate::event_spool::SpoolLimits;
+use crate::process_watcher::ProcessTreeNode;
+use crate::tracer_client::RunMetadata;
+
+pub use file::JsonLinesFileExporter;
+pub use http::HttpExporter;
+pub use otlp::OtlpExporter;
+pub use stdout::StdoutExporter;
+
+pub type ExportOutput<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+const DEFAULT_EVENTS_FILE_NAME: &str = "tracer-events.jsonl";
+
+/// Sink selection in `tracer.toml`, e.g.
+///
+/// ```toml
+/// [[exporters]]
+/// type = "http"
+///
+/// [[exporters]]
+/// type = "file"
+/// path = "tracer-events.jsonl"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExporterConfig {
+    /// The hosted `/data-collector-api`
+    Http,
+    /// JSON lines appended to a file, relative paths resolve against the workflow directory
+    File { path: Option<String> },
+    /// JSON lines printed to the daemon's stdout
+    Stdout,
+    /// OpenTelemetry collector over OTLP/HTTP JSON, e.g. `http://localhost:4318`
+    Otlp { endpoint: String },
+}
+
+/// What the daemon knew when the batch was collected.
+pub struct ExportContext<'a> {
+    pub run: Option<&'a RunMetadata>,
+    pub process_tree: &'a HashMap<Pid, ProcessTreeNode>,
+}
+
+/// A destination for the batches collected by the `EventRecorder`.
+pub trait EventExporter: Send {
+    fn name(&self) -> &'static str;
+
+    fn export<'a>(
+        &'a mut self,
+        events: &'a [Event],
+        context: &'a ExportContext<'a>,
+    ) -> ExportOutput<'a>;
+
+    /// Delivers anything the exporter kept back from earlier batches.
+    fn flush(&mut self) -> ExportOutput<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Closes out what the exporter tracks for the current run, e.g. its open spans. What that
+    /// produces is delivered by the next `export` or `flush`.
+    fn end_run(&mut self) {}
+
+    /// Applies settings reloaded from the config to an exporter kept across the reload.
+    fn reconfigure(&mut self, _config: &Config) {}
+
+    /// Number of batches kept back, waiting for the next `flush`.
+    fn pending_batches(&self) -> usize {
+        0
+    }
+
+    /// Number of batches given up on, e.g. to keep the spool within its limits.
+    fn dropped_batches(&self) -> u64 {
+        0
+    }
+}
+
+fn build_exporter(
+    exporter: &ExporterConfig,
+    config: &Config,
+    workflow_directory: &str,
+    spool_directory: &str,
+) -> Result<Box<dyn EventExporter>> {
+    Ok(match exporter {
+        ExporterConfig::Http => Box::new(HttpExporter::new(
+            &config.service_url,
+            &config.api_key,
+            spool_directory,
+            SpoolLimits::from_config(config),
+        )?),
+        ExporterConfig::File { path } => {
+            let path = Path::new(workflow_directory)
+                .join(path.as_deref().unwrap_or(DEFAULT_EVENTS_FILE_NAME));
+            Box::new(JsonLinesFileExporter::new(path))
+        }
+        ExporterConfig::Stdout => Box::new(StdoutExporter::new()),
+        ExporterConfig::Otlp { endpoint } => Box::new(OtlpExporter::new(endpoint)),
+    })
+}
+
+pub fn build_exporters(
+    config: &Config,
+    workflow_directory: &str,
+    spool_directory: &str,
+) -> Result<Vec<Box<dyn EventExporter>>> {
+    config
+        .exporters
+        .iter()
+        .map(|exporter| build_exporter(exporter, config, workflow_directory, spool_directory))
+        .collect()
+}
+
+/// Replaces `exporters`, built from `previous`, with the ones `config` asks for. Exporters
+/// whose entry is unchanged are kept along with their state, e.g. the spans the OTLP exporter
+/// has open. On error `exporters` are left as they were.
+pub fn rebuild_exporters(
+    config: &Config,
+    previous: &[ExporterConfig],
+    exporters: &mut Vec<Box<dyn EventExporter>>,
+    workflow_directory: &str,
+    spool_directory: &str,
+) -> Result<()> {
+    let mut unused: Vec<bool> = vec![true; previous.len()];
+    let mut reused = vec![];
+    for exporter in &config.exporters {
+        let index = previous
+            .iter()
+            .zip(&unused)
+            .position(|(previous, unused)| *unused && previous == exporter);
+        if let Some(index) = index {
+            unused[index] = false;
+        }
+        reused.push(index);
+    }
+
+    let mut built = vec![];
+    for (exporter, index) in config.exporters.iter().zip(&reused) {
+        if index.is_none() {
+            built.push(build_exporter(
+                exporter,
+                config,
+                workflow_directory,
+                spool_directory,
+            )?);
+        }
+    }
+
+    let mut previous_exporters: Vec<Option<Box<dyn EventExporter>>> =
+        exporters.drain(..).map(Some).collect();
+    let mut built = built.into_iter();
+    for index in reused {
+        let exporter = match index {
+            Some(index) => {
+                let mut exporter = previous_exporters[index].take().unwrap();
+                exporter.reconfigure(config);
+                exporter
+            }
+            None => built.next().unwrap(),
+        };
+        exporters.push(exporter);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_manager::ConfigManager;
+
+    #[test]
+    fn test_exporter_config_from_toml() {
+        #[derive(Deserialize)]
+        struct Exporters {
+            exporters: Vec<ExporterConfig>,
+        }
+
+        let parsed: Exporters = toml::from_str(
+            r#"
+            [[exporters]]
+            type = "http"
+
+            [[exporters]]
+            type = "file"
+            path = "/data/run/events.jsonl"
+
+            [[exporters]]
+            type = "stdout"
+
+            [[exporters]]
+            type = "otlp"
+            endpoint = "http://localhost:4318"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            parsed.exporters,
+            vec![
+                ExporterConfig::Http,
+                ExporterConfig::File {
+                    path: Some("/data/run/events.jsonl".to_string())
+                },
+                ExporterConfig::Stdout,
+                ExporterConfig::Otlp {
+                    endpoint: "http://localhost:4318".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_exporters() -> Result<()> {
+        let spool_directory = tempfile::tempdir()?;
+        let mut config = ConfigManager::load_default_config();
+        config.exporters = vec![
+            ExporterConfig::Stdout,
+            ExporterConfig::File { path: None },
+            ExporterConfig::Http,
+            ExporterConfig::Otlp {
+                endpoint: "http://localhost:4318".to_string(),
+            },
+        ];
+
+        let exporters = build_exporters(&config, "/tmp", spool_directory.path().to_str().unwrap())?;
+
+        let names: Vec<&str> = exporters.iter().map(|e| e.name()).collect();
+        assert_eq!(names, vec!["stdout", "file", "http", "otlp"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_exporters_keeps_unchanged_exporters() -> Result<()> {
+        let spool_directory = tempfile::tempdir()?;
+        let spool_directory = spool_directory.path().to_str().unwrap();
+        let mut config = ConfigManager::load_default_config();
+        config.exporters = vec![ExporterConfig::Http, ExporterConfig::Stdout];
+        let mut exporters = build_exporters(&config, "/tmp", spool_directory)?;
+        let http: *const dyn EventExporter = &*exporters[0];
+
+        let previous = std::mem::take(&mut config.exporters);
+        config.exporters = vec![
+            ExporterConfig::Otlp {
+                endpoint: "http://localhost:4318".to_string(),
+            },
+            ExporterConfig::Http,
+        ];
+        rebuild_exporters(&config, &previous, &mut exporters, "/tmp", spool_directory)?;
+
+        let names: Vec<&str> = exporters.iter().map(|e| e.name()).collect();
+        assert_eq!(names, vec!["otlp", "http"]);
+        assert!(std::ptr::addr_eq(&*exporters[1], http));
+
+        Ok(())
+    }
+}
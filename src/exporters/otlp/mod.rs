@@ -0,0 +1,695 @@
+mod payload;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use sysinfo::Pid;
+
+use super::{EventExporter, ExportContext, ExportOutput};
+use crate::event_attributes::EventAttributes;
+use crate::event_recorder::{Event, EventType};
+use crate::http_client::send_http_post;
+use crate::process_watcher::ProcessTreeNode;
+use payload::{
+    gauge, gauge_data_point, key_value, key_values, metrics_request, span, traces_request, SpanData,
+};
+
+const TRACE_ID_LENGTH: usize = 32;
+const SPAN_ID_LENGTH: usize = 16;
+const ID_CHARSET: &str = "0123456789abcdef";
+// Completed spans are kept while the collector is unreachable, up to this many
+const MAX_PENDING_SPANS: usize = 10_000;
+const MAX_ANCESTOR_DEPTH: usize = 64;
+
+const SYSTEM_GAUGES: &[(&str, &str, &str)] = &[
+    ("system_memory_total", "system.memory.total", "By"),
+    ("system_memory_used", "system.memory.used", "By"),
+    ("system_memory_available", "system.memory.available", "By"),
+    (
+        "system_memory_utilization",
+        "system.memory.utilization",
+        "%",
+    ),
+    ("system_memory_swap_total", "system.swap.total", "By"),
+    ("system_memory_swap_used", "system.swap.used", "By"),
+    ("system_cpu_utilization", "system.cpu.utilization", "%"),
+];
+
+const DISK_GAUGES: &[(&str, &str, &str)] = &[
+    ("disk_total_space", "system.disk.total", "By"),
+    ("disk_used_space", "system.disk.used", "By"),
+    ("disk_available_space", "system.disk.available", "By"),
+    ("disk_utilization", "system.disk.utilization", "%"),
+];
+
+const TOOL_GAUGES: &[(&str, &str, &str)] = &[
+    ("process_cpu_utilization", "process.cpu.utilization", "%"),
+    ("process_memory_usage", "process.memory.usage", "By"),
+    ("process_memory_virtual", "process.memory.virtual", "By"),
+    (
+        "process_disk_usage_read_total",
+        "process.disk.read.total",
+        "By",
+    ),
+    (
+        "process_disk_usage_write_total",
+        "process.disk.write.total",
+        "By",
+    ),
+    ("process_run_time", "process.run_time", "s"),
+];
+
+fn generate_id(length: usize) -> String {
+    random_string::generate(length, ID_CHARSET)
+}
+
+/// The trace a run's tools are reported under, with the run itself as root span.
+struct RunTrace {
+    run_id: Option<String>,
+    run_name: Option<String>,
+    trace_id: String,
+    root_span_id: Option<String>,
+    start: DateTime<Utc>,
+    last_activity: DateTime<Utc>,
+}
+
+struct OpenSpan {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: String,
+    start: DateTime<Utc>,
+    attributes: Map<String, Value>,
+}
+
+/// Exports tool executions as spans and metric events as gauges to an OpenTelemetry
+/// collector over OTLP/HTTP JSON.
+pub struct OtlpExporter {
+    endpoint: String,
+    trace: Option<RunTrace>,
+    open_spans: HashMap<String, OpenSpan>,
+    // Span ids of the current run's tools by pid, used to find a tool's parent span
+    tool_spans: HashMap<String, String>,
+    pending_spans: VecDeque<Value>,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: &str) -> Self {
+        OtlpExporter {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            trace: None,
+            open_spans: HashMap::new(),
+            tool_spans: HashMap::new(),
+            pending_spans: VecDeque::new(),
+        }
+    }
+
+    fn sync_run(&mut self, context: &ExportContext) {
+        let run_id = context.run.map(|run| run.id.clone());
+
+        if matches!(&self.trace, Some(trace) if trace.run_id == run_id) {
+            return;
+        }
+
+        if let Some(trace) = self.trace.take() {
+            self.finish_run_trace(trace);
+        }
+
+        self.trace = Some(RunTrace {
+            run_id,
+            run_name: context.run.map(|run| run.name.clone()),
+            trace_id: generate_id(TRACE_ID_LENGTH),
+            root_span_id: context.run.map(|_| generate_id(SPAN_ID_LENGTH)),
+            start: context
+                .run
+                .map(|run| run.start_time)
+                .unwrap_or_else(Utc::now),
+            last_activity: Utc::now(),
+        });
+    }
+
+    /// Ends the tools still running in the trace when it was left, and the run's root span.
+    fn finish_run_trace(&mut self, trace: RunTrace) {
+        self.tool_spans.clear();
+        let unfinished = Map::from_iter([("tracer.tool.unfinished".to_string(), json!(true))]);
+        for (_, open_span) in std::mem::take(&mut self.open_spans) {
+            let end = trace.last_activity.max(open_span.start);
+            self.finish_tool_span(open_span, end, &unfinished);
+        }
+
+        let Some(root_span_id) = &trace.root_span_id else {
+            return;
+        };
+
+        let mut attributes = Map::new();
+        attributes.insert("tracer.run.id".to_string(), json!(trace.run_id));
+        attributes.insert("tracer.run.name".to_string(), json!(trace.run_name));
+
+        let name = trace.run_name.as_deref().unwrap_or("run");
+        self.push_span(span(SpanData {
+            trace_id: &trace.trace_id,
+            span_id: root_span_id,
+            parent_span_id: None,
+            name,
+            start: trace.start,
+            end: trace.last_activity,
+            attributes: key_values(&attributes),
+        }));
+    }
+
+    fn push_span(&mut self, span: Value) {
+        if self.pending_spans.len() >= MAX_PENDING_SPANS {
+            self.pending_spans.pop_front();
+        }
+        self.pending_spans.push_back(span);
+    }
+
+    /// Walks up the process tree from `parent_pid` until it reaches a tool of this run,
+    /// falling back to the run's root span.
+    fn find_parent_span(
+        &self,
+        parent_pid: Option<String>,
+        process_tree: &HashMap<Pid, ProcessTreeNode>,
+    ) -> Option<String> {
+        let mut candidate = parent_pid;
+
+        for _ in 0..MAX_ANCESTOR_DEPTH {
+            let Some(pid) = candidate.filter(|pid| !pid.is_empty()) else {
+                break;
+            };
+
+            if let Some(span_id) = self.tool_spans.get(&pid) {
+                return Some(span_id.clone());
+            }
+
+            candidate = Pid::from_str(&pid)
+                .ok()
+                .and_then(|pid| process_tree.get(&pid))
+                .and_then(|node| node.parent_id)
+                .map(|pid| pid.to_string());
+        }
+
+        self.trace.as_ref()?.root_span_id.clone()
+    }
+
+    fn start_tool_span(&mut self, event: &Event, context: &ExportContext) {
+        let attributes = attributes_map(event);
+        let pid = string_attribute(&attributes, "tool_pid").unwrap_or_default();
+        let parent_pid = string_attribute(&attributes, "tool_parent_pid");
+        let name = string_attribute(&attributes, "tool_name").unwrap_or_else(|| "tool".into());
+
+        let trace = self.trace.as_ref().unwrap();
+        let mut open_span = OpenSpan {
+            trace_id: trace.trace_id.clone(),
+            span_id: generate_id(SPAN_ID_LENGTH),
+            parent_span_id: self.find_parent_span(parent_pid, context.process_tree),
+            name,
+            start: event.timestamp,
+            attributes,
+        };
+
+        if let Some(run_id) = &trace.run_id {
+            open_span
+                .attributes
+                .insert("tracer.run.id".to_string(), json!(run_id));
+        }
+
+        if pid.is_empty() {
+            // Short-lived processes are reported once, without a matching exit
+            self.finish_tool_span(open_span, event.timestamp, &Map::new());
+            return;
+        }
+
+        self.tool_spans
+            .insert(pid.clone(), open_span.span_id.clone());
+        self.open_spans.insert(pid, open_span);
+    }
+
+    fn finish_tool_span(
+        &mut self,
+        mut open_span: OpenSpan,
+        end: DateTime<Utc>,
+        finished_attributes: &Map<String, Value>,
+    ) {
+        for (key, value) in finished_attributes {
+            open_span.attributes.insert(key.clone(), value.clone());
+        }
+
+        let span = span(SpanData {
+            trace_id: &open_span.trace_id,
+            span_id: &open_span.span_id,
+            parent_span_id: open_span.parent_span_id.as_deref(),
+            name: &open_span.name,
+            start: open_span.start,
+            end,
+            attributes: key_values(&open_span.attributes),
+        });
+        self.push_span(span);
+    }
+
+    fn record_spans(&mut self, events: &[Event], context: &ExportContext) {
+        for event in events {
+            if let Some(trace) = self.trace.as_mut() {
+                trace.last_activity = trace.last_activity.max(event.timestamp);
+            }
+
+            if event.process_status == EventType::ToolExecution.as_str() {
+                self.start_tool_span(event, context);
+            } else if event.process_status == EventType::FinishedToolExecution.as_str() {
+                let attributes = attributes_map(event);
+                let pid = string_attribute(&attributes, "tool_pid").unwrap_or_default();
+                if let Some(open_span) = self.open_spans.remove(&pid) {
+                    self.finish_tool_span(open_span, event.timestamp, &attributes);
+                }
+            }
+        }
+    }
+
+    fn collect_metrics(&self, events: &[Event]) -> Vec<Value> {
+        let mut metrics: BTreeMap<&str, (&str, Vec<Value>)> = BTreeMap::new();
+        let mut add_gauges = |gauges: &[(&'static str, &'static str, &'static str)],
+                              values: &Map<String, Value>,
+                              time: DateTime<Utc>,
+                              point_attributes: &Vec<Value>| {
+            for (attribute, name, unit) in gauges {
+                let Some(value) = values.get(*attribute) else {
+                    continue;
+                };
+                if let Some(point) = gauge_data_point(value, time, point_attributes.clone()) {
+                    metrics.entry(name).or_insert((unit, vec![])).1.push(point);
+                }
+            }
+        };
+
+        let trace_run_id = self.trace.as_ref().and_then(|trace| trace.run_id.as_ref());
+
+        for event in events {
+            let attributes = attributes_map(event);
+            // Events recorded before a run switch belong to the run they were recorded in
+            let run_attributes: Vec<Value> = event
+                .run_id
+                .as_ref()
+                .or(trace_run_id)
+                .map(|run_id| vec![key_value("tracer.run.id", &json!(run_id))])
+                .unwrap_or_default();
+
+            if event.process_status == EventType::MetricEvent.as_str() {
+                add_gauges(SYSTEM_GAUGES, &attributes, event.timestamp, &run_attributes);
+
+                if let Some(Value::Object(disks)) = attributes.get("system_disk_io") {
+                    for (disk, disk_values) in disks {
+                        let Value::Object(disk_values) = disk_values else {
+                            continue;
+                        };
+                        let mut point_attributes = run_attributes.clone();
+                        point_attributes.push(key_value("system.device", &json!(disk)));
+                        add_gauges(DISK_GAUGES, disk_values, event.timestamp, &point_attributes);
+                    }
+                }
+            } else if event.process_status == EventType::ToolMetricEvent.as_str() {
+                let mut point_attributes = run_attributes.clone();
+                for (attribute, key) in [("tool_name", "tool.name"), ("tool_pid", "tool.pid")] {
+                    if let Some(value) = attributes.get(attribute) {
+                        point_attributes.push(key_value(key, value));
+                    }
+                }
+                add_gauges(TOOL_GAUGES, &attributes, event.timestamp, &point_attributes);
+            }
+        }
+
+        metrics
+            .into_iter()
+            .map(|(name, (unit, points))| gauge(name, unit, points))
+            .collect()
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<()> {
+        let url = format!("{}{}", self.endpoint, path);
+        let (status, response_text) = send_http_post(&url, body).await?;
+
+        if !(200..300).contains(&status) {
+            bail!(
+                "OTLP collector rejected {}: {} - {}",
+                url,
+                status,
+                response_text
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn attributes_map(event: &Event) -> Map<String, Value> {
+    match event.attributes.as_ref().map(EventAttributes::to_value) {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    }
+}
+
+fn string_attribute(attributes: &Map<String, Value>, key: &str) -> Option<String> {
+    match attributes.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+impl EventExporter for OtlpExporter {
+    fn name(&self) -> &'static str {
+        "otlp"
+    }
+
+    fn export<'a>(
+        &'a mut self,
+        events: &'a [Event],
+        context: &'a ExportContext<'a>,
+    ) -> ExportOutput<'a> {
+        Box::pin(async move {
+            self.sync_run(context);
+            self.record_spans(events, context);
+
+            let metrics = self.collect_metrics(events);
+            let metrics_result = if metrics.is_empty() {
+                Ok(())
+            } else {
+                self.post("/v1/metrics", &metrics_request(&metrics)).await
+            };
+
+            self.flush().await?;
+            metrics_result
+        })
+    }
+
+    fn flush(&mut self) -> ExportOutput<'_> {
+        Box::pin(async move {
+            if self.pending_spans.is_empty() {
+                return Ok(());
+            }
+
+            let request = traces_request(self.pending_spans.make_contiguous());
+            self.post("/v1/traces", &request).await?;
+            self.pending_spans.clear();
+            Ok(())
+        })
+    }
+
+    fn end_run(&mut self) {
+        if let Some(trace) = self.trace.take() {
+            self.finish_run_trace(trace);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_attributes::FinishedToolAttributes;
+    use crate::event_recorder::EventRecorder;
+    use crate::process_watcher::ProcessProperties;
+    use crate::tracer_client::RunMetadata;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    /// Minimal stand-in for an OTLP collector, forwarding (path, body) of every request.
+    async fn start_collector() -> (String, UnboundedReceiver<(String, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let mut buffer = vec![];
+                    let mut chunk = [0; 4096];
+                    loop {
+                        let n = stream.read(&mut chunk).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        buffer.extend_from_slice(&chunk[..n]);
+
+                        let Some(header_end) =
+                            buffer.windows(4).position(|window| window == b"\r\n\r\n")
+                        else {
+                            continue;
+                        };
+                        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+                        let content_length = head
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+                        if buffer.len() < header_end + 4 + content_length {
+                            continue;
+                        }
+
+                        let path = head.split_whitespace().nth(1).unwrap().to_string();
+                        let body = &buffer[header_end + 4..header_end + 4 + content_length];
+                        sender
+                            .send((path, serde_json::from_slice(body).unwrap()))
+                            .unwrap();
+                        buffer.drain(..header_end + 4 + content_length);
+
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                            .await
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        (endpoint, receiver)
+    }
+
+    fn test_run() -> RunMetadata {
+        RunMetadata {
+            last_interaction: Instant::now(),
+            name: "otlp-test-run".to_string(),
+            id: "run-1".to_string(),
+            service_name: "test".to_string(),
+            parent_pid: None,
+            start_time: Utc::now(),
+            details: Default::default(),
+        }
+    }
+
+    fn tool_properties(name: &str, pid: u32, parent_pid: u32) -> Value {
+        json!({
+            "tool_name": name,
+            "tool_pid": pid.to_string(),
+            "tool_parent_pid": parent_pid.to_string(),
+            "tool_cmd": name,
+            "process_memory_usage": 2048,
+            "process_cpu_utilization": 12.5,
+        })
+    }
+
+    fn spans_by_name(body: &Value) -> HashMap<String, Value> {
+        body["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| (span["name"].as_str().unwrap().to_string(), span.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_tools_are_exported_as_nested_spans() -> Result<()> {
+        let (endpoint, mut requests) = start_collector().await;
+        let mut exporter = OtlpExporter::new(&endpoint);
+        let run = test_run();
+        let process_tree = HashMap::new();
+
+        // bwa (pid 11) runs samtools (pid 12) in a subshell (pid 13)
+        let mut logs = EventRecorder::new();
+        logs.record_event(
+            EventType::ToolExecution,
+            "bwa".to_string(),
+            Some(EventAttributes::Other(tool_properties("bwa", 11, 10))),
+            None,
+        );
+        logs.record_event(
+            EventType::ToolExecution,
+            "samtools".to_string(),
+            Some(EventAttributes::Other(tool_properties("samtools", 12, 13))),
+            None,
+        );
+        logs.record_event(
+            EventType::FinishedToolExecution,
+            "samtools exited".to_string(),
+            Some(EventAttributes::FinishedTool(FinishedToolAttributes {
+                tool_name: "samtools".to_string(),
+                tool_pid: "12".to_string(),
+                duration: 5,
+            })),
+            None,
+        );
+        logs.record_event(
+            EventType::ToolMetricEvent,
+            "bwa metrics".to_string(),
+            Some(EventAttributes::Other(tool_properties("bwa", 11, 10))),
+            None,
+        );
+
+        let mut process_tree_with_subshell = process_tree.clone();
+        let subshell = ProcessTreeNode {
+            properties: ProcessProperties {
+                tool_name: "bash".to_string(),
+                tool_pid: "13".to_string(),
+                tool_parent_pid: "11".to_string(),
+                ..Default::default()
+            },
+            children: vec![],
+            parent_id: Some(Pid::from(11)),
+            start_time: Utc::now(),
+        };
+        process_tree_with_subshell.insert(Pid::from(13), subshell);
+
+        let context = ExportContext {
+            run: Some(&run),
+            process_tree: &process_tree_with_subshell,
+        };
+        exporter.export(logs.get_events(), &context).await?;
+
+        let (path, metrics) = requests.recv().await.unwrap();
+        assert_eq!(path, "/v1/metrics");
+        let metric_names: Vec<&str> = metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|metric| metric["name"].as_str().unwrap())
+            .collect();
+        assert!(metric_names.contains(&"process.memory.usage"));
+        assert!(metric_names.contains(&"process.cpu.utilization"));
+
+        let (path, traces) = requests.recv().await.unwrap();
+        assert_eq!(path, "/v1/traces");
+        let spans = spans_by_name(&traces);
+        assert_eq!(spans.len(), 1);
+        let samtools = &spans["samtools"];
+
+        // Finishing bwa and ending the run emits the remaining spans
+        logs.clear();
+        logs.record_event(
+            EventType::FinishedToolExecution,
+            "bwa exited".to_string(),
+            Some(EventAttributes::FinishedTool(FinishedToolAttributes {
+                tool_name: "bwa".to_string(),
+                tool_pid: "11".to_string(),
+                duration: 10,
+            })),
+            None,
+        );
+        exporter
+            .export(
+                logs.get_events(),
+                &ExportContext {
+                    run: Some(&run),
+                    process_tree: &process_tree,
+                },
+            )
+            .await?;
+        let (_, traces) = requests.recv().await.unwrap();
+        let bwa = spans_by_name(&traces)["bwa"].clone();
+
+        exporter
+            .export(
+                &[],
+                &ExportContext {
+                    run: None,
+                    process_tree: &process_tree,
+                },
+            )
+            .await?;
+        let (_, traces) = requests.recv().await.unwrap();
+        let root = spans_by_name(&traces)["otlp-test-run"].clone();
+
+        assert_eq!(root["parentSpanId"], "");
+        assert_eq!(bwa["parentSpanId"], root["spanId"]);
+        assert_eq!(samtools["parentSpanId"], bwa["spanId"]);
+        assert_eq!(samtools["traceId"], root["traceId"]);
+        assert_eq!(bwa["traceId"], root["traceId"]);
+        assert_eq!(root["traceId"].as_str().unwrap().len(), TRACE_ID_LENGTH);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spans_are_kept_while_collector_is_unreachable() -> Result<()> {
+        let mut exporter = OtlpExporter::new("http://127.0.0.1:1");
+        let mut logs = EventRecorder::new();
+        // Short-lived processes have no pid and are reported as finished right away
+        let mut properties = tool_properties("fastqc", 0, 0);
+        properties["tool_pid"] = json!("");
+        logs.record_event(
+            EventType::ToolExecution,
+            "short lived".to_string(),
+            Some(EventAttributes::Other(properties)),
+            None,
+        );
+
+        let process_tree = HashMap::new();
+        let context = ExportContext {
+            run: None,
+            process_tree: &process_tree,
+        };
+
+        assert!(exporter.export(logs.get_events(), &context).await.is_err());
+        assert_eq!(exporter.pending_spans.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ending_a_run_closes_its_spans() -> Result<()> {
+        let (endpoint, mut requests) = start_collector().await;
+        let mut exporter = OtlpExporter::new(&endpoint);
+        let run = test_run();
+        let process_tree = HashMap::new();
+
+        let mut logs = EventRecorder::new();
+        logs.record_event(
+            EventType::ToolExecution,
+            "bwa".to_string(),
+            Some(EventAttributes::Other(tool_properties("bwa", 11, 10))),
+            None,
+        );
+        let context = ExportContext {
+            run: Some(&run),
+            process_tree: &process_tree,
+        };
+        exporter.export(logs.get_events(), &context).await?;
+        assert_eq!(exporter.open_spans.len(), 1);
+
+        exporter.end_run();
+        assert!(exporter.open_spans.is_empty());
+        assert!(exporter.tool_spans.is_empty());
+
+        exporter.flush().await?;
+        let (path, traces) = requests.recv().await.unwrap();
+        assert_eq!(path, "/v1/traces");
+        let spans = spans_by_name(&traces);
+        let root = &spans["otlp-test-run"];
+        let bwa = &spans["bwa"];
+        assert_eq!(bwa["parentSpanId"], root["spanId"]);
+        assert!(bwa["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|attribute| attribute["key"] == "tracer.tool.unfinished"));
+
+        Ok(())
+    }
+}
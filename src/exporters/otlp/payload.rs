@@ -0,0 +1,140 @@
+// OTLP/HTTP JSON encoding, see https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use sysinfo::System;
+
+const SCOPE_NAME: &str = "tracer";
+const SERVICE_NAME: &str = "tracer";
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+pub fn unix_nanos(time: DateTime<Utc>) -> String {
+    time.timestamp_nanos_opt().unwrap_or_default().to_string()
+}
+
+pub fn any_value(value: &Value) -> Value {
+    match value {
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_f64() => json!({ "doubleValue": n.as_f64() }),
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+pub fn key_value(key: &str, value: &Value) -> Value {
+    json!({ "key": key, "value": any_value(value) })
+}
+
+pub fn key_values(attributes: &Map<String, Value>) -> Vec<Value> {
+    attributes
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| key_value(key, value))
+        .collect()
+}
+
+fn resource() -> Value {
+    let mut attributes = vec![key_value("service.name", &json!(SERVICE_NAME))];
+    if let Some(host_name) = System::host_name() {
+        attributes.push(key_value("host.name", &json!(host_name)));
+    }
+    json!({ "attributes": attributes })
+}
+
+fn scope() -> Value {
+    json!({ "name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION") })
+}
+
+pub struct SpanData<'a> {
+    pub trace_id: &'a str,
+    pub span_id: &'a str,
+    pub parent_span_id: Option<&'a str>,
+    pub name: &'a str,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub attributes: Vec<Value>,
+}
+
+pub fn span(data: SpanData) -> Value {
+    json!({
+        "traceId": data.trace_id,
+        "spanId": data.span_id,
+        "parentSpanId": data.parent_span_id.unwrap_or(""),
+        "name": data.name,
+        "kind": SPAN_KIND_INTERNAL,
+        "startTimeUnixNano": unix_nanos(data.start),
+        "endTimeUnixNano": unix_nanos(data.end.max(data.start)),
+        "attributes": data.attributes,
+    })
+}
+
+pub fn traces_request(spans: &[Value]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{ "scope": scope(), "spans": spans }],
+        }]
+    })
+}
+
+pub fn gauge_data_point(
+    value: &Value,
+    time: DateTime<Utc>,
+    attributes: Vec<Value>,
+) -> Option<Value> {
+    let mut point = json!({ "timeUnixNano": unix_nanos(time), "attributes": attributes });
+
+    if let Some(int) = value.as_u64() {
+        point["asInt"] = json!(int.to_string());
+    } else if let Some(double) = value.as_f64() {
+        point["asDouble"] = json!(double);
+    } else {
+        return None;
+    }
+
+    Some(point)
+}
+
+pub fn gauge(name: &str, unit: &str, data_points: Vec<Value>) -> Value {
+    json!({ "name": name, "unit": unit, "gauge": { "dataPoints": data_points } })
+}
+
+pub fn metrics_request(metrics: &[Value]) -> Value {
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{ "scope": scope(), "metrics": metrics }],
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_any_value() {
+        assert_eq!(any_value(&json!("bwa")), json!({ "stringValue": "bwa" }));
+        assert_eq!(any_value(&json!(42)), json!({ "intValue": "42" }));
+        assert_eq!(any_value(&json!(0.5)), json!({ "doubleValue": 0.5 }));
+        assert_eq!(any_value(&json!(true)), json!({ "boolValue": true }));
+        assert_eq!(
+            any_value(&json!(["a.fq"])),
+            json!({ "stringValue": "[\"a.fq\"]" })
+        );
+    }
+
+    #[test]
+    fn test_gauge_data_point() {
+        let time = DateTime::from_timestamp(1, 0).unwrap();
+
+        let int = gauge_data_point(&json!(1024), time, vec![]).unwrap();
+        assert_eq!(int["asInt"], "1024");
+        assert_eq!(int["timeUnixNano"], "1000000000");
+
+        let double = gauge_data_point(&json!(12.5), time, vec![]).unwrap();
+        assert_eq!(double["asDouble"], 12.5);
+
+        assert!(gauge_data_point(&json!("n/a"), time, vec![]).is_none());
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::Write;
+
+use super::{EventExporter, ExportContext, ExportOutput};
+use crate::event_recorder::Event;
+
+/// Prints every event as one JSON object per line.
+pub struct StdoutExporter;
+
+impl StdoutExporter {
+    pub fn new() -> Self {
+        StdoutExporter
+    }
+}
+
+impl EventExporter for StdoutExporter {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn export<'a>(
+        &'a mut self,
+        events: &'a [Event],
+        _context: &'a ExportContext<'a>,
+    ) -> ExportOutput<'a> {
+        Box::pin(async move {
+            let mut stdout = std::io::stdout().lock();
+            for event in events {
+                serde_json::to_writer(&mut stdout, event)?;
+                stdout.write_all(b"\n")?;
+            }
+            stdout.flush()?;
+            Ok(())
+        })
+    }
+}
@@ -8,11 +8,12 @@ use lazy_static::lazy_static;
 use predicates::prelude::predicate;
 use predicates::str::RegexPredicate;
 use predicates::Predicate;
+use serde::{Deserialize, Serialize};
 
 use crate::debug_log::Logger;
 use crate::upload::upload_from_file_path;
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WatchedFileInfo {
     pub path: String,
     pub size: u64,
@@ -41,7 +42,7 @@ pub enum FilePattern {
     PathMatch(RegexPredicate),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum FileAction {
     None,
     Upload,
@@ -238,9 +239,36 @@ impl FileWatcher {
         Ok(())
     }
 
+    /// Files watched by a previous daemon, whose cached copies are kept.
+    pub fn restore_watched_files(&mut self, files: Vec<WatchedFileInfo>) {
+        self.watched_files = files
+            .into_iter()
+            .map(|file| (file.path.clone(), file))
+            .collect();
+    }
+
+    pub fn watched_files(&self) -> Vec<WatchedFileInfo> {
+        self.watched_files.values().cloned().collect()
+    }
+
+    /// Watched files to upload whose latest version wasn't uploaded yet, e.g. because they
+    /// are still being written or their upload failed.
+    pub fn pending_upload_count(&self) -> usize {
+        self.watched_files
+            .values()
+            .filter(|file| {
+                matches!(file.action, FileAction::Upload)
+                    && file
+                        .last_upload
+                        .is_none_or(|last_upload| last_upload < file.last_update)
+            })
+            .count()
+    }
+
+    /// Creates the cache directory, emptying it unless watched files were restored.
     pub fn prepare_cache_directory(&self, file_cache_dir: &str) -> Result<()> {
         let path = Path::new(file_cache_dir);
-        if path.exists() {
+        if path.exists() && self.watched_files.is_empty() {
             fs::remove_dir_all(path)?;
         }
         fs::create_dir_all(path)?;
@@ -273,7 +301,8 @@ impl FileWatcher {
 
     pub fn get_file_by_path_suffix(&self, path_suffix: &str) -> Option<(&String, &FileInfo)> {
         let path = self.all_files.keys().find(|path| {
-            path.ends_with(path_suffix) && path_suffix.contains(path.split('/').last().unwrap())
+            path.ends_with(path_suffix)
+                && path_suffix.contains(path.split('/').next_back().unwrap())
         });
 
         if let Some(path) = path {
@@ -282,6 +311,7 @@ impl FileWatcher {
         None
     }
 
+    /// Uploads the new and changed files of the workflow directory, returning their paths.
     pub async fn poll_files(
         &mut self,
         service_url: &str,
@@ -289,7 +319,7 @@ impl FileWatcher {
         workflow_directory: &str,
         file_cache_dir: &str,
         new_size_duration: TimeDelta,
-    ) -> Result<()> {
+    ) -> Result<Vec<String>> {
         let logger = Logger::new();
         let mut to_upload: Vec<WatchedFileInfo> = Vec::new();
         let workflow_path = Path::new(workflow_directory);
@@ -300,7 +330,7 @@ impl FileWatcher {
                     None,
                 )
                 .await;
-            return Ok(());
+            return Ok(Vec::new());
         }
 
         let mut found_files = HashMap::new();
@@ -348,8 +378,10 @@ impl FileWatcher {
             }
         }
 
+        let mut uploaded = Vec::new();
         for file_info in to_upload {
             self.upload_file(service_url, api_key, &file_info).await?;
+            uploaded.push(file_info.path);
         }
 
         for file_info in watched_files.values_mut() {
@@ -370,7 +402,7 @@ impl FileWatcher {
         self.watched_files = watched_files;
         self.all_files = found_files;
 
-        Ok(())
+        Ok(uploaded)
     }
 }
 
@@ -387,8 +419,8 @@ mod tests {
         let old_file_info = WatchedFileInfo {
             path: "/tmp/test.txt".to_string(),
             size: 50,
-            last_update: now.clone(),
-            last_upload: Some(now.clone()),
+            last_update: now,
+            last_upload: Some(now),
             cached_path: None,
             action: FileAction::None,
         };
@@ -396,8 +428,8 @@ mod tests {
         let new_file_info = WatchedFileInfo {
             path: "/tmp/test.txt".to_string(),
             size: 50,
-            last_update: now.clone(),
-            last_upload: Some(now.clone()),
+            last_update: now,
+            last_upload: Some(now),
             cached_path: None,
             action: FileAction::None,
         };
@@ -412,8 +444,8 @@ mod tests {
         let old_file_info = WatchedFileInfo {
             path: "/tmp/test.txt".to_string(),
             size: 50,
-            last_update: now.clone(),
-            last_upload: Some(now.clone()),
+            last_update: now,
+            last_upload: Some(now),
             cached_path: None,
             action: FileAction::None,
         };
@@ -422,12 +454,36 @@ mod tests {
         let new_file_info = WatchedFileInfo {
             path: "/tmp/test.txt".to_string(),
             size: 50,
-            last_update: newer.clone(),
-            last_upload: Some(now.clone()),
+            last_update: newer,
+            last_upload: Some(now),
             cached_path: None,
             action: FileAction::None,
         };
 
         assert!(file_watcher.check_if_file_to_update(Some(&old_file_info), Some(&new_file_info)));
     }
+
+    #[test]
+    fn test_pending_upload_count() {
+        let now: DateTime<Utc> = Utc::now();
+        let earlier = now.checked_sub_days(Days::new(1)).unwrap();
+        let mut file_watcher = FileWatcher::new();
+        let watched_file = |path: &str, last_upload, action| WatchedFileInfo {
+            path: path.to_string(),
+            size: 50,
+            last_update: now,
+            last_upload,
+            cached_path: None,
+            action,
+        };
+
+        file_watcher.restore_watched_files(vec![
+            watched_file("/tmp/new.txt", None, FileAction::Upload),
+            watched_file("/tmp/changed.txt", Some(earlier), FileAction::Upload),
+            watched_file("/tmp/uploaded.txt", Some(now), FileAction::Upload),
+            watched_file("/tmp/ignored.txt", None, FileAction::None),
+        ]);
+
+        assert_eq!(file_watcher.pending_upload_count(), 2);
+    }
 }
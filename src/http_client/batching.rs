@@ -0,0 +1,107 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Splits events into batches of at most `max_events` events whose JSON array stays under
+/// `max_bytes`. An event larger than `max_bytes` on its own is sent in a batch by itself.
+pub fn split_batches(events: Vec<Value>, max_bytes: usize, max_events: usize) -> Vec<Vec<Value>> {
+    let max_events = max_events.max(1);
+    let mut batches = vec![];
+    let mut batch = vec![];
+    // Size of the serialized array: brackets plus the events and the commas between them
+    let mut batch_bytes = 2;
+
+    for event in events {
+        let event_bytes = serde_json::to_vec(&event).map(|v| v.len()).unwrap_or(0);
+        let separator_bytes = usize::from(!batch.is_empty());
+
+        if !batch.is_empty()
+            && (batch.len() >= max_events
+                || batch_bytes + separator_bytes + event_bytes > max_bytes)
+        {
+            batches.push(std::mem::take(&mut batch));
+            batch_bytes = 2;
+        }
+
+        batch_bytes += usize::from(!batch.is_empty()) + event_bytes;
+        batch.push(event);
+    }
+
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+/// Same as `split_batches`, for events that are not serialized yet.
+pub fn split_event_slices<T: Serialize>(
+    events: &[T],
+    max_bytes: usize,
+    max_events: usize,
+) -> Result<Vec<&[T]>> {
+    let values = events
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<serde_json::Result<Vec<_>>>()?;
+
+    let mut rest = events;
+    let mut slices = vec![];
+    for batch in split_batches(values, max_bytes, max_events) {
+        let (slice, remaining) = rest.split_at(batch.len());
+        slices.push(slice);
+        rest = remaining;
+    }
+
+    Ok(slices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn events(count: usize) -> Vec<Value> {
+        (0..count).map(|i| json!({ "id": i })).collect()
+    }
+
+    #[test]
+    fn test_split_by_event_count() {
+        let batches = split_batches(events(5), usize::MAX, 2);
+        let sizes: Vec<usize> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(batches[2][0], json!({ "id": 4 }));
+    }
+
+    #[test]
+    fn test_split_by_bytes() {
+        // Every event serializes to 8 bytes, so "[e,e]" is 19 bytes and "[e,e,e]" is 28
+        let batches = split_batches(events(5), 19, 100);
+        let sizes: Vec<usize> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+
+        for batch in batches {
+            assert!(serde_json::to_vec(&batch).unwrap().len() <= 19);
+        }
+    }
+
+    #[test]
+    fn test_oversized_event_goes_alone() {
+        let big = json!({ "message": "x".repeat(100) });
+        let batches = split_batches(vec![json!(1), big.clone(), json!(2)], 50, 100);
+        assert_eq!(batches, vec![vec![json!(1)], vec![big], vec![json!(2)]]);
+    }
+
+    #[test]
+    fn test_split_event_slices() -> Result<()> {
+        let events: Vec<u32> = (0..5).collect();
+        let slices = split_event_slices(&events, usize::MAX, 2)?;
+        assert_eq!(slices, vec![&events[0..2], &events[2..4], &events[4..5]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert!(split_batches(vec![], 10, 10).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct CircuitOpenError {
+    pub retry_in: Duration,
+}
+
+impl std::fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Service marked as unreachable, skipping request for another {} ms",
+            self.retry_in.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// Stops calling the service after `failure_threshold` consecutive failures and lets a
+/// single probe through once `reset_timeout` has passed. Other callers are turned away until
+/// the probe succeeds or fails, or another `reset_timeout` passed without an answer.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            reset_timeout,
+            consecutive_failures: 0,
+            open_until: None,
+            probe_started: None,
+        }
+    }
+
+    pub fn reconfigure(&mut self, failure_threshold: u32, reset_timeout: Duration) {
+        self.failure_threshold = failure_threshold;
+        self.reset_timeout = reset_timeout;
+    }
+
+    /// Lets the request through, counting it as the probe once the breaker is half-open.
+    pub fn check(&mut self, now: Instant) -> Result<(), CircuitOpenError> {
+        self.blocked_for(now)?;
+
+        if self.open_until.is_some() {
+            self.probe_started = Some(now);
+        }
+        Ok(())
+    }
+
+    fn blocked_for(&self, now: Instant) -> Result<(), CircuitOpenError> {
+        let Some(open_until) = self.open_until else {
+            return Ok(());
+        };
+
+        // A probe that never answered, e.g. because its request was dropped, is given up on
+        let retry_at = match self.probe_started {
+            Some(probe_started) => open_until.max(probe_started + self.reset_timeout),
+            None => open_until,
+        };
+
+        if now < retry_at {
+            return Err(CircuitOpenError {
+                retry_in: retry_at - now,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.open_until = None;
+        self.probe_started = None;
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        self.probe_started = None;
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if self.failure_threshold > 0 && self.consecutive_failures >= self.failure_threshold {
+            self.open_until = Some(now + self.reset_timeout);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_open(&self, now: Instant) -> bool {
+        self.blocked_for(now).is_err()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold_and_recovers() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        assert!(!breaker.is_open(now));
+
+        breaker.record_failure(now);
+        assert!(breaker.is_open(now));
+        assert!(breaker.is_open(now + Duration::from_secs(59)));
+
+        // Half-open: a probe is let through, another failure opens it again
+        assert!(!breaker.is_open(now + Duration::from_secs(61)));
+        breaker.record_failure(now + Duration::from_secs(61));
+        assert!(breaker.is_open(now + Duration::from_secs(62)));
+
+        breaker.record_success();
+        assert!(!breaker.is_open(now + Duration::from_secs(62)));
+    }
+
+    #[test]
+    fn test_half_open_lets_a_single_probe_through() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure(now);
+
+        let half_open = now + Duration::from_secs(61);
+        assert!(breaker.check(half_open).is_ok());
+        assert!(breaker.check(half_open).is_err());
+        assert!(breaker.check(half_open + Duration::from_secs(30)).is_err());
+
+        // The probe never answered, so another one is let through
+        let probe_timed_out = half_open + Duration::from_secs(61);
+        assert!(breaker.check(probe_timed_out).is_ok());
+        assert!(breaker.check(probe_timed_out).is_err());
+
+        breaker.record_success();
+        assert!(breaker.check(probe_timed_out).is_ok());
+        assert!(breaker.check(probe_timed_out).is_ok());
+    }
+
+    #[test]
+    fn test_zero_threshold_never_opens() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+
+        for _ in 0..10 {
+            breaker.record_failure(now);
+        }
+
+        assert!(!breaker.is_open(now));
+    }
+}
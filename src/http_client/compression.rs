@@ -0,0 +1,70 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+/// Content encoding used for request bodies. The service has to accept it, so it is opt-in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl RequestCompression {
+    /// Value of the Content-Encoding header, if the body is encoded.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            RequestCompression::None => None,
+            RequestCompression::Gzip => Some("gzip"),
+        }
+    }
+
+    pub fn encode(&self, body: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            RequestCompression::None => Ok(body),
+            RequestCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(&body)
+                    .context("Failed to compress request body")?;
+                encoder.finish().context("Failed to compress request body")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_gzip_round_trip() -> Result<()> {
+        let body = br#"{"logs":[{"message":"hello"},{"message":"hello"}]}"#.to_vec();
+
+        let encoded = RequestCompression::Gzip.encode(body.clone())?;
+        let mut decoded = vec![];
+        GzDecoder::new(encoded.as_slice()).read_to_end(&mut decoded)?;
+
+        assert_eq!(decoded, body);
+        assert_eq!(RequestCompression::None.encode(body.clone())?, body);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_from_config() {
+        #[derive(Deserialize)]
+        struct Config {
+            compression: RequestCompression,
+        }
+
+        let config: Config = toml::from_str("compression = \"gzip\"").unwrap();
+        assert_eq!(config.compression, RequestCompression::Gzip);
+        assert_eq!(config.compression.content_encoding(), Some("gzip"));
+    }
+}
@@ -1,13 +1,118 @@
-use std::time::Duration;
+mod backoff;
+mod batching;
+mod circuit_breaker;
+mod compression;
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Ok, Result};
 use chrono::Utc;
-use log::{error, info};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
 use reqwest::Client;
+use serde::Serialize;
 use serde_json::{json, Value};
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 
+use crate::config_manager::{Config, ConfigManager};
+use crate::debug_log::log_file_path;
+use crate::offline_archive::record_offline_request;
+use backoff::{backoff_delay, is_retryable_status, parse_retry_after};
+use batching::split_batches;
+pub use batching::split_event_slices;
+use circuit_breaker::CircuitBreaker;
+pub use compression::RequestCompression;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpClientSettings {
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    pub max_retries: u32,
+    pub retry_base_delay: Duration,
+    pub retry_max_delay: Duration,
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_reset: Duration,
+    pub compression: RequestCompression,
+    pub max_batch_bytes: usize,
+    pub max_batch_events: usize,
+}
+
+impl HttpClientSettings {
+    pub fn from_config(config: &Config) -> Self {
+        HttpClientSettings {
+            timeout: Duration::from_millis(config.http_timeout_ms),
+            connect_timeout: Duration::from_millis(config.http_connect_timeout_ms),
+            max_retries: config.http_max_retries,
+            retry_base_delay: Duration::from_millis(config.http_retry_base_delay_ms),
+            retry_max_delay: Duration::from_millis(config.http_retry_max_delay_ms),
+            circuit_breaker_threshold: config.circuit_breaker_threshold,
+            circuit_breaker_reset: Duration::from_millis(config.circuit_breaker_reset_ms),
+            compression: config.http_compression,
+            max_batch_bytes: config.max_batch_bytes,
+            max_batch_events: config.max_batch_events,
+        }
+    }
+}
+
+impl Default for HttpClientSettings {
+    fn default() -> Self {
+        HttpClientSettings::from_config(&ConfigManager::load_default_config())
+    }
+}
+
+struct SharedClient {
+    client: Client,
+    settings: HttpClientSettings,
+}
+
+impl SharedClient {
+    fn new(settings: HttpClientSettings) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(settings.timeout)
+            .connect_timeout(settings.connect_timeout)
+            .build()
+            .context("Failed to build http client")?;
+
+        Ok(SharedClient { client, settings })
+    }
+}
+
+lazy_static! {
+    static ref SHARED_CLIENT: RwLock<Arc<SharedClient>> = RwLock::new(Arc::new(
+        SharedClient::new(HttpClientSettings::default()).expect("Failed to build http client")
+    ));
+    static ref CIRCUIT_BREAKER: Mutex<CircuitBreaker> = {
+        let settings = HttpClientSettings::default();
+        Mutex::new(CircuitBreaker::new(
+            settings.circuit_breaker_threshold,
+            settings.circuit_breaker_reset,
+        ))
+    };
+}
+
+/// Rebuilds the shared client when the timeouts, retry policy or batching changed.
+pub fn configure_http_client(config: &Config) -> Result<()> {
+    let settings = HttpClientSettings::from_config(config);
+
+    if shared_client().settings == settings {
+        return Ok(());
+    }
+
+    CIRCUIT_BREAKER.lock().unwrap().reconfigure(
+        settings.circuit_breaker_threshold,
+        settings.circuit_breaker_reset,
+    );
+    *SHARED_CLIENT.write().unwrap() = Arc::new(SharedClient::new(settings)?);
+
+    Ok(())
+}
+
+fn shared_client() -> Arc<SharedClient> {
+    SHARED_CLIENT.read().unwrap().clone()
+}
+
 /// Creates a log message for outgoing HTTP calls.
 fn create_log_message(service_url: &str, api_key: &str, request_body: &Value) -> String {
     let timestamp = Utc::now().to_rfc3339();
@@ -22,7 +127,7 @@ async fn write_to_log_file(filename: &str, log_message: &str) -> Result<()> {
     let mut file = OpenOptions::new()
         .create(true)
         .append(true)
-        .open(filename)
+        .open(log_file_path(filename))
         .await?;
 
     file.write_all(log_message.as_bytes()).await?;
@@ -44,7 +149,7 @@ pub async fn send_http_get(
     api_key: Option<&str>,
     timeout_duration: Option<Duration>,
 ) -> Result<(u16, String)> {
-    let client = Client::new();
+    let client = shared_client().client.clone();
     let mut response = client.get(url);
 
     if let Some(api_key) = api_key {
@@ -66,20 +171,21 @@ pub async fn send_http_get(
     Ok((status.as_u16(), response_text))
 }
 
-pub async fn send_http_body(
-    url: &str,
-    api_key: &str,
-    request_body: &Value,
-) -> Result<(u16, String)> {
-    let client = Client::new();
-    let response = client
+/// Posts a JSON body to a third-party endpoint, without the service's api key, retries or
+/// circuit breaker. In offline mode the body is written to the archive instead.
+pub async fn send_http_post(url: &str, request_body: &Value) -> Result<(u16, String)> {
+    if record_offline_request(url, request_body)? {
+        return Ok((200, "{}".to_string()));
+    }
+
+    let response = shared_client()
+        .client
         .post(url)
-        .header("x-api-key", api_key)
         .header("Content-Type", "application/json")
         .json(request_body)
         .send()
         .await
-        .context("Failed to send event data")?;
+        .with_context(|| format!("Failed to send request to {}", url))?;
 
     let status = response.status();
     let response_text = response
@@ -90,13 +196,137 @@ pub async fn send_http_body(
     Ok((status.as_u16(), response_text))
 }
 
+/// Posts a JSON body to the service, retrying connection errors, timeouts, 429 and 5xx
+/// responses with exponential backoff. Fails fast while the circuit breaker is open.
+/// In offline mode the body is written to the archive instead.
+pub async fn send_http_body(
+    url: &str,
+    api_key: &str,
+    request_body: &Value,
+) -> Result<(u16, String)> {
+    if record_offline_request(url, request_body)? {
+        return Ok((200, "{}".to_string()));
+    }
+
+    CIRCUIT_BREAKER.lock().unwrap().check(Instant::now())?;
+
+    let shared_client = shared_client();
+    let settings = &shared_client.settings;
+    let body = settings
+        .compression
+        .encode(serde_json::to_vec(request_body)?)?;
+    let mut attempt = 0;
+
+    loop {
+        let mut request = shared_client
+            .client
+            .post(url)
+            .header("x-api-key", api_key)
+            .header("Content-Type", "application/json");
+
+        if let Some(content_encoding) = settings.compression.content_encoding() {
+            request = request.header("Content-Encoding", content_encoding);
+        }
+
+        let result = request.body(body.clone()).send().await;
+
+        let response = match result {
+            Err(error) if attempt < settings.max_retries => {
+                let delay =
+                    backoff_delay(attempt, settings.retry_base_delay, settings.retry_max_delay);
+                warn!(
+                    "Request to {} failed ({}), retrying in {} ms",
+                    url,
+                    error,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+            Err(error) => {
+                CIRCUIT_BREAKER
+                    .lock()
+                    .unwrap()
+                    .record_failure(Instant::now());
+                return Err(error).context("Failed to send event data");
+            }
+            std::result::Result::Ok(response) => response,
+        };
+
+        let status = response.status();
+
+        if is_retryable_status(status) {
+            let delay = parse_retry_after(response.headers(), Utc::now()).unwrap_or_else(|| {
+                backoff_delay(attempt, settings.retry_base_delay, settings.retry_max_delay)
+            });
+
+            if attempt < settings.max_retries && delay <= settings.retry_max_delay {
+                warn!(
+                    "Request to {} returned {}, retrying in {} ms",
+                    url,
+                    status,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+
+            CIRCUIT_BREAKER
+                .lock()
+                .unwrap()
+                .record_failure(Instant::now());
+        } else {
+            CIRCUIT_BREAKER.lock().unwrap().record_success();
+        }
+
+        let response_text = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+
+        return Ok((status.as_u16(), response_text));
+    }
+}
+
+/// Splits events into the batches the data collector takes in one request, so each can be
+/// spooled and retried on its own.
+pub fn split_event_batches<T: Serialize>(events: &[T]) -> Result<Vec<&[T]>> {
+    let settings = &shared_client().settings;
+    split_event_slices(events, settings.max_batch_bytes, settings.max_batch_events)
+}
+
+/// Sends events to the data collector, split into several requests when the batch exceeds
+/// the configured byte or event cap. Stops at the first rejected request and returns the
+/// response of the last one otherwise.
 pub async fn send_http_event(service_url: &str, api_key: &str, logs: &Value) -> Result<String> {
-    // Log request body
     let logs_array = match logs {
-        Value::Array(_) => logs.clone(),
-        _ => json!([logs]),
+        Value::Array(logs) => logs.clone(),
+        _ => vec![logs.clone()],
     };
-    let request_body = json!({ "logs": logs_array });
+
+    let settings = &shared_client().settings;
+    let mut batches = split_batches(
+        logs_array,
+        settings.max_batch_bytes,
+        settings.max_batch_events,
+    );
+    if batches.is_empty() {
+        batches.push(vec![]);
+    }
+
+    let mut response_text = String::new();
+    for batch in batches {
+        response_text = send_logs_batch(service_url, api_key, batch).await?;
+    }
+
+    Ok(response_text)
+}
+
+async fn send_logs_batch(service_url: &str, api_key: &str, logs: Vec<Value>) -> Result<String> {
+    // Log request body
+    let request_body = json!({ "logs": logs });
     record_all_outgoing_http_calls(service_url, api_key, &request_body).await?;
 
     let url = format!("{}/data-collector-api", service_url);
@@ -125,7 +355,7 @@ pub async fn send_http_event(service_url: &str, api_key: &str, logs: &Value) ->
         let mut file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open("error_outgoing_http_calls.txt")
+            .open(log_file_path("error_outgoing_http_calls.txt"))
             .await?;
         let log_message = format!(
             "Error while sending send_http_event: {} - {}\nRequest body: {}\nResponse body: {}\n",
@@ -144,7 +374,8 @@ pub async fn send_http_event(service_url: &str, api_key: &str, logs: &Value) ->
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{config_manager::ConfigManager, debug_log::Logger};
+    use crate::debug_log::Logger;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
     use anyhow::Error;
     use serde_json::json;
 
@@ -152,16 +383,15 @@ mod tests {
     async fn test_send_http_event() -> Result<(), Error> {
         let _ = env_logger::builder().is_test(true).try_init();
 
-        // Load configuration
-        let config = ConfigManager::load_default_config();
+        let service = MockService::start().await;
 
         let logger = Logger::new();
         logger
             .log(
                 "test:http_client",
                 Some(json!({
-                    "api_key": &config.api_key,
-                    "service_url": &config.service_url
+                    "api_key": MOCK_API_KEY,
+                    "service_url": service.url()
                 }))
                 .as_ref(),
             )
@@ -178,7 +408,7 @@ mod tests {
         ]);
 
         // Send the HTTP event
-        let result = send_http_event(&config.service_url, &config.api_key, &logs).await;
+        let result = send_http_event(service.url(), MOCK_API_KEY, &logs).await;
 
         // Ensure the request succeeded
         assert!(
@@ -195,6 +425,18 @@ mod tests {
             );
         }
 
+        assert_eq!(service.events(), logs.as_array().unwrap().clone());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_http_event_rejected() -> Result<(), Error> {
+        let service = MockService::start().await;
+
+        let result = send_http_event(service.url(), "wrong-key", &json!({})).await;
+
+        assert!(result.unwrap_err().to_string().contains("401"));
         Ok(())
     }
 }
@@ -0,0 +1,389 @@
+// src/lib.rs
+//! Tracer daemon: watches the tools a pipeline runs and reports them to the Tracer service.
+//!
+//! Besides the `tracer` binary, the crate can be embedded: [`TracerClient`] and
+//! [`ProcessWatcher`] do the monitoring in-process, and [`DaemonClient`] talks to a running
+//! daemon over its control socket.
+pub mod cli;
+pub mod config_manager;
+pub mod daemon_communication;
+mod daemon_state;
+mod debug_log;
+pub mod event_attributes;
+pub mod event_recorder;
+mod event_spool;
+mod events;
+mod exporters;
+mod file_watcher;
+mod http_client;
+mod metrics;
+#[cfg(test)]
+mod mock_service;
+mod offline_archive;
+pub mod process_watcher;
+mod prometheus;
+pub mod run_detection;
+pub mod run_scope;
+pub mod run_summary;
+pub mod runtime_paths;
+mod stdout;
+pub mod steps;
+mod submit_batched_data;
+mod syslog;
+pub mod tracer_client;
+mod upload;
+
+pub use config_manager::target_process::target_matching::TargetMatch;
+pub use config_manager::target_process::Target;
+pub use daemon_communication::client::DaemonClient;
+pub use daemon_communication::structs::{
+    InfoResponse, PsResponse, Request, Response, RunStatus, StatusResponse,
+};
+pub use event_attributes::{LogLevel, MessageAttributes, RunDetails};
+pub use process_watcher::{ProcessWatcher, ShortLivedProcessLog, TrackedProcess};
+pub use run_summary::RunSummary;
+pub use runtime_paths::RuntimePaths;
+pub use tracer_client::TracerClient;
+
+use anyhow::{Context, Ok, Result};
+use chrono::Utc;
+use daemon_communication::server::run_server;
+use daemonize::Daemonize;
+use http_client::configure_http_client;
+use offline_archive::{enable_offline_archive, OfflineArchive};
+use prometheus::run_prometheus_server;
+use run_detection::RunDetection;
+use runtime_paths::socket_group;
+use std::borrow::BorrowMut;
+use syslog::run_syslog_lines_read_thread;
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::config_manager::ConfigManager;
+
+const SYSLOG_FILE: &str = "/var/log/syslog";
+
+const REPO_OWNER: &str = "davincios";
+const REPO_NAME: &str = "tracer-daemon";
+
+pub fn start_daemon(offline: bool, paths: &RuntimePaths) -> Result<()> {
+    if !offline {
+        ConfigManager::test_service_config_sync()?;
+    }
+
+    paths.create_directory(socket_group(
+        &ConfigManager::load_config().socket_allowed_gids,
+    ))?;
+
+    let daemon = Daemonize::new();
+    daemon
+        .pid_file(&paths.pid_file)
+        .working_directory(&paths.directory)
+        .stdout(
+            File::create(&paths.stdout_file)
+                .context("Failed to create stdout file")
+                .unwrap(),
+        )
+        .stderr(
+            File::create(&paths.stderr_file)
+                .context("Failed to create stderr file")
+                .unwrap(),
+        )
+        .start()
+        .context("Failed to start daemon.")
+}
+
+#[tokio::main]
+pub async fn run(
+    workflow_directory_path: String,
+    offline_archive: Option<PathBuf>,
+    paths: RuntimePaths,
+) -> Result<()> {
+    let raw_config = ConfigManager::load_config();
+    configure_http_client(&raw_config)?;
+    if let Some(archive_directory) = offline_archive {
+        enable_offline_archive(OfflineArchive::open(
+            &archive_directory,
+            &raw_config.service_url,
+        )?);
+    }
+    let client = TracerClient::new(raw_config.clone(), workflow_directory_path, &paths)
+        .await
+        .context("Failed to create TracerClient")?;
+    let tracer_client = Arc::new(Mutex::new(client));
+    let prometheus_listen_address = raw_config.prometheus_listen_address.clone();
+    let config: Arc<RwLock<config_manager::Config>> = Arc::new(RwLock::new(raw_config));
+
+    let cancellation_token = CancellationToken::new();
+
+    let server_tracer_client = tracer_client.clone();
+    let server_cancellation_token = cancellation_token.clone();
+    let server_config = config.clone();
+    let socket_path = paths.socket.clone();
+    tokio::spawn(async move {
+        let server = run_server(
+            server_tracer_client,
+            &socket_path,
+            server_cancellation_token,
+            server_config,
+        );
+        if let Err(error) = server.await {
+            eprintln!("[{}] Control socket stopped: {:?}", Utc::now(), error);
+        }
+    });
+
+    if let Some(listen_address) = prometheus_listen_address {
+        let prometheus_server = run_prometheus_server(
+            tracer_client.clone(),
+            listen_address,
+            cancellation_token.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(error) = prometheus_server.await {
+                eprintln!("[{}] Prometheus endpoint stopped: {}", Utc::now(), error);
+            }
+        });
+    }
+
+    let syslog_lines_task = tokio::spawn(run_syslog_lines_read_thread(
+        SYSLOG_FILE,
+        tracer_client.lock().await.get_syslog_lines_buffer(),
+    ));
+
+    let stdout_stderr_lines_buffer = tracer_client.lock().await.get_stdout_stderr_lines_buffer();
+    let interceptor_files = (
+        paths.interceptor_stdout_file.clone(),
+        paths.interceptor_stderr_file.clone(),
+    );
+    let stdout_lines_task = tokio::spawn(async move {
+        stdout::run_stdout_lines_read_thread(
+            &interceptor_files.0,
+            &interceptor_files.1,
+            stdout_stderr_lines_buffer,
+        )
+        .await
+    });
+
+    // With run detection, runs start once the first tool is seen instead, and a run restored
+    // from the previous daemon carries on
+    let manual_runs = tracer_client.lock().await.run_detection() == RunDetection::Manual;
+    let resumed_run = tracer_client.lock().await.get_run_metadata().is_some();
+    if manual_runs && !resumed_run {
+        if let Err(error) = tracer_client
+            .lock()
+            .await
+            .borrow_mut()
+            .start_new_run(None, None, RunDetails::default())
+            .await
+        {
+            eprintln!("[{}] Failed to start a new run: {:?}", Utc::now(), error);
+        }
+    }
+
+    if let Err(error) = tracer_client
+        .lock()
+        .await
+        .borrow_mut()
+        .flush_exporters()
+        .await
+    {
+        eprintln!("Failed to submit spooled batches: {}", error);
+    }
+
+    while !cancellation_token.is_cancelled() {
+        let start_time = Instant::now();
+        while start_time.elapsed()
+            < Duration::from_millis(config.read().await.batch_submission_interval_ms)
+        {
+            if let Err(error) =
+                monitor_processes_with_tracer_client(tracer_client.lock().await.borrow_mut()).await
+            {
+                eprintln!("[{}] Failed to monitor processes: {:?}", Utc::now(), error);
+            }
+            sleep(Duration::from_millis(
+                config.read().await.process_polling_interval_ms,
+            ))
+            .await;
+            if cancellation_token.is_cancelled() {
+                break;
+            }
+        }
+
+        // Events stay in the spool when the service is unreachable, so keep monitoring
+        if let Err(error) = tracer_client
+            .lock()
+            .await
+            .borrow_mut()
+            .submit_batched_data()
+            .await
+        {
+            eprintln!(
+                "[{}] Failed to submit batched data: {:?}",
+                Utc::now(),
+                error
+            );
+        }
+
+        if let Err(error) = tracer_client.lock().await.borrow_mut().poll_files().await {
+            eprintln!("[{}] Failed to poll files: {:?}", Utc::now(), error);
+        }
+
+        if let Err(error) = tracer_client.lock().await.borrow_mut().save_state() {
+            eprintln!("[{}] Failed to save daemon state: {:#}", Utc::now(), error);
+        }
+    }
+
+    if let Err(error) = tracer_client.lock().await.borrow_mut().shutdown().await {
+        eprintln!("[{}] Failed to shut down cleanly: {:#}", Utc::now(), error);
+    }
+
+    syslog_lines_task.abort();
+    stdout_lines_task.abort();
+
+    Ok(())
+}
+
+pub async fn monitor_processes_with_tracer_client(tracer_client: &mut TracerClient) -> Result<()> {
+    tracer_client.remove_completed_processes().await?;
+    tracer_client.poll_processes()?;
+    tracer_client.run_cleanup().await?;
+    tracer_client.poll_process_metrics().await?;
+    tracer_client.poll_syslog().await?;
+    tracer_client.poll_stdout_stderr().await?;
+    tracer_client.refresh_sysinfo();
+    tracer_client.reset_just_started_process_flag();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_manager::ConfigManager;
+    use crate::daemon_state::DaemonState;
+    use crate::event_recorder::EventType;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
+    use config_manager::Config;
+    use std::path::Path;
+
+    fn load_test_config() -> Config {
+        ConfigManager::load_default_config()
+    }
+
+    #[tokio::test]
+    async fn test_monitor_processes_with_tracer_client() {
+        let config = load_test_config();
+        let pwd = std::env::current_dir().unwrap();
+        let runtime_directory = tempfile::tempdir().unwrap();
+        let mut tracer_client = TracerClient::new(
+            config,
+            pwd.to_str().unwrap().to_string(),
+            &RuntimePaths::new(runtime_directory.path()),
+        )
+        .await
+        .unwrap();
+        let result = monitor_processes_with_tracer_client(&mut tracer_client).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_ends_while_the_service_rejects_it() -> Result<()> {
+        let service = MockService::start().await;
+        let mut config = load_test_config();
+        config.service_url = service.url().to_string();
+        config.api_key = MOCK_API_KEY.to_string();
+        config.exporters = vec![];
+        let runtime_directory = tempfile::tempdir()?;
+        let mut tracer_client = TracerClient::new(
+            config,
+            runtime_directory.path().to_str().unwrap().to_string(),
+            &RuntimePaths::new(runtime_directory.path()),
+        )
+        .await?;
+
+        tracer_client
+            .start_new_run(None, None, RunDetails::default())
+            .await?;
+        service.respond_with_statuses("/data-collector-api", &[400]);
+        let buffered_events = tracer_client.buffered_event_count();
+
+        assert!(tracer_client.stop_run(None).await?.is_some());
+        assert!(tracer_client.get_run_metadata().is_none());
+        // The end of the run is sent with the next batch instead
+        assert_eq!(tracer_client.buffered_event_count(), buffered_events + 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inactive_run_ends_once_while_the_service_rejects_it() -> Result<()> {
+        let service = MockService::start().await;
+        let mut config = load_test_config();
+        config.service_url = service.url().to_string();
+        config.api_key = MOCK_API_KEY.to_string();
+        config.exporters = vec![];
+        config.run_detection = RunDetection::Inactivity;
+        config.new_run_pause_ms = 0;
+        let runtime_directory = tempfile::tempdir()?;
+        let mut tracer_client = TracerClient::new(
+            config,
+            runtime_directory.path().to_str().unwrap().to_string(),
+            &RuntimePaths::new(runtime_directory.path()),
+        )
+        .await?;
+
+        tracer_client
+            .start_new_run(None, None, RunDetails::default())
+            .await?;
+        service.respond_with_statuses("/data-collector-api", &[400]);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        tracer_client.run_cleanup().await?;
+        assert!(tracer_client.get_run_metadata().is_none());
+
+        let run_ends: Vec<serde_json::Value> = tracer_client
+            .logs
+            .get_events()
+            .iter()
+            .filter(|event| event.process_status == EventType::FinishedRun.as_str())
+            .map(|event| serde_json::to_value(event).unwrap()["message"].clone())
+            .collect();
+        assert_eq!(run_ends, vec!["Run ended due to inactivity"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_leaves_no_run_to_resume() -> Result<()> {
+        let service = MockService::start().await;
+        let mut config = load_test_config();
+        config.service_url = service.url().to_string();
+        config.api_key = MOCK_API_KEY.to_string();
+        config.exporters = vec![];
+        let runtime_directory = tempfile::tempdir()?;
+        let paths = RuntimePaths::new(runtime_directory.path());
+        let mut tracer_client = TracerClient::new(
+            config,
+            runtime_directory.path().to_str().unwrap().to_string(),
+            &paths,
+        )
+        .await?;
+
+        tracer_client
+            .start_new_run(None, None, RunDetails::default())
+            .await?;
+        tracer_client.save_state()?;
+        let state = DaemonState::load(Path::new(&paths.state_file))?.unwrap();
+        assert!(state.current_run.is_some());
+
+        tracer_client.shutdown().await?;
+        let state = DaemonState::load(Path::new(&paths.state_file))?.unwrap();
+        assert!(state.current_run.is_none());
+        assert!(tracer_client.get_run_metadata().is_none());
+
+        Ok(())
+    }
+}
@@ -3,9 +3,9 @@ use std::collections::HashMap;
 /// src/system_metrics.rs
 use anyhow::Result;
 use chrono::Utc;
-use serde_json::{json, Value};
 use sysinfo::{Disks, System};
 
+use crate::event_attributes::{DiskStatistic, EventAttributes, SystemMetricAttributes};
 use crate::event_recorder::{EventRecorder, EventType};
 
 pub struct SystemMetricsCollector;
@@ -15,10 +15,10 @@ impl SystemMetricsCollector {
         SystemMetricsCollector
     }
 
-    pub fn gather_disk_data() -> HashMap<String, serde_json::Value> {
+    pub fn gather_disk_data() -> HashMap<String, DiskStatistic> {
         let disks: Disks = Disks::new_with_refreshed_list();
 
-        let mut d_stats: HashMap<String, serde_json::Value> = HashMap::new();
+        let mut d_stats: HashMap<String, DiskStatistic> = HashMap::new();
 
         for d in disks.iter() {
             let Some(d_name) = d.name().to_str() else {
@@ -30,12 +30,12 @@ impl SystemMetricsCollector {
             let used_space = total_space - available_space;
             let disk_utilization = (used_space as f64 / total_space as f64) * 100.0;
 
-            let disk_data = json!({
-                  "disk_total_space": total_space,
-                  "disk_used_space": used_space,
-                  "disk_available_space": available_space,
-                  "disk_utilization": disk_utilization,
-            });
+            let disk_data = DiskStatistic {
+                disk_total_space: total_space,
+                disk_used_space: used_space,
+                disk_available_space: available_space,
+                disk_utilization,
+            };
 
             d_stats.insert(d_name.to_string(), disk_data);
         }
@@ -43,7 +43,7 @@ impl SystemMetricsCollector {
         d_stats
     }
 
-    pub fn gather_metrics_object_attributes(system: &mut System) -> Value {
+    pub fn gather_metrics_object_attributes(system: &mut System) -> SystemMetricAttributes {
         let used_memory = system.used_memory();
         let total_memory = system.total_memory();
         let memory_utilization = (used_memory as f64 / total_memory as f64) * 100.0;
@@ -52,19 +52,17 @@ impl SystemMetricsCollector {
 
         let d_stats = Self::gather_disk_data();
 
-        let attributes = json!({
-            "events_name": "global_system_metrics",
-            "system_memory_total": total_memory,
-            "system_memory_used": used_memory,
-            "system_memory_available": system.available_memory(),
-            "system_memory_utilization": memory_utilization,
-            "system_memory_swap_total": system.total_swap(),
-            "system_memory_swap_used": system.used_swap(),
-            "system_cpu_utilization": cpu_usage,
-            "system_disk_io": d_stats,
-        });
-
-        attributes
+        SystemMetricAttributes {
+            events_name: "global_system_metrics".to_string(),
+            system_memory_total: total_memory,
+            system_memory_used: used_memory,
+            system_memory_available: system.available_memory(),
+            system_memory_utilization: memory_utilization,
+            system_memory_swap_total: system.total_swap(),
+            system_memory_swap_used: system.used_swap(),
+            system_cpu_utilization: cpu_usage,
+            system_disk_io: d_stats,
+        }
     }
 
     pub fn collect_metrics(&self, system: &mut System, logs: &mut EventRecorder) -> Result<()> {
@@ -73,7 +71,7 @@ impl SystemMetricsCollector {
         logs.record_event(
             EventType::MetricEvent,
             format!("[{}] System's resources metric", Utc::now()),
-            Some(attributes),
+            Some(EventAttributes::SystemMetric(attributes)),
             None,
         );
 
@@ -101,7 +99,7 @@ mod tests {
 
         assert!(event.attributes.is_some());
 
-        let attributes = event.attributes.as_ref().unwrap();
+        let attributes = event.attributes.as_ref().unwrap().to_value();
         assert_eq!(attributes["events_name"], "global_system_metrics");
         assert!(attributes["system_memory_total"].is_number());
         assert!(attributes["system_memory_used"].is_number());
@@ -0,0 +1,265 @@
+// src/mock_service.rs
+//! In-process stand-in for the Tracer service, so tests run offline and deterministically.
+//!
+//! It serves the endpoints the daemon talks to (`/data-collector-api`, `/upload/presigned-put`,
+//! `/stdout-capture`) plus an S3-like PUT target the presigned urls point to, and records
+//! every request it receives.
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use url::Url;
+
+pub const MOCK_API_KEY: &str = "mock-api-key";
+pub const MOCK_RUN_NAME: &str = "mock-run";
+pub const MOCK_RUN_ID: &str = "mock-run-id";
+pub const MOCK_SERVICE_NAME: &str = "mock-service";
+
+const S3_PREFIX: &str = "/s3/";
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path without the query string
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    /// Body as sent, after undoing any Content-Encoding
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    requests: Vec<RecordedRequest>,
+    uploads: HashMap<String, Vec<u8>>,
+    /// Statuses to answer with before falling back to the regular behaviour, per path
+    scripted_statuses: HashMap<String, VecDeque<u16>>,
+}
+
+pub struct MockService {
+    url: String,
+    state: Arc<Mutex<MockState>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockService {
+    pub async fn start() -> MockService {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock service");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let handle = tokio::spawn({
+            let url = url.clone();
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve_connection(stream, url.clone(), state.clone()));
+                }
+            }
+        });
+
+        MockService { url, state, handle }
+    }
+
+    /// Base url to use as `service_url`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_to(&self, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.path == path)
+            .collect()
+    }
+
+    /// Events received by the data collector, in order.
+    pub fn events(&self) -> Vec<Value> {
+        self.requests_to("/data-collector-api")
+            .iter()
+            .flat_map(|request| {
+                request.json()["logs"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Content of a file uploaded to the S3-like target.
+    pub fn uploaded_file(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().uploads.get(file_name).cloned()
+    }
+
+    /// Answers the next requests to `path` with the given statuses, in order.
+    pub fn respond_with_statuses(&self, path: &str, statuses: &[u16]) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted_statuses
+            .entry(path.to_string())
+            .or_default()
+            .extend(statuses);
+    }
+}
+
+impl Drop for MockService {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve_connection(mut stream: TcpStream, url: String, state: Arc<Mutex<MockState>>) {
+    let mut buffer = vec![];
+    let mut chunk = [0; 8192];
+
+    loop {
+        let request = loop {
+            if let Some((request, consumed)) = parse_request(&buffer) {
+                buffer.drain(..consumed);
+                break request;
+            }
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            }
+        };
+
+        let (status, body) = respond(&url, &state, request);
+        let response = format!(
+            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+
+        if stream.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Parses one complete request from the buffer, returning it with the number of bytes used.
+fn parse_request(buffer: &[u8]) -> Option<(RecordedRequest, usize)> {
+    let header_end = buffer.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = Url::parse(&format!("http://mock{}", request_line.next()?)).ok()?;
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let content_length: usize = headers
+        .get("content-length")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    let body_start = header_end + 4;
+    if buffer.len() < body_start + content_length {
+        return None;
+    }
+
+    let mut body = buffer[body_start..body_start + content_length].to_vec();
+    if headers.get("content-encoding").map(String::as_str) == Some("gzip") {
+        let mut decoded = vec![];
+        GzDecoder::new(body.as_slice())
+            .read_to_end(&mut decoded)
+            .ok()?;
+        body = decoded;
+    }
+
+    let request = RecordedRequest {
+        method,
+        path: target.path().to_string(),
+        query: target.query_pairs().into_owned().collect(),
+        headers,
+        body,
+    };
+
+    Some((request, body_start + content_length))
+}
+
+fn respond(url: &str, state: &Mutex<MockState>, request: RecordedRequest) -> (u16, String) {
+    let mut state = state.lock().unwrap();
+    state.requests.push(request.clone());
+
+    if let Some(status) = state
+        .scripted_statuses
+        .get_mut(&request.path)
+        .and_then(VecDeque::pop_front)
+    {
+        return (status, json!({ "error": "scripted response" }).to_string());
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/data-collector-api") => {
+            if request.header("x-api-key") != Some(MOCK_API_KEY) {
+                return (401, json!({ "error": "invalid api key" }).to_string());
+            }
+
+            // The service answers a new_run event with the properties of the created run,
+            // named as requested if the event asks for a name
+            let result: Vec<Value> = request.json()["logs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|log| log["process_status"] == "new_run")
+                .map(|log| {
+                    json!({
+                        "properties": {
+                            "run_name": log["attributes"]["run_name"]
+                                .as_str()
+                                .unwrap_or(MOCK_RUN_NAME),
+                            "run_id": MOCK_RUN_ID,
+                            "service_name": MOCK_SERVICE_NAME,
+                        }
+                    })
+                })
+                .collect();
+
+            (200, json!({ "result": result }).to_string())
+        }
+        ("POST", "/upload/presigned-put") => match request.query.get("fileName") {
+            Some(file_name) => {
+                let signed_url = format!(
+                    "{}{}{}?X-Amz-Signature=mock-signature",
+                    url, S3_PREFIX, file_name
+                );
+                (200, json!({ "signedUrl": signed_url }).to_string())
+            }
+            None => (400, json!({ "error": "missing fileName" }).to_string()),
+        },
+        ("POST", "/stdout-capture") => (200, json!({}).to_string()),
+        ("PUT", path) if path.starts_with(S3_PREFIX) => {
+            let file_name = path.trim_start_matches(S3_PREFIX).to_string();
+            state.uploads.insert(file_name, request.body);
+            (200, String::new())
+        }
+        _ => (404, json!({ "error": "not found" }).to_string()),
+    }
+}
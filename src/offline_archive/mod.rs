@@ -0,0 +1,389 @@
+// src/offline_archive/mod.rs
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::http_client::{send_http_body, send_http_post};
+use crate::upload::upload_from_file_path;
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const FILES_DIRECTORY: &str = "files";
+const REPLAY_PROGRESS_FILE: &str = "replay-progress";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    /// A request to the service, `endpoint` is relative to the service url.
+    Request { endpoint: String, body: Value },
+    /// A request to a third-party endpoint, e.g. an OTLP collector. Replayed without the
+    /// service's api key.
+    Export { url: String, body: Value },
+    /// A file handed to `tracer upload`, copied into the archive's files directory.
+    Upload { file: String, file_name: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub sequence: u64,
+    pub recorded_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub record: ArchiveRecord,
+}
+
+/// Local stand-in for the service when the daemon runs with `tracer init --offline`.
+///
+/// Everything that would have been sent is appended to a journal in the order it happened,
+/// so `tracer replay` can push it to the service later with the original payloads.
+pub struct OfflineArchive {
+    directory: PathBuf,
+    service_url: String,
+    next_sequence: u64,
+}
+
+impl OfflineArchive {
+    pub fn open(directory: &Path, service_url: &str) -> Result<Self> {
+        fs::create_dir_all(directory.join(FILES_DIRECTORY))
+            .with_context(|| format!("Failed to create archive directory {:?}", directory))?;
+
+        let next_sequence = read_entries(directory)?
+            .last()
+            .map(|entry| entry.sequence + 1)
+            .unwrap_or(0);
+
+        Ok(OfflineArchive {
+            directory: directory.to_path_buf(),
+            service_url: service_url.trim_end_matches('/').to_string(),
+            next_sequence,
+        })
+    }
+
+    pub fn record_request(&mut self, url: &str, body: &Value) -> Result<()> {
+        let endpoint = url
+            .strip_prefix(&self.service_url)
+            .filter(|endpoint| endpoint.starts_with('/'));
+
+        self.append(match endpoint {
+            Some(endpoint) => ArchiveRecord::Request {
+                endpoint: endpoint.to_string(),
+                body: body.clone(),
+            },
+            None => ArchiveRecord::Export {
+                url: url.to_string(),
+                body: body.clone(),
+            },
+        })
+    }
+
+    pub fn record_upload(&mut self, file_path: &Path, file_name: &str) -> Result<()> {
+        let file = format!(
+            "{}/{:06}-{}",
+            FILES_DIRECTORY, self.next_sequence, file_name
+        );
+        fs::copy(file_path, self.directory.join(&file))
+            .with_context(|| format!("Failed to copy {:?} into the archive", file_path))?;
+
+        self.append(ArchiveRecord::Upload {
+            file,
+            file_name: file_name.to_string(),
+        })
+    }
+
+    fn append(&mut self, record: ArchiveRecord) -> Result<()> {
+        let entry = ArchiveEntry {
+            sequence: self.next_sequence,
+            recorded_at: Utc::now(),
+            record,
+        };
+
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(JOURNAL_FILE))
+            .context("Failed to open archive journal")?;
+        writeln!(journal, "{}", serde_json::to_string(&entry)?)
+            .context("Failed to write archive journal")?;
+
+        self.next_sequence += 1;
+        Ok(())
+    }
+}
+
+/// Reads the journal of an archive, oldest entry first.
+pub fn read_entries(directory: &Path) -> Result<Vec<ArchiveEntry>> {
+    let journal_path = directory.join(JOURNAL_FILE);
+    if !journal_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let journal = File::open(&journal_path)
+        .with_context(|| format!("Failed to open archive journal {:?}", journal_path))?;
+
+    let mut entries = vec![];
+    for line in BufReader::new(journal).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            // A daemon killed mid-write leaves a truncated last line behind
+            Err(error) => eprintln!("Skipping unreadable archive entry: {}", error),
+        }
+    }
+
+    Ok(entries)
+}
+
+lazy_static! {
+    static ref OFFLINE_ARCHIVE: Mutex<Option<OfflineArchive>> = Mutex::new(None);
+}
+
+/// Routes every request to the service into the archive instead of the network.
+pub fn enable_offline_archive(archive: OfflineArchive) {
+    *OFFLINE_ARCHIVE.lock().unwrap() = Some(archive);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE_ARCHIVE.lock().unwrap().is_some()
+}
+
+/// Records the request when running offline. Returns false when the request should go out.
+pub fn record_offline_request(url: &str, body: &Value) -> Result<bool> {
+    match OFFLINE_ARCHIVE.lock().unwrap().as_mut() {
+        Some(archive) => archive.record_request(url, body).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// Records the upload when running offline. Returns false when the file should be uploaded.
+pub fn record_offline_upload(file_path: &Path, file_name: &str) -> Result<bool> {
+    match OFFLINE_ARCHIVE.lock().unwrap().as_mut() {
+        Some(archive) => archive.record_upload(file_path, file_name).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+fn read_replay_progress(directory: &Path) -> Result<u64> {
+    match fs::read_to_string(directory.join(REPLAY_PROGRESS_FILE)) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .context("Failed to parse archive replay progress"),
+        Err(_) => Ok(0),
+    }
+}
+
+fn write_replay_progress(directory: &Path, next_sequence: u64) -> Result<()> {
+    fs::write(
+        directory.join(REPLAY_PROGRESS_FILE),
+        next_sequence.to_string(),
+    )
+    .context("Failed to write archive replay progress")
+}
+
+/// Pushes an archive to the service in the order it was recorded.
+///
+/// Progress is stored next to the journal, so a replay that stopped on an error resumes
+/// after the last entry the service accepted. Returns the number of entries sent.
+pub async fn replay_archive(directory: &Path, service_url: &str, api_key: &str) -> Result<usize> {
+    let service_url = service_url.trim_end_matches('/');
+    let next_sequence = read_replay_progress(directory)?;
+    let mut replayed = 0;
+
+    for entry in read_entries(directory)? {
+        if entry.sequence < next_sequence {
+            continue;
+        }
+
+        match &entry.record {
+            ArchiveRecord::Request { endpoint, body }
+            | ArchiveRecord::Export {
+                url: endpoint,
+                body,
+            } => {
+                // Archives written before `Export` kept third-party urls as requests, the
+                // service's api key only goes to the service
+                let (status, response) = if endpoint.starts_with('/') {
+                    let url = format!("{}{}", service_url, endpoint);
+                    send_http_body(&url, api_key, body).await?
+                } else {
+                    send_http_post(endpoint, body).await?
+                };
+                if !(200..300).contains(&status) {
+                    bail!(
+                        "Service rejected archive entry {}: {} - {}",
+                        entry.sequence,
+                        status,
+                        response
+                    );
+                }
+            }
+            ArchiveRecord::Upload { file, file_name } => {
+                let file_path = directory.join(file);
+                let file_path = file_path
+                    .to_str()
+                    .context("Archive path is not valid UTF-8")?;
+
+                upload_from_file_path(service_url, api_key, file_path, Some(file_name))
+                    .await
+                    .with_context(|| {
+                        format!("Failed to upload archive entry {}", entry.sequence)
+                    })?;
+            }
+        }
+
+        write_replay_progress(directory, entry.sequence + 1)?;
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_records_requests_and_uploads_in_order() -> Result<()> {
+        let directory = tempdir()?;
+        let upload = directory.path().join("report.txt");
+        fs::write(&upload, "report")?;
+
+        let archive_directory = directory.path().join("archive");
+        let mut archive = OfflineArchive::open(&archive_directory, "https://service/api/")?;
+        archive.record_request(
+            "https://service/api/data-collector-api",
+            &json!({"logs": []}),
+        )?;
+        archive.record_upload(&upload, "report.txt")?;
+        archive.record_request("https://elsewhere/v1/traces", &json!({}))?;
+
+        let entries = read_entries(&archive_directory)?;
+        let sequences: Vec<u64> = entries.iter().map(|entry| entry.sequence).collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+        assert_eq!(
+            entries[0].record,
+            ArchiveRecord::Request {
+                endpoint: "/data-collector-api".to_string(),
+                body: json!({"logs": []}),
+            }
+        );
+        assert_eq!(
+            entries[1].record,
+            ArchiveRecord::Upload {
+                file: "files/000001-report.txt".to_string(),
+                file_name: "report.txt".to_string(),
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(archive_directory.join("files/000001-report.txt"))?,
+            "report"
+        );
+        assert_eq!(
+            entries[2].record,
+            ArchiveRecord::Export {
+                url: "https://elsewhere/v1/traces".to_string(),
+                body: json!({}),
+            }
+        );
+
+        // Reopening the archive continues the sequence
+        let mut archive = OfflineArchive::open(&archive_directory, "https://service/api")?;
+        archive.record_request("https://service/api/stdout-capture", &json!({}))?;
+        assert_eq!(read_entries(&archive_directory)?[3].sequence, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_journal_line_is_skipped() -> Result<()> {
+        let directory = tempdir()?;
+        let mut archive = OfflineArchive::open(directory.path(), "https://service")?;
+        archive.record_request("https://service/stdout-capture", &json!({"lines": []}))?;
+
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(directory.path().join(JOURNAL_FILE))?;
+        write!(journal, "{{\"sequence\": 1, \"recor")?;
+
+        assert_eq!(read_entries(directory.path())?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_resumes_after_rejected_entry() -> Result<()> {
+        let directory = tempdir()?;
+        let upload = directory.path().join("report.txt");
+        fs::write(&upload, "report")?;
+
+        let archive_directory = directory.path().join("archive");
+        let mut archive = OfflineArchive::open(&archive_directory, "https://service")?;
+        for i in 0..3 {
+            archive.record_request(
+                "https://service/data-collector-api",
+                &json!({"logs": [{"timestamp": i}]}),
+            )?;
+        }
+        archive.record_upload(&upload, "report.txt")?;
+
+        let service = MockService::start().await;
+        service.respond_with_statuses("/data-collector-api", &[200, 400]);
+
+        let result = replay_archive(&archive_directory, service.url(), MOCK_API_KEY).await;
+        assert!(result.is_err());
+        assert_eq!(read_replay_progress(&archive_directory)?, 1);
+
+        assert_eq!(
+            replay_archive(&archive_directory, service.url(), MOCK_API_KEY).await?,
+            3
+        );
+
+        let timestamps: Vec<u64> = service
+            .requests_to("/data-collector-api")
+            .iter()
+            .map(|request| request.json()["logs"][0]["timestamp"].as_u64().unwrap())
+            .collect();
+        assert_eq!(timestamps, vec![0, 1, 1, 2]);
+        assert_eq!(
+            service.uploaded_file("report.txt"),
+            Some(b"report".to_vec())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_keeps_api_key_from_third_parties() -> Result<()> {
+        let service = MockService::start().await;
+        let collector = MockService::start().await;
+        collector.respond_with_statuses("/v1/traces", &[200]);
+
+        let directory = tempdir()?;
+        let mut archive = OfflineArchive::open(directory.path(), service.url())?;
+        let traces_url = format!("{}/v1/traces", collector.url());
+        archive.record_request(&traces_url, &json!({"resourceSpans": []}))?;
+
+        assert_eq!(
+            replay_archive(directory.path(), service.url(), MOCK_API_KEY).await?,
+            1
+        );
+
+        let requests = collector.requests_to("/v1/traces");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("x-api-key"), None);
+        assert!(service.requests().is_empty());
+
+        Ok(())
+    }
+}
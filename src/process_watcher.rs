@@ -1,14 +1,17 @@
 // src/process_watcher.rs
 use crate::config_manager::target_process::Target;
 use crate::config_manager::target_process::TargetMatchable;
+use crate::event_attributes::{EventAttributes, FinishedToolAttributes, ToolExecutionAttributes};
 use crate::event_recorder::EventRecorder;
 use crate::event_recorder::EventType;
+use crate::event_recorder::RecordingRun;
 use crate::file_watcher::FileWatcher;
+use crate::run_scope::{self, RunScope};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json::json;
 use std::collections::hash_map::Entry::Vacant;
 use std::collections::HashMap;
 use std::path::Path;
@@ -20,6 +23,9 @@ pub struct ProcessWatcher {
     targets: Vec<Target>,
     seen: HashMap<Pid, Proc>,
     process_tree: HashMap<Pid, ProcessTreeNode>,
+    run_scopes: Vec<RunScope>,
+    /// Path of the running step of each run, by scoped run id, `None` for the daemon's run
+    active_steps: HashMap<Option<String>, String>,
 }
 
 enum ProcLastUpdate {
@@ -29,12 +35,30 @@ enum ProcLastUpdate {
 
 pub struct Proc {
     name: String,
+    display_name: String,
+    /// Target the process matched, `None` for short-lived processes reported by the CLI
+    target: Option<String>,
     start_time: DateTime<Utc>,
     last_update: ProcLastUpdate,
     just_started: bool,
+    /// Scoped run the process was started in, `None` for the daemon's run
+    run: Option<RecordingRun>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A tracked process, saved to be tracked again by the next daemon.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PersistedProcess {
+    pub pid: u32,
+    /// Tells the process apart from a later one reusing its pid
+    pub process_start_time: u64,
+    pub name: String,
+    pub display_name: String,
+    pub target: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub run: Option<RecordingRun>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct InputFile {
     pub file_name: String,
     pub file_size: u64,
@@ -43,7 +67,7 @@ pub struct InputFile {
     pub file_updated_at_timestamp: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
 pub struct ProcessProperties {
     pub tool_name: String,
     pub tool_pid: String,
@@ -62,13 +86,30 @@ pub struct ProcessProperties {
     pub process_status: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ShortLivedProcessLog {
     pub command: String,
     pub timestamp: String,
     pub properties: ProcessProperties,
 }
 
+/// A process the watcher tracks, with its live data when it is still running.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrackedProcess {
+    pub pid: u32,
+    pub name: String,
+    pub display_name: String,
+    pub target: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub cpu_utilization: Option<f32>,
+    /// Resident memory in bytes
+    pub memory_rss: Option<u64>,
+    pub disk_read_total: Option<u64>,
+    pub disk_write_total: Option<u64>,
+    /// `None` once the process exited, until the watcher notices
+    pub status: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ProcessTreeNode {
     pub properties: ProcessProperties,
@@ -100,7 +141,59 @@ impl ProcessWatcher {
             targets,
             seen: HashMap::new(),
             process_tree: HashMap::new(),
+            run_scopes: Vec::new(),
+            active_steps: HashMap::new(),
+        }
+    }
+
+    /// Sets the steps tools started from now on are tagged with.
+    pub fn set_active_steps(&mut self, active_steps: HashMap<Option<String>, String>) {
+        self.active_steps = active_steps;
+    }
+
+    fn active_step(&self, run: Option<&RecordingRun>) -> Option<String> {
+        self.active_steps
+            .get(&run.map(|run| run.id.clone()))
+            .cloned()
+    }
+
+    /// Sets the scoped runs new tools are attributed to. Tools of a run that ended are
+    /// recorded in the daemon's run from now on.
+    pub fn set_run_scopes(&mut self, run_scopes: Vec<RunScope>) {
+        for proc in self.seen.values_mut() {
+            let still_running = proc
+                .run
+                .as_ref()
+                .is_some_and(|run| run_scopes.iter().any(|scope| &scope.run == run));
+            if !still_running {
+                proc.run = None;
+            }
+        }
+
+        self.run_scopes = run_scopes;
+    }
+
+    /// Scoped run of the tool, looking its ancestors up in the live process table first, as
+    /// tools started since the last poll are not in the process tree yet.
+    fn find_run(
+        &self,
+        system: Option<&System>,
+        pid: Pid,
+        working_directory: Option<&Path>,
+    ) -> Option<RecordingRun> {
+        if self.run_scopes.is_empty() {
+            return None;
         }
+
+        let ancestors = run_scope::ancestors(pid, |pid| {
+            system
+                .and_then(|system| system.process(pid))
+                .and_then(Process::parent)
+                .or_else(|| self.process_tree.get(&pid)?.parent_id)
+        });
+
+        run_scope::find_run_scope(&self.run_scopes, &ancestors, working_directory)
+            .map(|scope| scope.run.clone())
     }
 
     pub fn poll_processes(
@@ -162,7 +255,8 @@ impl ProcessWatcher {
                             self.seen.get_mut(pid).unwrap().last_update =
                                 ProcLastUpdate::RefreshesRemaining(refresh_count - 1);
                         } else {
-                            self.add_process_metrics(proc, event_logger, None)?;
+                            let run = p.run.clone();
+                            self.add_process_metrics(proc, run.as_ref(), event_logger, None)?;
                             self.seen.get_mut(pid).unwrap().last_update =
                                 ProcLastUpdate::Some(Utc::now());
                         }
@@ -170,7 +264,8 @@ impl ProcessWatcher {
                     }
                     if let ProcLastUpdate::Some(last_update) = p.last_update {
                         if last_update + process_metrics_send_interval < Utc::now() {
-                            self.add_process_metrics(proc, event_logger, None)?;
+                            let run = p.run.clone();
+                            self.add_process_metrics(proc, run.as_ref(), event_logger, None)?;
                             self.seen.get_mut(pid).unwrap().last_update =
                                 ProcLastUpdate::Some(Utc::now());
                         }
@@ -208,6 +303,11 @@ impl ProcessWatcher {
         Ok(())
     }
 
+    /// When the process started, as opposed to when the watcher first saw it.
+    fn process_start_time(proc: &Process) -> DateTime<Utc> {
+        DateTime::from_timestamp(proc.start_time() as i64, 0).unwrap_or_else(Utc::now)
+    }
+
     pub fn build_process_trees(&mut self, system_processes: &HashMap<Pid, Process>) {
         let mut nodes: HashMap<Pid, ProcessTreeNode> = HashMap::new();
 
@@ -217,7 +317,7 @@ impl ProcessWatcher {
                 properties,
                 children: vec![],
                 parent_id: proc.parent(),
-                start_time: DateTime::from_timestamp(proc.start_time() as i64, 0).unwrap(),
+                start_time: Self::process_start_time(proc),
             };
 
             nodes.insert(*pid, node);
@@ -236,6 +336,10 @@ impl ProcessWatcher {
         self.process_tree = nodes
     }
 
+    pub fn get_process_tree(&self) -> &HashMap<Pid, ProcessTreeNode> {
+        &self.process_tree
+    }
+
     pub fn get_parent_processes(
         &self,
         map: &HashMap<Pid, ProcessTreeNode>,
@@ -356,8 +460,19 @@ impl ProcessWatcher {
         short_lived_process: ShortLivedProcessLog,
         event_logger: &mut EventRecorder,
     ) -> Result<()> {
-        let properties = json!(short_lived_process.properties);
-        event_logger.record_event(
+        let reported = &short_lived_process.properties;
+        let pid = reported.tool_pid.parse::<Pid>().ok();
+        // Processes that already exited are attributed through the shell that reported them
+        let run = pid
+            .or_else(|| reported.tool_parent_pid.parse::<Pid>().ok())
+            .and_then(|pid| self.find_run(None, pid, None));
+        let properties = EventAttributes::ToolExecution(ToolExecutionAttributes {
+            properties: short_lived_process.properties.clone(),
+            input_files: None,
+            step: self.active_step(run.as_ref()),
+        });
+        event_logger.record_scoped_event(
+            run.as_ref(),
             EventType::ToolExecution,
             format!(
                 "[{}] Short lived process: {}",
@@ -367,15 +482,20 @@ impl ProcessWatcher {
             None,
         );
 
-        if let Vacant(v) = self
-            .seen
-            .entry(short_lived_process.properties.tool_pid.parse().unwrap())
-        {
+        // Processes that already exited when the command was logged have no pid to track
+        let Some(pid) = pid else {
+            return Ok(());
+        };
+
+        if let Vacant(v) = self.seen.entry(pid) {
             v.insert(Proc {
+                display_name: short_lived_process.command.clone(),
                 name: short_lived_process.command,
+                target: None,
                 start_time: Utc::now(),
                 last_update: ProcLastUpdate::RefreshesRemaining(2),
                 just_started: true,
+                run,
             });
         }
 
@@ -424,13 +544,28 @@ impl ProcessWatcher {
         target: Option<&Target>,
         file_watcher: &FileWatcher,
     ) -> Result<()> {
+        let display_name = if let Some(target) = target {
+            let name = target
+                .get_display_name_object()
+                .get_display_name(proc.name(), proc.cmd());
+
+            name
+        } else {
+            proc.name().to_owned()
+        };
+
+        let run = self.find_run(Some(system), pid, proc.cwd());
+
         self.seen.insert(
             pid,
             Proc {
                 name: proc.name().to_string(),
+                display_name: display_name.clone(),
+                target: target.map(|target| target.match_type.describe()),
                 start_time: Utc::now(),
                 last_update: ProcLastUpdate::RefreshesRemaining(2),
                 just_started: true,
+                run: run.clone(),
             },
         );
 
@@ -441,21 +576,7 @@ impl ProcessWatcher {
 
         let start_time = Utc::now();
 
-        let display_name = if let Some(target) = target {
-            let name = target
-                .get_display_name_object()
-                .get_display_name(proc.name(), proc.cmd());
-
-            name
-        } else {
-            proc.name().to_owned()
-        };
-
-        let mut properties = json!(Self::gather_process_data(
-            &pid,
-            p,
-            Some(display_name.clone())
-        ));
+        let properties = Self::gather_process_data(&pid, p, Some(display_name.clone()));
 
         let cmd_arguments = p.cmd();
         let mut input_files = vec![];
@@ -490,12 +611,15 @@ impl ProcessWatcher {
             }
         }
 
-        properties["input_files"] = serde_json::to_value(input_files)?;
-
-        event_logger.record_event(
+        event_logger.record_scoped_event(
+            run.as_ref(),
             EventType::ToolExecution,
             format!("[{}] Tool process: {}", start_time, &display_name),
-            Some(properties),
+            Some(EventAttributes::ToolExecution(ToolExecutionAttributes {
+                properties,
+                input_files: Some(input_files),
+                step: self.active_step(run.as_ref()),
+            })),
             None,
         );
 
@@ -505,6 +629,7 @@ impl ProcessWatcher {
     fn add_process_metrics(
         &mut self,
         proc: &Process,
+        run: Option<&RecordingRun>,
         event_logger: &mut EventRecorder,
         target: Option<&Target>,
     ) -> Result<()> {
@@ -519,16 +644,13 @@ impl ProcessWatcher {
             proc.name().to_owned()
         };
 
-        let properties = json!(Self::gather_process_data(
-            &pid,
-            proc,
-            Some(display_name.clone())
-        ));
+        let properties = Self::gather_process_data(&pid, proc, Some(display_name.clone()));
 
-        event_logger.record_event(
+        event_logger.record_scoped_event(
+            run,
             EventType::ToolMetricEvent,
             format!("[{}] Tool metric event: {}", start_time, &display_name),
-            Some(properties),
+            Some(EventAttributes::ToolMetric(properties)),
             None,
         );
 
@@ -600,6 +722,97 @@ impl ProcessWatcher {
         }
     }
 
+    /// Live properties of every tracked process that is still running.
+    pub fn tracked_process_properties(&self, system: &System) -> Vec<ProcessProperties> {
+        self.seen
+            .iter()
+            .filter_map(|(pid, proc)| {
+                let process = system.process(*pid)?;
+                Some(Self::gather_process_data(
+                    pid,
+                    process,
+                    Some(proc.display_name.clone()),
+                ))
+            })
+            .collect()
+    }
+
+    /// Every tracked process joined with its live data, oldest first.
+    pub fn tracked_processes(&self, system: &System) -> Vec<TrackedProcess> {
+        let mut processes: Vec<TrackedProcess> = self
+            .seen
+            .iter()
+            .map(|(pid, proc)| {
+                let process = system.process(*pid);
+                TrackedProcess {
+                    pid: pid.as_u32(),
+                    name: proc.name.clone(),
+                    display_name: proc.display_name.clone(),
+                    target: proc.target.clone(),
+                    // Events keep the time the process was detected, `ps` shows when it
+                    // actually started
+                    start_time: process
+                        .map(Self::process_start_time)
+                        .unwrap_or(proc.start_time),
+                    cpu_utilization: process.map(Process::cpu_usage),
+                    memory_rss: process.map(Process::memory),
+                    disk_read_total: process.map(|process| process.disk_usage().total_read_bytes),
+                    disk_write_total: process
+                        .map(|process| process.disk_usage().total_written_bytes),
+                    status: process.map(|process| process_status_to_string(&process.status())),
+                }
+            })
+            .collect();
+
+        processes.sort_by_key(|process| (process.start_time, process.pid));
+        processes
+    }
+
+    /// Tracked processes that are still running.
+    pub fn persisted_processes(&self, system: &System) -> Vec<PersistedProcess> {
+        self.seen
+            .iter()
+            .filter_map(|(pid, proc)| {
+                Some(PersistedProcess {
+                    pid: pid.as_u32(),
+                    process_start_time: system.process(*pid)?.start_time(),
+                    name: proc.name.clone(),
+                    display_name: proc.display_name.clone(),
+                    target: proc.target.clone(),
+                    start_time: proc.start_time,
+                    run: proc.run.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Tracks the processes of a previous daemon that are still running, without recording
+    /// their execution again.
+    pub fn restore_processes(&mut self, system: &System, processes: Vec<PersistedProcess>) {
+        for process in processes {
+            let pid = Pid::from_u32(process.pid);
+            let still_running = system
+                .process(pid)
+                .is_some_and(|running| running.start_time() == process.process_start_time);
+            if !still_running {
+                continue;
+            }
+
+            self.seen.insert(
+                pid,
+                Proc {
+                    name: process.name,
+                    display_name: process.display_name,
+                    target: process.target,
+                    start_time: process.start_time,
+                    last_update: ProcLastUpdate::Some(Utc::now()),
+                    just_started: false,
+                    run: process.run,
+                },
+            );
+        }
+    }
+
     pub fn is_process_alive(&self, system: &System, pid: Pid) -> bool {
         system.process(pid).is_some()
     }
@@ -610,15 +823,16 @@ impl ProcessWatcher {
         proc: &Proc,
         event_logger: &mut EventRecorder,
     ) -> Result<()> {
-        let duration = (Utc::now() - proc.start_time).to_std()?.as_millis();
+        let duration = (Utc::now() - proc.start_time).to_std()?.as_millis() as u64;
 
-        let properties = json!({
-            "tool_name": proc.name,
-            "tool_pid": pid.to_string(),
-            "duration": duration
+        let properties = EventAttributes::FinishedTool(FinishedToolAttributes {
+            tool_name: proc.name.clone(),
+            tool_pid: pid.to_string(),
+            duration,
         });
 
-        event_logger.record_event(
+        event_logger.record_scoped_event(
+            proc.run.as_ref(),
             EventType::FinishedToolExecution,
             format!("[{}] {} exited", Utc::now(), &proc.name),
             Some(properties),
@@ -640,6 +854,10 @@ impl ProcessWatcher {
     pub fn is_empty(&self) -> bool {
         self.seen.is_empty()
     }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
 }
 
 #[cfg(test)]
@@ -718,4 +936,112 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_tracked_processes() -> Result<()> {
+        let mut process_watcher = ProcessWatcher::new(vec![]);
+        let mut event_logger = EventRecorder::new();
+        let system = System::new_all();
+        let own_pid = sysinfo::get_current_pid().unwrap();
+
+        for (pid, command) in [(own_pid.to_string(), "own"), ("".to_string(), "exited")] {
+            process_watcher.fill_logs_with_short_lived_process(
+                ShortLivedProcessLog {
+                    command: command.to_string(),
+                    timestamp: Utc::now().to_string(),
+                    properties: ProcessProperties {
+                        tool_pid: pid,
+                        ..Default::default()
+                    },
+                },
+                &mut event_logger,
+            )?;
+        }
+        process_watcher.seen.insert(
+            Pid::from_u32(u32::MAX),
+            Proc {
+                name: "gone".to_string(),
+                display_name: "gone".to_string(),
+                target: Some("process name gone".to_string()),
+                start_time: Utc::now(),
+                last_update: ProcLastUpdate::RefreshesRemaining(1),
+                just_started: false,
+                run: None,
+            },
+        );
+
+        let processes = process_watcher.tracked_processes(&system);
+
+        // The process without a pid isn't tracked
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes[0].pid, own_pid.as_u32());
+        assert_eq!(processes[0].display_name, "own");
+        assert!(processes[0].target.is_none());
+        assert!(processes[0].memory_rss.is_some());
+        assert!(processes[0].status.is_some());
+
+        assert_eq!(processes[1].target.as_deref(), Some("process name gone"));
+        assert!(processes[1].status.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tracked_processes_report_process_start_time() -> Result<()> {
+        let mut process_watcher = ProcessWatcher::new(vec![]);
+        let mut event_logger = EventRecorder::new();
+        let system = System::new_all();
+        let own_pid = sysinfo::get_current_pid().unwrap();
+        let own_start_time = system.process(own_pid).unwrap().start_time();
+
+        process_watcher.fill_logs_with_short_lived_process(
+            ShortLivedProcessLog {
+                command: "own".to_string(),
+                timestamp: Utc::now().to_string(),
+                properties: ProcessWatcher::gather_process_data(
+                    &own_pid,
+                    system.process(own_pid).unwrap(),
+                    None,
+                ),
+            },
+            &mut event_logger,
+        )?;
+
+        let processes = process_watcher.tracked_processes(&system);
+
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].start_time.timestamp() as u64, own_start_time);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_processes() {
+        let system = System::new_all();
+        let own_pid = sysinfo::get_current_pid().unwrap();
+        let own_start_time = system.process(own_pid).unwrap().start_time();
+
+        let persisted = |process_start_time| PersistedProcess {
+            pid: own_pid.as_u32(),
+            process_start_time,
+            name: "own".to_string(),
+            display_name: "own".to_string(),
+            target: None,
+            start_time: Utc::now(),
+            run: Some(RecordingRun {
+                id: "run-id".to_string(),
+                name: "run".to_string(),
+            }),
+        };
+
+        // A later process reusing the pid isn't the one that was tracked
+        let mut process_watcher = ProcessWatcher::new(vec![]);
+        process_watcher.restore_processes(&system, vec![persisted(own_start_time + 1)]);
+        assert!(process_watcher.is_empty());
+
+        let tracked = persisted(own_start_time);
+        process_watcher.restore_processes(&system, vec![tracked.clone()]);
+        assert_eq!(process_watcher.persisted_processes(&system), [tracked]);
+        assert!(!process_watcher.seen[&own_pid].just_started);
+    }
 }
@@ -0,0 +1,327 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::process_watcher::ProcessProperties;
+use crate::tracer_client::TracerClient;
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const MAX_REQUEST_HEAD_SIZE: usize = 8 * 1024;
+
+// (attribute in the system metrics object, metric name, help)
+const SYSTEM_GAUGES: &[(&str, &str, &str)] = &[
+    (
+        "system_memory_total",
+        "tracer_system_memory_total_bytes",
+        "Total memory",
+    ),
+    (
+        "system_memory_used",
+        "tracer_system_memory_used_bytes",
+        "Used memory",
+    ),
+    (
+        "system_memory_available",
+        "tracer_system_memory_available_bytes",
+        "Available memory",
+    ),
+    (
+        "system_memory_utilization",
+        "tracer_system_memory_utilization_percent",
+        "Memory utilization",
+    ),
+    (
+        "system_memory_swap_total",
+        "tracer_system_swap_total_bytes",
+        "Total swap",
+    ),
+    (
+        "system_memory_swap_used",
+        "tracer_system_swap_used_bytes",
+        "Used swap",
+    ),
+    (
+        "system_cpu_utilization",
+        "tracer_system_cpu_utilization_percent",
+        "CPU utilization",
+    ),
+];
+
+const DISK_GAUGES: &[(&str, &str, &str)] = &[
+    (
+        "disk_total_space",
+        "tracer_system_disk_total_bytes",
+        "Total disk space",
+    ),
+    (
+        "disk_used_space",
+        "tracer_system_disk_used_bytes",
+        "Used disk space",
+    ),
+    (
+        "disk_available_space",
+        "tracer_system_disk_available_bytes",
+        "Available disk space",
+    ),
+    (
+        "disk_utilization",
+        "tracer_system_disk_utilization_percent",
+        "Disk utilization",
+    ),
+];
+
+const TOOL_GAUGES: &[(&str, &str, &str)] = &[
+    (
+        "process_cpu_utilization",
+        "tracer_tool_cpu_utilization_percent",
+        "CPU utilization of a tracked tool",
+    ),
+    (
+        "process_memory_usage",
+        "tracer_tool_memory_usage_bytes",
+        "Resident memory of a tracked tool",
+    ),
+    (
+        "process_memory_virtual",
+        "tracer_tool_memory_virtual_bytes",
+        "Virtual memory of a tracked tool",
+    ),
+    (
+        "process_disk_usage_read_total",
+        "tracer_tool_disk_read_bytes",
+        "Bytes read by a tracked tool",
+    ),
+    (
+        "process_disk_usage_write_total",
+        "tracer_tool_disk_written_bytes",
+        "Bytes written by a tracked tool",
+    ),
+    (
+        "process_run_time",
+        "tracer_tool_run_time_seconds",
+        "Run time of a tracked tool",
+    ),
+];
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Renders the system metrics object and the tracked tools in the Prometheus text format.
+pub fn render_metrics(
+    system_metrics: &Value,
+    tools: &[ProcessProperties],
+    run_id: Option<&str>,
+) -> String {
+    let mut output = String::new();
+    let run_id = run_id.unwrap_or("");
+
+    for (attribute, name, help) in SYSTEM_GAUGES {
+        let Some(value) = system_metrics[attribute].as_f64() else {
+            continue;
+        };
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} gauge", name);
+        let _ = writeln!(
+            output,
+            "{}{} {}",
+            name,
+            format_labels(&[("run_id", run_id)]),
+            value
+        );
+    }
+
+    if let Some(disks) = system_metrics["system_disk_io"].as_object() {
+        for (attribute, name, help) in DISK_GAUGES {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            for (disk, disk_metrics) in disks {
+                let Some(value) = disk_metrics[attribute].as_f64() else {
+                    continue;
+                };
+                let labels = format_labels(&[("disk", disk), ("run_id", run_id)]);
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+    }
+
+    if !tools.is_empty() {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|tool| serde_json::to_value(tool).unwrap_or_default())
+            .collect();
+
+        for (attribute, name, help) in TOOL_GAUGES {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            for tool in &tools {
+                let Some(value) = tool[attribute].as_f64() else {
+                    continue;
+                };
+                let labels = format_labels(&[
+                    ("tool_name", tool["tool_name"].as_str().unwrap_or("")),
+                    ("pid", tool["tool_pid"].as_str().unwrap_or("")),
+                    ("run_id", run_id),
+                ]);
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+    }
+
+    output
+}
+
+async fn read_request_path(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut buffer = vec![];
+    let mut chunk = [0; 1024];
+
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buffer.len() > MAX_REQUEST_HEAD_SIZE {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&buffer);
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => Ok(Some(path.to_string())),
+        _ => Ok(None),
+    }
+}
+
+async fn handle_connection(
+    tracer_client: Arc<Mutex<TracerClient>>,
+    mut stream: TcpStream,
+) -> Result<()> {
+    let path = read_request_path(&mut stream).await?;
+
+    let response = match path
+        .as_deref()
+        .map(|path| path.split('?').next().unwrap_or(path))
+    {
+        Some(METRICS_PATH) => {
+            let body = tracer_client.lock().await.render_prometheus_metrics();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                CONTENT_TYPE,
+                body.len(),
+                body
+            )
+        }
+        Some(_) => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+        None => {
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+pub async fn run_prometheus_server(
+    tracer_client: Arc<Mutex<TracerClient>>,
+    listen_address: String,
+    cancellation_token: CancellationToken,
+) -> Result<()> {
+    let listener = TcpListener::bind(&listen_address)
+        .await
+        .with_context(|| format!("Failed to bind prometheus listener to {}", listen_address))?;
+
+    loop {
+        let (stream, _) = tokio::select! {
+            _ = cancellation_token.cancelled() => return Ok(()),
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    eprintln!("[{}] Failed to accept prometheus connection: {}", Utc::now(), error);
+                    continue;
+                }
+            },
+        };
+
+        tokio::spawn(handle_connection(tracer_client.clone(), stream));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_metrics() {
+        let system_metrics = json!({
+            "events_name": "global_system_metrics",
+            "system_memory_total": 1024,
+            "system_memory_used": 512,
+            "system_cpu_utilization": 12.5,
+            "system_disk_io": {
+                "/dev/sda1": { "disk_used_space": 100, "disk_utilization": 50.0 }
+            }
+        });
+
+        let tools = vec![ProcessProperties {
+            tool_name: "STAR \"aligner\"".to_string(),
+            tool_pid: "42".to_string(),
+            process_memory_usage: 2048,
+            process_cpu_utilization: 99.5,
+            ..Default::default()
+        }];
+
+        let output = render_metrics(&system_metrics, &tools, Some("run-1"));
+
+        assert!(output.contains("# TYPE tracer_system_memory_total_bytes gauge"));
+        assert!(output.contains("tracer_system_memory_total_bytes{run_id=\"run-1\"} 1024"));
+        assert!(output.contains("tracer_system_cpu_utilization_percent{run_id=\"run-1\"} 12.5"));
+        assert!(output
+            .contains("tracer_system_disk_used_bytes{disk=\"/dev/sda1\",run_id=\"run-1\"} 100"));
+        assert!(output.contains(
+            "tracer_tool_memory_usage_bytes{tool_name=\"STAR \\\"aligner\\\"\",pid=\"42\",run_id=\"run-1\"} 2048"
+        ));
+        assert!(!output.contains("tracer_system_swap_total_bytes"));
+    }
+
+    #[test]
+    fn test_render_metrics_without_run_or_tools() {
+        let output = render_metrics(&json!({ "system_memory_used": 1 }), &[], None);
+
+        assert_eq!(
+            output,
+            "# HELP tracer_system_memory_used_bytes Used memory\n\
+             # TYPE tracer_system_memory_used_bytes gauge\n\
+             tracer_system_memory_used_bytes{run_id=\"\"} 1\n"
+        );
+    }
+}
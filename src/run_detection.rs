@@ -0,0 +1,204 @@
+// src/run_detection.rs
+//! When the daemon starts and ends runs on its own, so unattended pipelines get separate runs
+//! without anyone calling `tracer start`.
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sysinfo::Pid;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunDetection {
+    /// Runs are started with `tracer start` and when the daemon starts
+    #[default]
+    Manual,
+    /// A run ends once no tool ran for `new_run_pause_ms`
+    Inactivity,
+    /// A run ends when the process that launched its first tool exits
+    ParentProcess,
+}
+
+/// What the daemon knows about the current run and the tools it tracks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunObservation {
+    pub has_run: bool,
+    /// Time since a tool was last tracked in the current run
+    pub idle: Duration,
+    /// Process that launched the current run's tools, once known
+    pub parent_pid: Option<Pid>,
+    pub parent_alive: bool,
+    /// Best guess of the process that launched the tracked tools
+    pub candidate_parent_pid: Option<Pid>,
+    pub tracking_processes: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunAction {
+    Keep,
+    Start,
+    End { reason: &'static str },
+    SetParent(Pid),
+}
+
+/// Decides what to do with the current run. New runs start with the first tool seen after
+/// the previous run ended, so runs are never started only to sit empty.
+pub fn decide_run_action(
+    mode: RunDetection,
+    observation: &RunObservation,
+    pause: Duration,
+) -> RunAction {
+    if mode == RunDetection::Manual {
+        return RunAction::Keep;
+    }
+
+    if !observation.has_run {
+        return if observation.tracking_processes {
+            RunAction::Start
+        } else {
+            RunAction::Keep
+        };
+    }
+
+    match mode {
+        RunDetection::Manual => RunAction::Keep,
+        RunDetection::Inactivity if observation.idle > pause => RunAction::End {
+            reason: "Run ended due to inactivity",
+        },
+        RunDetection::Inactivity => RunAction::Keep,
+        RunDetection::ParentProcess => {
+            match (observation.parent_pid, observation.candidate_parent_pid) {
+                (Some(_), _) if !observation.parent_alive => RunAction::End {
+                    reason: "Run ended due to parent process termination",
+                },
+                (None, Some(candidate)) => RunAction::SetParent(candidate),
+                _ => RunAction::Keep,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAUSE: Duration = Duration::from_secs(600);
+
+    fn running() -> RunObservation {
+        RunObservation {
+            has_run: true,
+            idle: Duration::ZERO,
+            parent_pid: None,
+            parent_alive: false,
+            candidate_parent_pid: None,
+            tracking_processes: true,
+        }
+    }
+
+    #[test]
+    fn test_manual_mode_never_changes_runs() {
+        let idle = RunObservation {
+            idle: PAUSE * 2,
+            ..running()
+        };
+        let no_run = RunObservation {
+            has_run: false,
+            ..running()
+        };
+
+        assert_eq!(
+            decide_run_action(RunDetection::Manual, &idle, PAUSE),
+            RunAction::Keep
+        );
+        assert_eq!(
+            decide_run_action(RunDetection::Manual, &no_run, PAUSE),
+            RunAction::Keep
+        );
+    }
+
+    #[test]
+    fn test_runs_start_with_the_first_tool() {
+        for mode in [RunDetection::Inactivity, RunDetection::ParentProcess] {
+            let waiting = RunObservation {
+                has_run: false,
+                tracking_processes: false,
+                ..running()
+            };
+            assert_eq!(decide_run_action(mode, &waiting, PAUSE), RunAction::Keep);
+
+            let tool_seen = RunObservation {
+                has_run: false,
+                ..running()
+            };
+            assert_eq!(decide_run_action(mode, &tool_seen, PAUSE), RunAction::Start);
+        }
+    }
+
+    #[test]
+    fn test_inactivity_ends_runs_after_the_pause() {
+        let active = RunObservation {
+            idle: PAUSE,
+            ..running()
+        };
+        assert_eq!(
+            decide_run_action(RunDetection::Inactivity, &active, PAUSE),
+            RunAction::Keep
+        );
+
+        let idle = RunObservation {
+            idle: PAUSE + Duration::from_millis(1),
+            ..running()
+        };
+        assert_eq!(
+            decide_run_action(RunDetection::Inactivity, &idle, PAUSE),
+            RunAction::End {
+                reason: "Run ended due to inactivity"
+            }
+        );
+    }
+
+    #[test]
+    fn test_parent_process_ends_runs_when_it_exits() {
+        let parent = Pid::from(42);
+
+        let unknown_parent = RunObservation {
+            candidate_parent_pid: Some(parent),
+            ..running()
+        };
+        assert_eq!(
+            decide_run_action(RunDetection::ParentProcess, &unknown_parent, PAUSE),
+            RunAction::SetParent(parent)
+        );
+
+        let alive = RunObservation {
+            parent_pid: Some(parent),
+            parent_alive: true,
+            idle: PAUSE * 2,
+            ..running()
+        };
+        assert_eq!(
+            decide_run_action(RunDetection::ParentProcess, &alive, PAUSE),
+            RunAction::Keep
+        );
+
+        let exited = RunObservation {
+            parent_pid: Some(parent),
+            parent_alive: false,
+            ..running()
+        };
+        assert_eq!(
+            decide_run_action(RunDetection::ParentProcess, &exited, PAUSE),
+            RunAction::End {
+                reason: "Run ended due to parent process termination"
+            }
+        );
+
+        let no_candidate = RunObservation {
+            tracking_processes: false,
+            ..running()
+        };
+        assert_eq!(
+            decide_run_action(RunDetection::ParentProcess, &no_candidate, PAUSE),
+            RunAction::Keep
+        );
+    }
+}
@@ -0,0 +1,172 @@
+// src/run_scope.rs
+//! Runs anchored to part of the system, so several pipelines on the same node each get
+//! their own run. A tool belongs to the run anchored to its nearest ancestor, or else to the
+//! deepest directory containing its working directory, and to the daemon's run otherwise.
+use std::path::Path;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sysinfo::Pid;
+
+use crate::event_recorder::RecordingRun;
+
+/// Ancestors walked before giving up, in case the process table has a cycle.
+const MAX_ANCESTOR_DEPTH: usize = 64;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunAnchor {
+    /// Tools started below this process, e.g. the shell that called `tracer start`
+    RootPid(u32),
+    /// Tools running in this directory or below it
+    WorkingDirectory(String),
+}
+
+impl RunAnchor {
+    pub fn describe(&self) -> String {
+        match self {
+            RunAnchor::RootPid(pid) => format!("pid {}", pid),
+            RunAnchor::WorkingDirectory(directory) => format!("directory {}", directory),
+        }
+    }
+}
+
+/// A run tools are recorded in when they match its anchor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunScope {
+    pub anchor: RunAnchor,
+    pub run: RecordingRun,
+}
+
+/// The process and its ancestors, nearest first.
+pub fn ancestors(pid: Pid, parent_of: impl Fn(Pid) -> Option<Pid>) -> Vec<Pid> {
+    let mut ancestors = vec![pid];
+    let mut current = pid;
+
+    while ancestors.len() < MAX_ANCESTOR_DEPTH {
+        match parent_of(current) {
+            Some(parent) if !ancestors.contains(&parent) => {
+                ancestors.push(parent);
+                current = parent;
+            }
+            _ => break,
+        }
+    }
+
+    ancestors
+}
+
+/// Finds the run of a tool from its ancestors, nearest first, and its working directory.
+pub fn find_run_scope<'a>(
+    scopes: &'a [RunScope],
+    ancestors: &[Pid],
+    working_directory: Option<&Path>,
+) -> Option<&'a RunScope> {
+    let by_ancestor = ancestors.iter().find_map(|pid| {
+        scopes
+            .iter()
+            .find(|scope| scope.anchor == RunAnchor::RootPid(pid.as_u32()))
+    });
+    if by_ancestor.is_some() {
+        return by_ancestor;
+    }
+
+    let working_directory = working_directory?;
+    scopes
+        .iter()
+        .filter_map(|scope| match &scope.anchor {
+            RunAnchor::WorkingDirectory(directory) => working_directory
+                .starts_with(directory)
+                .then_some((Path::new(directory).components().count(), scope)),
+            RunAnchor::RootPid(_) => None,
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, scope)| scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn scope(anchor: RunAnchor, id: &str) -> RunScope {
+        RunScope {
+            anchor,
+            run: RecordingRun {
+                id: id.to_string(),
+                name: id.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let parents: HashMap<Pid, Pid> = [(30, 20), (20, 10), (10, 1)]
+            .into_iter()
+            .map(|(pid, parent)| (Pid::from(pid), Pid::from(parent)))
+            .collect();
+
+        assert_eq!(
+            ancestors(Pid::from(30), |pid| parents.get(&pid).copied()),
+            [30, 20, 10, 1].map(Pid::from)
+        );
+        assert_eq!(ancestors(Pid::from(5), |_| None), [Pid::from(5)]);
+
+        // A cycle in a stale process table doesn't loop forever
+        let cyclic = |pid: Pid| Some(if pid == Pid::from(2) { 3 } else { 2 }.into());
+        assert_eq!(ancestors(Pid::from(2), cyclic), [2, 3].map(Pid::from));
+    }
+
+    #[test]
+    fn test_find_run_scope_by_nearest_ancestor() {
+        let scopes = [
+            scope(RunAnchor::RootPid(10), "outer"),
+            scope(RunAnchor::RootPid(20), "inner"),
+            scope(RunAnchor::WorkingDirectory("/data".to_string()), "data"),
+        ];
+        let tool_ancestors = [30, 20, 10, 1].map(Pid::from);
+
+        let found = find_run_scope(&scopes, &tool_ancestors, Some(Path::new("/data/a")));
+        assert_eq!(found.unwrap().run.id, "inner");
+
+        let other_tree = [40, 1].map(Pid::from);
+        let found = find_run_scope(&scopes, &other_tree, Some(Path::new("/data/a")));
+        assert_eq!(found.unwrap().run.id, "data");
+
+        assert_eq!(find_run_scope(&scopes, &other_tree, None), None);
+    }
+
+    #[test]
+    fn test_find_run_scope_by_deepest_directory() {
+        let scopes = [
+            scope(RunAnchor::WorkingDirectory("/data".to_string()), "data"),
+            scope(
+                RunAnchor::WorkingDirectory("/data/project".to_string()),
+                "project",
+            ),
+        ];
+        let tool = [Pid::from(30)];
+
+        let found = find_run_scope(&scopes, &tool, Some(Path::new("/data/project/sample")));
+        assert_eq!(found.unwrap().run.id, "project");
+
+        let found = find_run_scope(&scopes, &tool, Some(Path::new("/data/other")));
+        assert_eq!(found.unwrap().run.id, "data");
+
+        // Only whole path components match
+        let found = find_run_scope(&scopes, &tool, Some(Path::new("/data-old")));
+        assert_eq!(found, None);
+    }
+
+    #[test]
+    fn test_anchor_wire_format() {
+        assert_eq!(
+            serde_json::to_value(RunAnchor::RootPid(42)).unwrap(),
+            serde_json::json!({ "root_pid": 42 })
+        );
+        assert_eq!(
+            serde_json::to_value(RunAnchor::WorkingDirectory("/data".to_string())).unwrap(),
+            serde_json::json!({ "working_directory": "/data" })
+        );
+    }
+}
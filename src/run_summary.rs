@@ -0,0 +1,452 @@
+// src/run_summary.rs
+//! Summary of a run written next to the workflow when it ends, so every run leaves a
+//! self-contained record without going through the service.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::event_attributes::EventAttributes;
+use crate::event_recorder::{Event, RecordingRun};
+use crate::process_watcher::ProcessProperties;
+
+/// Resources used by every invocation of a tool during a run.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ToolSummary {
+    pub tool_name: String,
+    pub invocations: u64,
+    /// Summed over the invocations, up to the end of the run for tools still running
+    pub wall_time_ms: u64,
+    /// Estimated from the CPU utilization of the metric samples
+    pub cpu_seconds: f64,
+    /// Largest resident memory of a single invocation, in bytes
+    pub peak_rss: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Time spent in a step marked with `tracer step`, over every time it ran.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StepSummary {
+    pub step_path: String,
+    pub wall_time_ms: u64,
+    /// Tool invocations started while the step was the innermost running one
+    pub tool_invocations: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RunSummary {
+    pub run_id: String,
+    pub run_name: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    /// Sorted by tool name
+    pub tools: Vec<ToolSummary>,
+    /// In the order the steps first started
+    #[serde(default)]
+    pub steps: Vec<StepSummary>,
+    pub files_uploaded: Vec<String>,
+    /// Number of syslog errors seen during the run, by error
+    pub syslog_errors: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InvocationTally {
+    tool_name: String,
+    started_at: DateTime<Utc>,
+    last_sample: DateTime<Utc>,
+    cpu_seconds: f64,
+    peak_rss: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+    wall_time_ms: Option<u64>,
+}
+
+impl InvocationTally {
+    fn sample(&mut self, properties: &ProcessProperties, timestamp: DateTime<Utc>) {
+        // The utilization is averaged since the previous refresh, in percent of one core
+        let elapsed = (timestamp - self.last_sample).num_milliseconds().max(0) as f64 / 1000.;
+        self.cpu_seconds += properties.process_cpu_utilization as f64 / 100. * elapsed;
+        self.last_sample = timestamp;
+
+        self.peak_rss = self.peak_rss.max(properties.process_memory_usage);
+        self.bytes_read = self
+            .bytes_read
+            .max(properties.process_disk_usage_read_total);
+        self.bytes_written = self
+            .bytes_written
+            .max(properties.process_disk_usage_write_total);
+    }
+}
+
+/// Adds up the events of one run until it ends.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RunTally {
+    invocations: Vec<InvocationTally>,
+    /// Invocation of each running pid
+    running: HashMap<String, usize>,
+    #[serde(default)]
+    steps: Vec<StepSummary>,
+    files_uploaded: Vec<String>,
+    syslog_errors: BTreeMap<String, u64>,
+}
+
+impl RunTally {
+    pub fn record(&mut self, event: &Event) {
+        match &event.attributes {
+            Some(EventAttributes::ToolExecution(attributes)) => {
+                let properties = &attributes.properties;
+                let mut invocation = InvocationTally {
+                    tool_name: properties.tool_name.clone(),
+                    started_at: event.timestamp,
+                    last_sample: event.timestamp,
+                    cpu_seconds: 0.,
+                    peak_rss: 0,
+                    bytes_read: 0,
+                    bytes_written: 0,
+                    wall_time_ms: None,
+                };
+                invocation.sample(properties, event.timestamp);
+
+                if let Some(step) = &attributes.step {
+                    self.step_mut(step).tool_invocations += 1;
+                }
+
+                // Short-lived processes that already exited have no pid to follow
+                if !properties.tool_pid.is_empty() {
+                    self.running
+                        .insert(properties.tool_pid.clone(), self.invocations.len());
+                }
+                self.invocations.push(invocation);
+            }
+            Some(EventAttributes::ToolMetric(properties)) => {
+                if let Some(&index) = self.running.get(&properties.tool_pid) {
+                    self.invocations[index].sample(properties, event.timestamp);
+                }
+            }
+            Some(EventAttributes::FinishedTool(attributes)) => {
+                if let Some(index) = self.running.remove(&attributes.tool_pid) {
+                    self.invocations[index].wall_time_ms = Some(attributes.duration);
+                }
+            }
+            Some(EventAttributes::Step(attributes)) => {
+                let step = self.step_mut(&attributes.step_path);
+                step.wall_time_ms += attributes.duration.unwrap_or_default();
+            }
+            Some(EventAttributes::SyslogError(attributes)) => {
+                *self
+                    .syslog_errors
+                    .entry(attributes.error_display_name.clone())
+                    .or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn step_mut(&mut self, step_path: &str) -> &mut StepSummary {
+        let index = match self
+            .steps
+            .iter()
+            .position(|step| step.step_path == step_path)
+        {
+            Some(index) => index,
+            None => {
+                self.steps.push(StepSummary {
+                    step_path: step_path.to_string(),
+                    ..Default::default()
+                });
+                self.steps.len() - 1
+            }
+        };
+        &mut self.steps[index]
+    }
+
+    pub fn record_upload(&mut self, path: String) {
+        self.files_uploaded.push(path);
+    }
+
+    pub fn summarize(
+        self,
+        run: &RecordingRun,
+        started_at: DateTime<Utc>,
+        ended_at: DateTime<Utc>,
+    ) -> RunSummary {
+        let mut tools: BTreeMap<String, ToolSummary> = BTreeMap::new();
+
+        for invocation in self.invocations {
+            let wall_time_ms = invocation.wall_time_ms.unwrap_or_else(|| {
+                (ended_at - invocation.started_at).num_milliseconds().max(0) as u64
+            });
+
+            let tool = tools
+                .entry(invocation.tool_name.clone())
+                .or_insert_with(|| ToolSummary {
+                    tool_name: invocation.tool_name,
+                    ..Default::default()
+                });
+            tool.invocations += 1;
+            tool.wall_time_ms += wall_time_ms;
+            tool.cpu_seconds += invocation.cpu_seconds;
+            tool.peak_rss = tool.peak_rss.max(invocation.peak_rss);
+            tool.bytes_read += invocation.bytes_read;
+            tool.bytes_written += invocation.bytes_written;
+        }
+
+        RunSummary {
+            run_id: run.id.clone(),
+            run_name: run.name.clone(),
+            started_at,
+            ended_at,
+            tools: tools.into_values().collect(),
+            steps: self.steps,
+            files_uploaded: self.files_uploaded,
+            syslog_errors: self.syslog_errors,
+        }
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl RunSummary {
+    pub fn to_markdown(&self) -> String {
+        let duration = (self.ended_at - self.started_at).num_seconds().max(0);
+
+        let mut markdown = format!("# Run summary: {}\n\n", self.run_name);
+        markdown.push_str("| | |\n|---|---|\n");
+        for (label, value) in [
+            ("Run ID", self.run_id.clone()),
+            ("Started at", self.started_at.to_rfc3339()),
+            ("Ended at", self.ended_at.to_rfc3339()),
+            ("Duration", format!("{} s", duration)),
+        ] {
+            let _ = writeln!(markdown, "| {} | {} |", label, value);
+        }
+
+        markdown.push_str("\n## Tools\n\n");
+        if self.tools.is_empty() {
+            markdown.push_str("No tools ran.\n");
+        } else {
+            markdown.push_str(
+                "| Tool | Invocations | Wall time | CPU time | Peak RSS | Read | Written |\n\
+                 |---|---:|---:|---:|---:|---:|---:|\n",
+            );
+            for tool in &self.tools {
+                let _ = writeln!(
+                    markdown,
+                    "| {} | {} | {:.1} s | {:.1} s | {} | {} | {} |",
+                    tool.tool_name,
+                    tool.invocations,
+                    tool.wall_time_ms as f64 / 1000.,
+                    tool.cpu_seconds,
+                    format_bytes(tool.peak_rss),
+                    format_bytes(tool.bytes_read),
+                    format_bytes(tool.bytes_written),
+                );
+            }
+        }
+
+        if !self.steps.is_empty() {
+            markdown.push_str(
+                "\n## Steps\n\n| Step | Wall time | Tool invocations |\n|---|---:|---:|\n",
+            );
+            for step in &self.steps {
+                let _ = writeln!(
+                    markdown,
+                    "| {} | {:.1} s | {} |",
+                    step.step_path,
+                    step.wall_time_ms as f64 / 1000.,
+                    step.tool_invocations,
+                );
+            }
+        }
+
+        markdown.push_str("\n## Files uploaded\n\n");
+        if self.files_uploaded.is_empty() {
+            markdown.push_str("None.\n");
+        }
+        for file in &self.files_uploaded {
+            let _ = writeln!(markdown, "- {}", file);
+        }
+
+        markdown.push_str("\n## Syslog errors\n\n");
+        if self.syslog_errors.is_empty() {
+            markdown.push_str("None.\n");
+        } else {
+            markdown.push_str("| Error | Count |\n|---|---:|\n");
+            for (error, count) in &self.syslog_errors {
+                let _ = writeln!(markdown, "| {} | {} |", error, count);
+            }
+        }
+
+        markdown
+    }
+
+    /// Writes `tracer-run-<run id>.json` and `.md` to `directory`.
+    pub fn write_to(&self, directory: &Path) -> Result<()> {
+        let json_path = directory.join(format!("tracer-run-{}.json", self.run_id));
+        std::fs::write(&json_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", json_path.display()))?;
+
+        let markdown_path = directory.join(format!("tracer-run-{}.md", self.run_id));
+        std::fs::write(&markdown_path, self.to_markdown())
+            .with_context(|| format!("Failed to write {}", markdown_path.display()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_attributes::{
+        FinishedToolAttributes, StepAttributes, ToolExecutionAttributes,
+    };
+    use crate::event_recorder::{EventRecorder, EventType};
+    use chrono::TimeDelta;
+
+    fn properties(pid: &str, cpu: f32, memory: u64, read: u64) -> ProcessProperties {
+        ProcessProperties {
+            tool_name: "bwa".to_string(),
+            tool_pid: pid.to_string(),
+            process_cpu_utilization: cpu,
+            process_memory_usage: memory,
+            process_disk_usage_read_total: read,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize_tools() {
+        let started_at = DateTime::parse_from_rfc3339("2024-08-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let at = |seconds| Some(started_at + TimeDelta::seconds(seconds));
+
+        let mut recorder = EventRecorder::new();
+        let mut record = |event_type, attributes, timestamp| {
+            recorder.record_event(event_type, String::new(), Some(attributes), timestamp)
+        };
+        record(
+            EventType::ToolExecution,
+            EventAttributes::ToolExecution(ToolExecutionAttributes {
+                properties: properties("10", 0., 100, 0),
+                input_files: None,
+                step: Some("align".to_string()),
+            }),
+            at(0),
+        );
+        record(
+            EventType::ToolMetricEvent,
+            EventAttributes::ToolMetric(properties("10", 200., 300, 50)),
+            at(10),
+        );
+        record(
+            EventType::FinishedToolExecution,
+            EventAttributes::FinishedTool(FinishedToolAttributes {
+                tool_name: "bwa".to_string(),
+                tool_pid: "10".to_string(),
+                duration: 12_000,
+            }),
+            at(12),
+        );
+        record(
+            EventType::StepFinished,
+            EventAttributes::Step(StepAttributes {
+                step_name: "align".to_string(),
+                step_path: "align".to_string(),
+                duration: Some(15_000),
+            }),
+            at(15),
+        );
+        // Still running when the run ends
+        record(
+            EventType::ToolExecution,
+            EventAttributes::ToolExecution(ToolExecutionAttributes {
+                properties: properties("11", 0., 200, 20),
+                input_files: None,
+                step: None,
+            }),
+            at(20),
+        );
+
+        let mut tally = RunTally::default();
+        for event in recorder.get_events() {
+            tally.record(event);
+        }
+        tally.record_upload("/data/out.bam".to_string());
+
+        let run = RecordingRun {
+            id: "run-id".to_string(),
+            name: "batch-117".to_string(),
+        };
+        let summary = tally.summarize(&run, started_at, started_at + TimeDelta::seconds(30));
+
+        assert_eq!(
+            summary.tools,
+            [ToolSummary {
+                tool_name: "bwa".to_string(),
+                invocations: 2,
+                wall_time_ms: 22_000,
+                cpu_seconds: 20.,
+                peak_rss: 300,
+                bytes_read: 70,
+                bytes_written: 0,
+            }]
+        );
+        assert_eq!(
+            summary.steps,
+            [StepSummary {
+                step_path: "align".to_string(),
+                wall_time_ms: 15_000,
+                tool_invocations: 1,
+            }]
+        );
+        assert_eq!(summary.files_uploaded, ["/data/out.bam"]);
+
+        let markdown = summary.to_markdown();
+        assert!(markdown.starts_with("# Run summary: batch-117\n"));
+        assert!(markdown.contains("| bwa | 2 | 22.0 s | 20.0 s | 300 B | 70 B | 0 B |\n"));
+        assert!(markdown.contains("| align | 15.0 s | 1 |\n"));
+        assert!(markdown.contains("- /data/out.bam\n"));
+    }
+
+    #[test]
+    fn test_write_to() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let summary = RunTally::default().summarize(
+            &RecordingRun {
+                id: "run-id".to_string(),
+                name: "run".to_string(),
+            },
+            Utc::now(),
+            Utc::now(),
+        );
+
+        summary.write_to(directory.path())?;
+
+        let json = std::fs::read_to_string(directory.path().join("tracer-run-run-id.json"))?;
+        assert_eq!(serde_json::from_str::<RunSummary>(&json)?, summary);
+        let markdown = std::fs::read_to_string(directory.path().join("tracer-run-run-id.md"))?;
+        assert!(markdown.contains("No tools ran.\n"));
+
+        Ok(())
+    }
+}
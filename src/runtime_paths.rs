@@ -0,0 +1,286 @@
+// src/runtime_paths.rs
+//! Location of the files a daemon shares with its clients.
+//!
+//! Everything lives in one runtime directory, so users on a shared machine don't collide and
+//! several daemons, e.g. one per project, can run side by side with their own directories.
+//! By default the spool, the file cache and the saved state go to a state directory instead,
+//! as `$XDG_RUNTIME_DIR` is emptied when the user logs out.
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+const RUNTIME_SUBDIRECTORY: &str = "tracer";
+const FALLBACK_RUNTIME_PARENT: &str = "/tmp";
+const STATE_SUBDIRECTORY: &str = "tracer";
+const FALLBACK_STATE_HOME_FROM_HOME: &str = ".local/state";
+
+const PID_FILE_NAME: &str = "tracerd.pid";
+const SOCKET_FILE_NAME: &str = "tracerd.sock";
+const STDOUT_FILE_NAME: &str = "tracerd.out";
+const STDERR_FILE_NAME: &str = "tracerd.err";
+const FILE_CACHE_DIR_NAME: &str = "tracerd_cache";
+const SPOOL_DIR_NAME: &str = "tracerd_spool";
+const STATE_FILE_NAME: &str = "tracerd_state.json";
+const INTERCEPTOR_STDOUT_FILE_NAME: &str = "tracerd-stdout";
+const INTERCEPTOR_STDERR_FILE_NAME: &str = "tracerd-stderr";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimePaths {
+    pub directory: String,
+    /// Directory of the files that must outlive the session, the runtime directory when set
+    pub state_directory: String,
+    pub pid_file: String,
+    pub socket: String,
+    pub stdout_file: String,
+    pub stderr_file: String,
+    pub file_cache_dir: String,
+    pub spool_dir: String,
+    /// Runs and watchers saved for the next daemon started in this directory
+    pub state_file: String,
+    /// Output of the shells set up by `tracer apply-bashrc`
+    pub interceptor_stdout_file: String,
+    pub interceptor_stderr_file: String,
+}
+
+impl RuntimePaths {
+    pub fn new(directory: &Path) -> RuntimePaths {
+        RuntimePaths::with_state_directory(directory, directory)
+    }
+
+    pub fn with_state_directory(directory: &Path, state_directory: &Path) -> RuntimePaths {
+        let path = |name: &str| directory.join(name).to_string_lossy().to_string();
+        let state_path = |name: &str| state_directory.join(name).to_string_lossy().to_string();
+
+        RuntimePaths {
+            directory: directory.to_string_lossy().to_string(),
+            state_directory: state_directory.to_string_lossy().to_string(),
+            pid_file: path(PID_FILE_NAME),
+            socket: path(SOCKET_FILE_NAME),
+            stdout_file: path(STDOUT_FILE_NAME),
+            stderr_file: path(STDERR_FILE_NAME),
+            file_cache_dir: state_path(FILE_CACHE_DIR_NAME),
+            spool_dir: state_path(SPOOL_DIR_NAME),
+            state_file: state_path(STATE_FILE_NAME),
+            interceptor_stdout_file: path(INTERCEPTOR_STDOUT_FILE_NAME),
+            interceptor_stderr_file: path(INTERCEPTOR_STDERR_FILE_NAME),
+        }
+    }
+
+    /// Picks the runtime directory from `--runtime-dir`, then the `runtime_dir` config option,
+    /// then `$XDG_RUNTIME_DIR`, falling back to a per-user directory under `/tmp`.
+    /// Without a configured directory, durable files go to `$XDG_STATE_HOME/tracer`.
+    pub fn resolve(
+        runtime_dir: Option<&str>,
+        config_runtime_dir: Option<&str>,
+        socket: Option<&str>,
+    ) -> Result<RuntimePaths> {
+        let configured_directory = runtime_dir.or(config_runtime_dir);
+        let xdg_runtime_dir = std::env::var("XDG_RUNTIME_DIR").ok();
+        let directory = absolute(&resolve_directory(
+            configured_directory,
+            xdg_runtime_dir.as_deref(),
+            current_uid(),
+        ))?;
+        let state_directory = match configured_directory {
+            Some(_) => None,
+            None => resolve_state_directory(
+                std::env::var("XDG_STATE_HOME").ok().as_deref(),
+                std::env::var("HOME").ok().as_deref(),
+            ),
+        };
+
+        let mut paths = RuntimePaths::with_state_directory(
+            &directory,
+            &absolute(&state_directory.unwrap_or_else(|| directory.clone()))?,
+        );
+        if let Some(socket) = socket {
+            paths.socket = absolute(Path::new(socket))?.to_string_lossy().to_string();
+        }
+
+        Ok(paths)
+    }
+
+    /// Creates the runtime and state directories, readable by their owner only. Directories
+    /// that already exist must belong to the current user and be writable by them alone, so
+    /// another user can't plant a socket or state in them. With a `socket_group`, its members
+    /// may also enter the runtime directory to reach the socket.
+    pub fn create_directory(&self, socket_group: Option<u32>) -> Result<()> {
+        for directory in [&self.state_directory, &self.directory] {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(directory)
+                .with_context(|| format!("Failed to create directory {}", directory))?;
+            check_directory_is_private(Path::new(directory), current_uid())?;
+        }
+
+        if let Some(gid) = socket_group {
+            std::os::unix::fs::chown(&self.directory, None, Some(gid))
+                .with_context(|| format!("Failed to give group {} the runtime directory", gid))?;
+            fs::set_permissions(&self.directory, fs::Permissions::from_mode(0o750))
+                .context("Failed to open the runtime directory to its group")?;
+        }
+        Ok(())
+    }
+}
+
+/// Group the runtime directory and the socket are handed to when `socket_allowed_gids` lets
+/// other users in, the first allowed one.
+pub fn socket_group(allowed_gids: &[u32]) -> Option<u32> {
+    allowed_gids.first().copied()
+}
+
+fn check_directory_is_private(directory: &Path, uid: u32) -> Result<()> {
+    let metadata = fs::metadata(directory)
+        .with_context(|| format!("Failed to read directory {}", directory.display()))?;
+
+    if metadata.uid() != uid {
+        bail!(
+            "Directory {} belongs to uid {}, not to the current user",
+            directory.display(),
+            metadata.uid()
+        );
+    }
+    if metadata.permissions().mode() & 0o022 != 0 {
+        bail!(
+            "Directory {} is writable by other users",
+            directory.display()
+        );
+    }
+    Ok(())
+}
+
+fn resolve_directory(
+    configured_directory: Option<&str>,
+    xdg_runtime_dir: Option<&str>,
+    uid: u32,
+) -> PathBuf {
+    if let Some(directory) = configured_directory {
+        return PathBuf::from(directory);
+    }
+
+    match xdg_runtime_dir.filter(|directory| !directory.is_empty()) {
+        Some(directory) => Path::new(directory).join(RUNTIME_SUBDIRECTORY),
+        None => Path::new(FALLBACK_RUNTIME_PARENT).join(format!("tracer-{}", uid)),
+    }
+}
+
+fn resolve_state_directory(xdg_state_home: Option<&str>, home: Option<&str>) -> Option<PathBuf> {
+    if let Some(directory) = xdg_state_home.filter(|directory| !directory.is_empty()) {
+        return Some(Path::new(directory).join(STATE_SUBDIRECTORY));
+    }
+
+    home.filter(|home| !home.is_empty()).map(|home| {
+        Path::new(home)
+            .join(FALLBACK_STATE_HOME_FROM_HOME)
+            .join(STATE_SUBDIRECTORY)
+    })
+}
+
+/// The daemon changes its working directory, so relative paths are resolved up front.
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+
+    Ok(std::env::current_dir()
+        .context("Failed to resolve the runtime directory")?
+        .join(path))
+}
+
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_directory_precedence() {
+        assert_eq!(
+            resolve_directory(Some("/data/project"), Some("/run/user/1000"), 1000),
+            PathBuf::from("/data/project")
+        );
+        assert_eq!(
+            resolve_directory(None, Some("/run/user/1000"), 1000),
+            PathBuf::from("/run/user/1000/tracer")
+        );
+        assert_eq!(
+            resolve_directory(None, Some(""), 1000),
+            PathBuf::from("/tmp/tracer-1000")
+        );
+        assert_eq!(
+            resolve_directory(None, None, 1001),
+            PathBuf::from("/tmp/tracer-1001")
+        );
+    }
+
+    #[test]
+    fn test_resolve_state_directory() {
+        assert_eq!(
+            resolve_state_directory(Some("/home/user/state"), Some("/home/user")),
+            Some(PathBuf::from("/home/user/state/tracer"))
+        );
+        assert_eq!(
+            resolve_state_directory(Some(""), Some("/home/user")),
+            Some(PathBuf::from("/home/user/.local/state/tracer"))
+        );
+        assert_eq!(resolve_state_directory(None, None), None);
+    }
+
+    #[test]
+    fn test_directory_must_be_private() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let uid = current_uid();
+        fs::set_permissions(directory.path(), fs::Permissions::from_mode(0o700))?;
+
+        assert!(check_directory_is_private(directory.path(), uid).is_ok());
+        assert!(check_directory_is_private(directory.path(), uid + 1).is_err());
+
+        fs::set_permissions(directory.path(), fs::Permissions::from_mode(0o777))?;
+        assert!(check_directory_is_private(directory.path(), uid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_directory_for_a_socket_group() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let paths = RuntimePaths::new(&directory.path().join("runtime"));
+        // SAFETY: getgid has no preconditions and cannot fail
+        let gid = unsafe { libc::getgid() };
+
+        paths.create_directory(Some(gid))?;
+
+        let metadata = fs::metadata(&paths.directory)?;
+        assert_eq!(metadata.gid(), gid);
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+        // Group access doesn't make the directory unsafe to reuse
+        paths.create_directory(Some(gid))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths_share_the_runtime_directory() -> Result<()> {
+        let paths = RuntimePaths::resolve(Some("/data/project"), Some("/ignored"), None)?;
+        assert_eq!(paths.directory, "/data/project");
+        assert_eq!(paths.socket, "/data/project/tracerd.sock");
+        assert_eq!(paths.pid_file, "/data/project/tracerd.pid");
+        assert_eq!(paths.spool_dir, "/data/project/tracerd_spool");
+        assert_eq!(paths.state_file, "/data/project/tracerd_state.json");
+
+        let paths = RuntimePaths::resolve(Some("/data/project"), None, Some("/tmp/other.sock"))?;
+        assert_eq!(paths.socket, "/tmp/other.sock");
+        assert_eq!(paths.pid_file, "/data/project/tracerd.pid");
+
+        let paths = RuntimePaths::resolve(Some("relative"), None, None)?;
+        assert!(Path::new(&paths.directory).is_absolute());
+
+        Ok(())
+    }
+}
@@ -90,3 +90,38 @@ impl StdoutWatcher {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
+
+    #[tokio::test]
+    async fn test_poll_stdout_sends_pending_lines() -> Result<()> {
+        let service = MockService::start().await;
+        let pending_lines = Arc::new(RwLock::new(vec![
+            "first line".to_string(),
+            "second line".to_string(),
+        ]));
+
+        let mut watcher = StdoutWatcher::new();
+        watcher
+            .poll_stdout(service.url(), MOCK_API_KEY, pending_lines.clone(), true)
+            .await?;
+        // Nothing left to send, so no second request
+        watcher
+            .poll_stdout(service.url(), MOCK_API_KEY, pending_lines.clone(), true)
+            .await?;
+
+        assert!(pending_lines.read().await.is_empty());
+
+        let requests = service.requests_to("/stdout-capture");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].json(),
+            json!({ "lines": ["first line", "second line"], "isError": true })
+        );
+
+        Ok(())
+    }
+}
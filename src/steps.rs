@@ -0,0 +1,117 @@
+// src/steps.rs
+//! Named stages of a run marked with `tracer step`, nested like `align/index`. Tools started
+//! while a step runs are tagged with its path.
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const STEP_PATH_SEPARATOR: &str = "/";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActiveStep {
+    pub name: String,
+    pub path: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Steps of a run that are still running, outermost first.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StepStack {
+    steps: Vec<ActiveStep>,
+}
+
+impl StepStack {
+    /// Path of the innermost running step.
+    pub fn path(&self) -> Option<&str> {
+        self.steps.last().map(|step| step.path.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Starts a step nested in the running one.
+    pub fn begin(&mut self, name: &str, started_at: DateTime<Utc>) -> Result<&ActiveStep> {
+        if name.is_empty() || name.contains(STEP_PATH_SEPARATOR) {
+            bail!(
+                "Step names must be non-empty and can't contain {}",
+                STEP_PATH_SEPARATOR
+            );
+        }
+
+        let path = match self.path() {
+            Some(parent) => format!("{}{}{}", parent, STEP_PATH_SEPARATOR, name),
+            None => name.to_string(),
+        };
+        self.steps.push(ActiveStep {
+            name: name.to_string(),
+            path,
+            started_at,
+        });
+
+        Ok(self.steps.last().unwrap())
+    }
+
+    /// Ends the innermost running step called `name`, with the steps still running inside it.
+    /// Returns the ended steps, innermost first.
+    pub fn end(&mut self, name: &str) -> Result<Vec<ActiveStep>> {
+        let Some(index) = self.steps.iter().rposition(|step| step.name == name) else {
+            bail!("Step {} is not running", name);
+        };
+
+        Ok(self.steps.drain(index..).rev().collect())
+    }
+
+    /// Ends every running step, innermost first.
+    pub fn end_all(&mut self) -> Vec<ActiveStep> {
+        self.steps.drain(..).rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(steps: &[ActiveStep]) -> Vec<&str> {
+        steps.iter().map(|step| step.path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_nested_steps() -> Result<()> {
+        let mut stack = StepStack::default();
+        assert_eq!(stack.path(), None);
+
+        stack.begin("align", Utc::now())?;
+        assert_eq!(stack.begin("index", Utc::now())?.path, "align/index");
+        assert_eq!(stack.path(), Some("align/index"));
+
+        assert_eq!(paths(&stack.end("index")?), ["align/index"]);
+        assert_eq!(stack.path(), Some("align"));
+
+        stack.begin("sort", Utc::now())?;
+        stack.begin("merge", Utc::now())?;
+        // Ending an outer step ends the ones left running inside it
+        assert_eq!(
+            paths(&stack.end("align")?),
+            ["align/sort/merge", "align/sort", "align"]
+        );
+        assert!(stack.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_steps() -> Result<()> {
+        let mut stack = StepStack::default();
+
+        assert!(stack.begin("", Utc::now()).is_err());
+        assert!(stack.begin("align/index", Utc::now()).is_err());
+        assert!(stack.end("align").is_err());
+
+        stack.begin("qc", Utc::now())?;
+        stack.begin("fastqc", Utc::now())?;
+        assert_eq!(paths(&stack.end_all()), ["qc/fastqc", "qc"]);
+
+        Ok(())
+    }
+}
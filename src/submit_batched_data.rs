@@ -1,19 +1,22 @@
 // src/submit_batched_data.rs
 use crate::event_recorder::EventRecorder;
+use crate::event_spool::EventSpool;
+use crate::exporters::{EventExporter, ExportContext};
 use crate::http_client::send_http_event;
 use crate::metrics::SystemMetricsCollector;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
 use serde_json::json;
 use std::time::{Duration, Instant};
 use sysinfo::System;
 use tracing::info;
 
 pub async fn submit_batched_data(
-    api_key: &str,
-    service_url: &str,
     system: &mut System,
     logs: &mut EventRecorder, // Todo and change: there should be a distinction between logs array and event recorder. The logs appears as vector while it isn't
+    exporters: &mut [Box<dyn EventExporter>],
+    context: &ExportContext<'_>,
     metrics_collector: &mut SystemMetricsCollector,
     last_sent: &mut Option<Instant>,
     interval: Duration,
@@ -23,16 +26,29 @@ pub async fn submit_batched_data(
             .collect_metrics(system, logs)
             .context("Failed to collect metrics")?;
 
-        let data = json!(logs.get_events());
+        let mut failed_exporters = vec![];
 
-        info!("Payload: {:#?}", data);
+        for exporter in exporters.iter_mut() {
+            if let Err(error) = exporter.export(logs.get_events(), context).await {
+                eprintln!(
+                    "[{}] Failed to export events to {}: {:?}",
+                    Utc::now(),
+                    exporter.name(),
+                    error
+                );
+                failed_exporters.push(exporter.name());
+            }
+        }
 
         *last_sent = Some(Instant::now());
         logs.clear();
 
-        send_http_event(service_url, api_key, &data)
-            .await
-            .context("Failed to send HTTP event")?;
+        if !failed_exporters.is_empty() {
+            bail!(
+                "Failed to export events to: {}",
+                failed_exporters.join(", ")
+            );
+        }
 
         Ok(())
     } else {
@@ -40,24 +56,67 @@ pub async fn submit_batched_data(
     }
 }
 
+/// Sends every spooled batch in order, removing each one only after the service accepted it.
+/// Stops at the first failure so the remaining batches are retried on the next call.
+pub async fn submit_spooled_batches(
+    service_url: &str,
+    api_key: &str,
+    spool: &mut EventSpool,
+) -> Result<()> {
+    for batch in spool.pending_batches()? {
+        let Ok(events) = spool.read_batch(&batch) else {
+            continue;
+        };
+
+        let data = json!(events);
+
+        info!("Payload: {:#?}", data);
+
+        send_http_event(service_url, api_key, &data)
+            .await
+            .context("Failed to send HTTP event")?;
+
+        spool.remove_batch(&batch)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config_manager::ConfigManager;
     use crate::event_recorder::{EventRecorder, EventType};
+    use crate::event_spool::SpoolLimits;
+    use crate::exporters::{HttpExporter, JsonLinesFileExporter};
+    use crate::http_client::split_event_slices;
     use crate::metrics::SystemMetricsCollector;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
     use anyhow::Result;
+    use serde_json::Value;
+    use std::collections::HashMap;
     use std::time::Duration;
     use sysinfo::System;
+    use tempfile::tempdir;
+
+    fn spool_limits() -> SpoolLimits {
+        SpoolLimits::from_config(&ConfigManager::load_default_config())
+    }
 
     #[tokio::test]
     async fn test_submit_batched_data() -> Result<()> {
-        let config = ConfigManager::load_default_config();
-        let service_url = config.service_url.clone();
-        let api_key = config.api_key.clone();
+        let service = MockService::start().await;
 
         let mut system = System::new();
         let mut logs = EventRecorder::new();
+        let spool_directory = tempdir()?;
+        let spool_directory_path = spool_directory.path().to_str().unwrap();
+        let mut exporters: Vec<Box<dyn EventExporter>> = vec![Box::new(HttpExporter::new(
+            service.url(),
+            MOCK_API_KEY,
+            spool_directory_path,
+            spool_limits(),
+        )?)];
         let mut metrics_collector = SystemMetricsCollector::new();
         let mut last_sent = None;
         let interval = Duration::from_secs(60);
@@ -72,16 +131,153 @@ mod tests {
 
         // Call the method to submit batched data
         submit_batched_data(
-            &api_key,
-            &service_url,
             &mut system,
             &mut logs,
+            &mut exporters,
+            &ExportContext {
+                run: None,
+                process_tree: &HashMap::new(),
+            },
             &mut metrics_collector,
             &mut last_sent,
             interval,
         )
         .await?;
 
+        assert!(logs.is_empty());
+        assert_eq!(
+            EventSpool::new(spool_directory_path, spool_limits())?.len(),
+            0
+        );
+
+        // The test event plus the system metrics event
+        let events = service.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["message"], "[submit_batched_data.rs] Test event");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_batches_stay_spooled() -> Result<()> {
+        let service = MockService::start().await;
+        let spool_directory = tempdir()?;
+        let mut spool = EventSpool::new(spool_directory.path().to_str().unwrap(), spool_limits())?;
+
+        let mut logs = EventRecorder::new();
+        logs.record_event(
+            EventType::TestEvent,
+            "[submit_batched_data.rs] Spooled event".to_string(),
+            None,
+            None,
+        );
+        spool.write_batch(logs.get_events())?;
+        spool.write_batch(logs.get_events())?;
+
+        service.respond_with_statuses("/data-collector-api", &[200, 400]);
+        assert!(
+            submit_spooled_batches(service.url(), MOCK_API_KEY, &mut spool)
+                .await
+                .is_err()
+        );
+        assert_eq!(spool.len(), 1);
+
+        submit_spooled_batches(service.url(), MOCK_API_KEY, &mut spool).await?;
+        assert_eq!(spool.len(), 0);
+        assert_eq!(service.requests_to("/data-collector-api").len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_split_batches_are_delivered_once() -> Result<()> {
+        let service = MockService::start().await;
+        let spool_directory = tempdir()?;
+        let mut spool = EventSpool::new(spool_directory.path().to_str().unwrap(), spool_limits())?;
+
+        let mut logs = EventRecorder::new();
+        for i in 0..3 {
+            logs.record_event(
+                EventType::TestEvent,
+                format!("[submit_batched_data.rs] Split event {}", i),
+                None,
+                None,
+            );
+        }
+        // One batch per event, as the http exporter spools a batch over the request limits
+        for batch in split_event_slices(logs.get_events(), usize::MAX, 1)? {
+            spool.write_batch(batch)?;
+        }
+
+        service.respond_with_statuses("/data-collector-api", &[200, 400]);
+        assert!(
+            submit_spooled_batches(service.url(), MOCK_API_KEY, &mut spool)
+                .await
+                .is_err()
+        );
+        submit_spooled_batches(service.url(), MOCK_API_KEY, &mut spool).await?;
+
+        // The second request was rejected, the service kept the events of the others
+        let messages: Vec<Value> = service
+            .requests_to("/data-collector-api")
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != 1)
+            .flat_map(|(_, request)| request.json()["logs"].as_array().cloned().unwrap())
+            .map(|event| event["message"].clone())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "[submit_batched_data.rs] Split event 0",
+                "[submit_batched_data.rs] Split event 1",
+                "[submit_batched_data.rs] Split event 2",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submit_batched_data_fans_out_to_every_exporter() -> Result<()> {
+        let directory = tempdir()?;
+        let first_path = directory.path().join("first.jsonl");
+        let second_path = directory.path().join("second.jsonl");
+
+        let mut system = System::new();
+        let mut logs = EventRecorder::new();
+        let mut exporters: Vec<Box<dyn EventExporter>> = vec![
+            Box::new(JsonLinesFileExporter::new(first_path.clone())),
+            Box::new(JsonLinesFileExporter::new(second_path.clone())),
+        ];
+        let mut last_sent = None;
+
+        logs.record_event(
+            EventType::TestEvent,
+            "[submit_batched_data.rs] Test event".to_string(),
+            None,
+            None,
+        );
+
+        submit_batched_data(
+            &mut system,
+            &mut logs,
+            &mut exporters,
+            &ExportContext {
+                run: None,
+                process_tree: &HashMap::new(),
+            },
+            &mut SystemMetricsCollector::new(),
+            &mut last_sent,
+            Duration::from_secs(60),
+        )
+        .await?;
+
+        // The test event plus the system metrics event
+        assert_eq!(std::fs::read_to_string(first_path)?.lines().count(), 2);
+        assert_eq!(std::fs::read_to_string(second_path)?.lines().count(), 2);
+        assert!(logs.is_empty());
+
         Ok(())
     }
 }
@@ -1,21 +1,36 @@
 // src/tracer_client.rs
-use crate::event_recorder::{EventRecorder, EventType};
-use crate::events::{send_end_run_event, send_start_run_event};
+use crate::daemon_state::{DaemonState, PersistedRun};
+use crate::event_attributes::{EventAttributes, RunDetails, StepAttributes};
+use crate::event_recorder::{Event, EventRecorder, EventType, RecordingRun};
+use crate::events::{record_offline_start_run_event, send_end_run_event, send_start_run_event};
+use crate::exporters::{
+    build_exporters, rebuild_exporters, EventExporter, ExportContext, ExporterConfig,
+};
 use crate::file_watcher::FileWatcher;
+use crate::http_client::configure_http_client;
 use crate::metrics::SystemMetricsCollector;
-use crate::process_watcher::ProcessWatcher;
+use crate::offline_archive::is_offline;
+use crate::process_watcher::{ProcessWatcher, TrackedProcess};
+use crate::prometheus::render_metrics;
+use crate::run_detection::{decide_run_action, RunAction, RunDetection, RunObservation};
+use crate::run_scope::{find_run_scope, RunAnchor, RunScope};
+use crate::run_summary::{RunSummary, RunTally};
+use crate::runtime_paths::RuntimePaths;
 use crate::stdout::StdoutWatcher;
+use crate::steps::{ActiveStep, StepStack};
 use crate::submit_batched_data::submit_batched_data;
 use crate::syslog::SyslogWatcher;
-use crate::FILE_CACHE_DIR;
 use crate::{config_manager::Config, process_watcher::ShortLivedProcessLog};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::json;
+use std::collections::HashMap;
 use std::ops::Sub;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use sysinfo::{Pid, System};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 #[derive(Clone)]
 pub struct RunMetadata {
@@ -25,52 +40,127 @@ pub struct RunMetadata {
     pub service_name: String,
     pub parent_pid: Option<Pid>,
     pub start_time: DateTime<Utc>,
+    pub details: RunDetails,
 }
 
-const RUN_COMPLICATED_PROCESS_IDENTIFICATION: bool = false;
-const WAIT_FOR_PROCESS_BEFORE_NEW_RUN: bool = false;
+impl RunMetadata {
+    fn recording_run(&self) -> RecordingRun {
+        RecordingRun {
+            id: self.id.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+impl From<&RunMetadata> for PersistedRun {
+    fn from(run: &RunMetadata) -> Self {
+        PersistedRun {
+            name: run.name.clone(),
+            id: run.id.clone(),
+            service_name: run.service_name.clone(),
+            parent_pid: run.parent_pid.map(|pid| pid.as_u32()),
+            start_time: run.start_time,
+            details: run.details.clone(),
+        }
+    }
+}
+
+impl From<PersistedRun> for RunMetadata {
+    fn from(run: PersistedRun) -> Self {
+        RunMetadata {
+            last_interaction: Instant::now(),
+            name: run.name,
+            id: run.id,
+            service_name: run.service_name,
+            parent_pid: run.parent_pid.map(Pid::from_u32),
+            start_time: run.start_time,
+            details: run.details,
+        }
+    }
+}
 
 pub type LinesBufferArc = Arc<RwLock<Vec<String>>>;
 
+/// Counters reported by `tracer status`.
+#[derive(Clone, Debug)]
+pub struct DaemonStats {
+    pub started_at: DateTime<Utc>,
+    pub last_successful_submission: Option<DateTime<Utc>>,
+    pub last_failed_submission: Option<DateTime<Utc>>,
+    pub submission_failures: u64,
+    pub file_upload_failures: u64,
+}
+
 pub struct TracerClient {
     system: System,
     last_sent: Option<Instant>,
     interval: Duration,
     last_interaction_new_run_duration: Duration,
+    run_detection: RunDetection,
     process_metrics_send_interval: Duration,
     last_file_size_change_time_delta: TimeDelta,
     pub logs: EventRecorder,
+    exporters: Vec<Box<dyn EventExporter>>,
+    /// The config entries `exporters` were built from
+    exporter_configs: Vec<ExporterConfig>,
     process_watcher: ProcessWatcher,
     syslog_watcher: SyslogWatcher,
     stdout_watcher: StdoutWatcher,
     metrics_collector: SystemMetricsCollector,
     file_watcher: FileWatcher,
     workflow_directory: String,
+    file_cache_dir: String,
+    spool_dir: String,
+    state_file: String,
     api_key: String,
     service_url: String,
     current_run: Option<RunMetadata>,
+    /// Runs started with an anchor, running alongside the current run
+    scoped_runs: Vec<RunMetadata>,
+    /// Summaries of the runs in progress, by run id
+    run_tallies: HashMap<String, RunTally>,
+    /// Recorded events already added to the tallies
+    tallied_events: usize,
+    /// Steps running in each run, by run id
+    steps: HashMap<String, StepStack>,
+    stats: DaemonStats,
     syslog_lines_buffer: LinesBufferArc,
     stdout_lines_buffer: LinesBufferArc,
     stderr_lines_buffer: LinesBufferArc,
 }
 
 impl TracerClient {
-    pub async fn new(config: Config, workflow_directory: String) -> Result<TracerClient> {
+    pub async fn new(
+        config: Config,
+        workflow_directory: String,
+        paths: &RuntimePaths,
+    ) -> Result<TracerClient> {
         let service_url = config.service_url.clone();
 
         println!("Initializing TracerClient with API Key: {}", config.api_key);
         println!("Service URL: {}", service_url);
 
-        let file_watcher = FileWatcher::new();
+        let mut state = DaemonState::load(Path::new(&paths.state_file)).unwrap_or_else(|error| {
+            eprintln!("[{}] Starting afresh: {:#}", Utc::now(), error);
+            None
+        });
+
+        let mut file_watcher = FileWatcher::new();
+        if let Some(state) = state.as_mut() {
+            file_watcher.restore_watched_files(std::mem::take(&mut state.watched_files));
+        }
+
+        file_watcher.prepare_cache_directory(&paths.file_cache_dir)?;
 
-        file_watcher.prepare_cache_directory(FILE_CACHE_DIR)?;
+        let exporters = build_exporters(&config, &workflow_directory, &paths.spool_dir)?;
 
-        Ok(TracerClient {
+        let mut client = TracerClient {
             // fixed values
             api_key: config.api_key,
             service_url,
             interval: Duration::from_millis(config.process_polling_interval_ms),
             last_interaction_new_run_duration: Duration::from_millis(config.new_run_pause_ms),
+            run_detection: config.run_detection,
             process_metrics_send_interval: Duration::from_millis(
                 config.process_metrics_send_interval_ms,
             ),
@@ -81,25 +171,138 @@ impl TracerClient {
             system: System::new_all(),
             last_sent: None,
             current_run: None,
+            scoped_runs: Vec::new(),
+            run_tallies: HashMap::new(),
+            tallied_events: 0,
+            steps: HashMap::new(),
+            stats: DaemonStats {
+                started_at: Utc::now(),
+                last_successful_submission: None,
+                last_failed_submission: None,
+                submission_failures: 0,
+                file_upload_failures: 0,
+            },
             syslog_watcher: SyslogWatcher::new(),
             stdout_watcher: StdoutWatcher::new(),
             // Sub mannagers
             logs: EventRecorder::new(),
+            exporters,
+            exporter_configs: config.exporters,
             file_watcher,
             workflow_directory,
+            file_cache_dir: paths.file_cache_dir.clone(),
+            spool_dir: paths.spool_dir.clone(),
+            state_file: paths.state_file.clone(),
             syslog_lines_buffer: Arc::new(RwLock::new(Vec::new())),
             stdout_lines_buffer: Arc::new(RwLock::new(Vec::new())),
             stderr_lines_buffer: Arc::new(RwLock::new(Vec::new())),
             process_watcher: ProcessWatcher::new(config.targets),
             metrics_collector: SystemMetricsCollector::new(),
-        })
+        };
+
+        if let Some(state) = state {
+            client.restore_state(state);
+        }
+        Ok(client)
+    }
+
+    /// Continues the runs of the previous daemon and tracks its tools that are still running.
+    fn restore_state(&mut self, state: DaemonState) {
+        self.run_tallies = state.run_tallies;
+        self.steps = state.steps;
+        self.scoped_runs = state
+            .scoped_runs
+            .into_iter()
+            .map(RunMetadata::from)
+            .collect();
+        self.sync_run_scopes();
+        self.set_current_run(state.current_run.map(RunMetadata::from));
+        self.process_watcher
+            .restore_processes(&self.system, state.processes);
+
+        if let Some(run) = &self.current_run {
+            println!("Resuming run {} ({})", run.name, run.id);
+        }
+    }
+
+    /// Saves the runs and the watchers for the next daemon started in the same directory.
+    pub fn save_state(&mut self) -> Result<()> {
+        self.tally_events();
+
+        let state = DaemonState {
+            current_run: self.current_run.as_ref().map(PersistedRun::from),
+            scoped_runs: self.scoped_runs.iter().map(PersistedRun::from).collect(),
+            processes: self.process_watcher.persisted_processes(&self.system),
+            watched_files: self.file_watcher.watched_files(),
+            run_tallies: std::mem::take(&mut self.run_tallies),
+            steps: std::mem::take(&mut self.steps),
+        };
+        let result = state.save(Path::new(&self.state_file));
+        self.run_tallies = state.run_tallies;
+        self.steps = state.steps;
+
+        result
+    }
+
+    /// Ends the runs and saves the state on a graceful shutdown, so only a daemon that crashed
+    /// or was replaced leaves runs for the next one to resume.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        let anchors: Vec<RunAnchor> = self
+            .scoped_runs
+            .iter()
+            .filter_map(|run| run.details.anchor.clone())
+            .collect();
+        for anchor in anchors {
+            if let Err(error) = self
+                .end_scoped_run(&anchor, Some("Run ended due to daemon termination"))
+                .await
+            {
+                eprintln!("[{}] Failed to end scoped run: {:#}", Utc::now(), error);
+            }
+        }
+
+        if let Err(error) = self.stop_run(None).await {
+            eprintln!("[{}] Failed to end run: {:#}", Utc::now(), error);
+        }
+
+        if let Err(error) = self.submit_batched_data().await {
+            eprintln!(
+                "[{}] Failed to submit batched data: {:#}",
+                Utc::now(),
+                error
+            );
+        }
+
+        // Whatever the exporters still track outside a run is closed out as well
+        self.end_exporter_runs();
+        if let Err(error) = self.flush_exporters().await {
+            eprintln!("[{}] Failed to flush exporters: {:#}", Utc::now(), error);
+        }
+
+        self.save_state()
     }
 
     pub fn reload_config_file(&mut self, config: &Config) {
+        if let Err(error) = configure_http_client(config) {
+            eprintln!("Failed to reconfigure http client: {}", error);
+        }
         self.api_key.clone_from(&config.api_key);
         self.service_url.clone_from(&config.service_url);
         self.interval = Duration::from_millis(config.process_polling_interval_ms);
+        self.last_interaction_new_run_duration = Duration::from_millis(config.new_run_pause_ms);
+        self.run_detection = config.run_detection;
         self.process_watcher.reload_targets(config.targets.clone());
+
+        match rebuild_exporters(
+            config,
+            &self.exporter_configs,
+            &mut self.exporters,
+            &self.workflow_directory,
+            &self.spool_dir,
+        ) {
+            Ok(()) => self.exporter_configs.clone_from(&config.exporters),
+            Err(error) => eprintln!("Failed to rebuild exporters: {}", error),
+        }
     }
 
     pub fn fill_logs_with_short_lived_process(
@@ -123,85 +326,323 @@ impl TracerClient {
     }
 
     pub async fn submit_batched_data(&mut self) -> Result<()> {
-        submit_batched_data(
-            &self.api_key,
-            &self.service_url,
+        // Submitted events are cleared, so they are added to the run summaries first
+        self.tally_events();
+
+        let context = ExportContext {
+            run: self.current_run.as_ref(),
+            process_tree: self.process_watcher.get_process_tree(),
+        };
+
+        let previous_submission = self.last_sent;
+        let result = submit_batched_data(
             &mut self.system,
             &mut self.logs,
+            &mut self.exporters,
+            &context,
             &mut self.metrics_collector,
             &mut self.last_sent,
             self.interval,
         )
-        .await
+        .await;
+        self.tallied_events = self.logs.len();
+
+        match &result {
+            Err(_) => {
+                self.stats.last_failed_submission = Some(Utc::now());
+                self.stats.submission_failures += 1;
+            }
+            // Nothing is sent before the submission interval elapsed
+            Ok(()) if self.last_sent != previous_submission => {
+                self.stats.last_successful_submission = Some(Utc::now());
+            }
+            Ok(()) => {}
+        }
+
+        result
+    }
+
+    /// Delivers batches left over from failed submissions or a previous daemon instance.
+    pub async fn flush_exporters(&mut self) -> Result<()> {
+        for exporter in self.exporters.iter_mut() {
+            exporter.flush().await?;
+        }
+        Ok(())
+    }
+
+    fn end_exporter_runs(&mut self) {
+        for exporter in self.exporters.iter_mut() {
+            exporter.end_run();
+        }
     }
 
     pub fn get_run_metadata(&self) -> Option<RunMetadata> {
         self.current_run.clone()
     }
 
+    pub fn scoped_runs(&self) -> &[RunMetadata] {
+        &self.scoped_runs
+    }
+
+    pub fn get_stats(&self) -> DaemonStats {
+        self.stats.clone()
+    }
+
+    pub fn tracked_process_count(&self) -> usize {
+        self.process_watcher.len()
+    }
+
+    pub fn buffered_event_count(&self) -> usize {
+        self.logs.len()
+    }
+
+    pub fn spooled_batch_count(&self) -> usize {
+        self.exporters
+            .iter()
+            .map(|exporter| exporter.pending_batches())
+            .sum()
+    }
+
+    pub fn pending_file_upload_count(&self) -> usize {
+        self.file_watcher.pending_upload_count()
+    }
+
+    pub fn dropped_batch_count(&self) -> u64 {
+        self.exporters
+            .iter()
+            .map(|exporter| exporter.dropped_batches())
+            .sum()
+    }
+
+    /// Starts and ends runs as the configured run detection mode decides. Scoped runs end when
+    /// their root process exits, whatever the mode.
     pub async fn run_cleanup(&mut self) -> Result<()> {
-        if let Some(run) = self.current_run.as_mut() {
-            if !RUN_COMPLICATED_PROCESS_IDENTIFICATION {
-                return Ok(());
-            }
-            if run.last_interaction.elapsed() > self.last_interaction_new_run_duration {
-                self.logs.record_event(
-                    EventType::FinishedRun,
-                    "Run ended due to inactivity".to_string(),
-                    None,
+        self.end_exited_scoped_runs().await?;
+
+        if self.run_detection == RunDetection::Manual {
+            return Ok(());
+        }
+
+        let observation = self.observe_run();
+        let action = decide_run_action(
+            self.run_detection,
+            &observation,
+            self.last_interaction_new_run_duration,
+        );
+
+        match action {
+            RunAction::Keep => {}
+            RunAction::Start => {
+                let earliest_process_time = self.process_watcher.get_earliest_process_time();
+                self.start_new_run(
+                    Some(earliest_process_time.sub(Duration::from_millis(1))),
                     None,
-                );
-                self.current_run = None;
-            } else if run.parent_pid.is_none() && !self.process_watcher.is_empty() {
-                run.parent_pid = self.process_watcher.get_parent_pid(Some(run.start_time));
-            } else if run.parent_pid.is_some() {
-                let parent_pid = run.parent_pid.unwrap();
-                if !self
-                    .process_watcher
-                    .is_process_alive(&self.system, parent_pid)
-                {
-                    self.logs.record_event(
-                        EventType::FinishedRun,
-                        "Run ended due to parent process termination".to_string(),
-                        None,
-                        None,
-                    );
-                    self.current_run = None;
+                    RunDetails::default(),
+                )
+                .await?;
+            }
+            RunAction::End { reason } => {
+                self.stop_run(Some(reason)).await?;
+            }
+            RunAction::SetParent(pid) => {
+                if let Some(run) = self.current_run.as_mut() {
+                    run.parent_pid = Some(pid);
                 }
             }
-        } else if !WAIT_FOR_PROCESS_BEFORE_NEW_RUN || !self.process_watcher.is_empty() {
-            let earliest_process_time = self.process_watcher.get_earliest_process_time();
-            self.start_new_run(Some(earliest_process_time.sub(Duration::from_millis(1))))
-                .await?;
         }
         Ok(())
     }
 
-    pub async fn start_new_run(&mut self, timestamp: Option<DateTime<Utc>>) -> Result<()> {
-        if self.current_run.is_some() {
-            self.stop_run().await?;
+    fn observe_run(&self) -> RunObservation {
+        let run = self.current_run.as_ref();
+        let parent_pid = run.and_then(|run| run.parent_pid);
+
+        RunObservation {
+            has_run: run.is_some(),
+            idle: run
+                .map(|run| run.last_interaction.elapsed())
+                .unwrap_or_default(),
+            parent_pid,
+            parent_alive: parent_pid
+                .is_some_and(|pid| self.process_watcher.is_process_alive(&self.system, pid)),
+            // Walking the process tree is only worth it until the parent is found
+            candidate_parent_pid: run
+                .filter(|run| {
+                    self.run_detection == RunDetection::ParentProcess && run.parent_pid.is_none()
+                })
+                .and_then(|run| self.process_watcher.get_parent_pid(Some(run.start_time))),
+            tracking_processes: !self.process_watcher.is_empty(),
         }
+    }
+
+    async fn end_exited_scoped_runs(&mut self) -> Result<()> {
+        let exited: Vec<RunAnchor> = self
+            .scoped_runs
+            .iter()
+            .filter_map(|run| run.details.anchor.clone())
+            .filter(|anchor| {
+                matches!(anchor, RunAnchor::RootPid(pid)
+                    if !self.process_watcher.is_process_alive(&self.system, Pid::from_u32(*pid)))
+            })
+            .collect();
+
+        for anchor in exited {
+            self.end_scoped_run(&anchor, Some("Run ended due to root process termination"))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn set_current_run(&mut self, run: Option<RunMetadata>) {
+        self.logs
+            .set_run(run.as_ref().map(RunMetadata::recording_run));
+        self.current_run = run;
+        self.sync_active_steps();
+    }
+
+    fn run_scopes(&self) -> Vec<RunScope> {
+        self.scoped_runs
+            .iter()
+            .filter_map(|run| {
+                Some(RunScope {
+                    anchor: run.details.anchor.clone()?,
+                    run: run.recording_run(),
+                })
+            })
+            .collect()
+    }
 
-        let result = send_start_run_event(&self.service_url, &self.api_key, &self.system).await?;
+    fn sync_run_scopes(&mut self) {
+        self.process_watcher.set_run_scopes(self.run_scopes());
+    }
+
+    pub fn run_detection(&self) -> RunDetection {
+        self.run_detection
+    }
+
+    /// Ends the current run and starts a new one, named by the service unless `name` is given.
+    /// A run with an anchor only replaces the run with the same anchor, and leaves the current
+    /// run alone.
+    pub async fn start_new_run(
+        &mut self,
+        timestamp: Option<DateTime<Utc>>,
+        name: Option<String>,
+        details: RunDetails,
+    ) -> Result<RunMetadata> {
+        match &details.anchor {
+            Some(anchor) => self.end_scoped_run(anchor, None).await?,
+            None => self.stop_run(None).await?,
+        };
+
+        let result = if is_offline() {
+            record_offline_start_run_event(
+                &self.service_url,
+                &self.api_key,
+                &self.system,
+                name,
+                &details,
+            )
+            .await?
+        } else {
+            send_start_run_event(
+                &self.service_url,
+                &self.api_key,
+                &self.system,
+                name,
+                &details,
+            )
+            .await?
+        };
 
-        self.current_run = Some(RunMetadata {
+        let run = RunMetadata {
             last_interaction: Instant::now(),
             parent_pid: None,
             start_time: timestamp.unwrap_or_else(Utc::now),
             name: result.run_name,
             id: result.run_id,
             service_name: result.service_name,
-        });
+            details,
+        };
+
+        self.run_tallies.insert(run.id.clone(), RunTally::default());
+        if run.details.anchor.is_some() {
+            self.scoped_runs.push(run.clone());
+            self.sync_run_scopes();
+        } else {
+            self.set_current_run(Some(run.clone()));
+        }
 
-        Ok(())
+        Ok(run)
+    }
+
+    /// Ends the current run, returning its summary.
+    /// Ends the current run, with `reason` as the message of its end.
+    pub async fn stop_run(&mut self, reason: Option<&str>) -> Result<Option<RunSummary>> {
+        let Some(run) = self.current_run.clone() else {
+            return Ok(None);
+        };
+
+        self.send_end_run(&run, reason).await;
+        let summary = self.summarize_run(&run);
+        self.set_current_run(None);
+        self.end_exporter_runs();
+        Ok(Some(summary))
     }
 
-    pub async fn stop_run(&mut self) -> Result<()> {
-        if self.current_run.is_some() {
-            send_end_run_event(&self.service_url, &self.api_key).await?;
-            self.current_run = None;
+    /// Tells the service the run ended. While it is unreachable the run ends all the same, and
+    /// the event goes through the spool with the other events instead.
+    async fn send_end_run(&mut self, run: &RunMetadata, reason: Option<&str>) {
+        let message = reason.unwrap_or("[CLI] Finishing pipeline run");
+        if let Err(error) =
+            send_end_run_event(&self.service_url, &self.api_key, &run.id, message).await
+        {
+            eprintln!(
+                "[{}] Failed to send the end of run {}, spooling it: {:#}",
+                Utc::now(),
+                run.id,
+                error
+            );
+            self.logs.record_scoped_event(
+                Some(&run.recording_run()),
+                EventType::FinishedRun,
+                message.to_string(),
+                None,
+                None,
+            );
         }
-        Ok(())
+    }
+
+    /// Ends the run scoped to `anchor`, or the current run without one, returning its summary.
+    pub async fn end_run(&mut self, anchor: Option<&RunAnchor>) -> Result<Option<RunSummary>> {
+        match anchor {
+            Some(anchor) if self.scoped_run_index(anchor).is_none() => {
+                bail!("No run is scoped to {}", anchor.describe())
+            }
+            Some(anchor) => self.end_scoped_run(anchor, None).await,
+            None => self.stop_run(None).await,
+        }
+    }
+
+    fn scoped_run_index(&self, anchor: &RunAnchor) -> Option<usize> {
+        self.scoped_runs
+            .iter()
+            .position(|run| run.details.anchor.as_ref() == Some(anchor))
+    }
+
+    /// Ends the run scoped to `anchor`, recording why when it wasn't asked for.
+    async fn end_scoped_run(
+        &mut self,
+        anchor: &RunAnchor,
+        reason: Option<&str>,
+    ) -> Result<Option<RunSummary>> {
+        let Some(index) = self.scoped_run_index(anchor) else {
+            return Ok(None);
+        };
+        let run = self.scoped_runs.remove(index);
+        self.sync_run_scopes();
+
+        self.send_end_run(&run, reason).await;
+        Ok(Some(self.summarize_run(&run)))
     }
 
     /// These functions require logs and the system
@@ -212,8 +653,10 @@ impl TracerClient {
             &self.file_watcher,
         )?;
 
-        if self.current_run.is_some() && !self.process_watcher.is_empty() {
-            self.current_run.as_mut().unwrap().last_interaction = Instant::now();
+        if let Some(run) = self.current_run.as_mut() {
+            if !self.process_watcher.is_empty() {
+                run.last_interaction = Instant::now();
+            }
         }
         Ok(())
     }
@@ -234,16 +677,170 @@ impl TracerClient {
     }
 
     pub async fn poll_files(&mut self) -> Result<()> {
-        self.file_watcher
+        let result = self
+            .file_watcher
             .poll_files(
                 &self.service_url,
                 &self.api_key,
                 &self.workflow_directory,
-                FILE_CACHE_DIR,
+                &self.file_cache_dir,
                 self.last_file_size_change_time_delta,
             )
-            .await?;
-        Ok(())
+            .await;
+
+        match result {
+            Ok(uploaded) => {
+                for path in uploaded {
+                    self.record_upload(path);
+                }
+                Ok(())
+            }
+            Err(error) => {
+                self.stats.file_upload_failures += 1;
+                Err(error)
+            }
+        }
+    }
+
+    /// Counts an uploaded file in the run scoped to its directory, or the current run.
+    fn record_upload(&mut self, path: String) {
+        let scopes = self.run_scopes();
+        let run_id = find_run_scope(&scopes, &[], Some(Path::new(&path)))
+            .map(|scope| &scope.run.id)
+            .or(self.current_run.as_ref().map(|run| &run.id));
+
+        if let Some(tally) = run_id.and_then(|run_id| self.run_tallies.get_mut(run_id)) {
+            tally.record_upload(path);
+        }
+    }
+
+    /// Adds the events recorded since the last call to the summaries of their runs.
+    fn tally_events(&mut self) {
+        for event in &self.logs.get_events()[self.tallied_events.min(self.logs.len())..] {
+            let tally = event
+                .run_id
+                .as_ref()
+                .and_then(|run_id| self.run_tallies.get_mut(run_id));
+            if let Some(tally) = tally {
+                tally.record(event);
+            }
+        }
+        self.tallied_events = self.logs.len();
+    }
+
+    /// The run steps are marked in: the run scoped to `anchor`, or the current run without one.
+    fn step_run(&self, anchor: Option<&RunAnchor>) -> Result<RecordingRun> {
+        let run = match anchor {
+            Some(anchor) => self
+                .scoped_run_index(anchor)
+                .map(|index| &self.scoped_runs[index]),
+            None => self.current_run.as_ref(),
+        };
+
+        match (run, anchor) {
+            (Some(run), _) => Ok(run.recording_run()),
+            (None, Some(anchor)) => bail!("No run is scoped to {}", anchor.describe()),
+            (None, None) => bail!("No run is in progress"),
+        }
+    }
+
+    /// Starts a step nested in the running one, in the run scoped to `anchor` or the current
+    /// run without one. Tools started from now on are tagged with its path.
+    pub fn begin_step(&mut self, name: &str, anchor: Option<&RunAnchor>) -> Result<ActiveStep> {
+        let run = self.step_run(anchor)?;
+        let step = self
+            .steps
+            .entry(run.id.clone())
+            .or_default()
+            .begin(name, Utc::now())?
+            .clone();
+
+        self.logs.record_scoped_event(
+            Some(&run),
+            EventType::StepStarted,
+            format!("[{}] Step started: {}", step.started_at, step.path),
+            Some(EventAttributes::Step(StepAttributes {
+                step_name: step.name.clone(),
+                step_path: step.path.clone(),
+                duration: None,
+            })),
+            Some(step.started_at),
+        );
+        self.sync_active_steps();
+
+        Ok(step)
+    }
+
+    /// Ends the innermost running step called `name`, with the steps still running inside it,
+    /// returning the ended steps innermost first.
+    pub fn end_step(&mut self, name: &str, anchor: Option<&RunAnchor>) -> Result<Vec<ActiveStep>> {
+        let run = self.step_run(anchor)?;
+        let ended = match self.steps.get_mut(&run.id) {
+            Some(stack) => stack.end(name)?,
+            None => bail!("Step {} is not running", name),
+        };
+
+        self.record_finished_steps(&run, &ended);
+        Ok(ended)
+    }
+
+    /// Ends the steps left running in a run that ended.
+    fn end_open_steps(&mut self, run: &RecordingRun) {
+        if let Some(mut stack) = self.steps.remove(&run.id) {
+            self.record_finished_steps(run, &stack.end_all());
+        }
+    }
+
+    fn record_finished_steps(&mut self, run: &RecordingRun, steps: &[ActiveStep]) {
+        let ended_at = Utc::now();
+        for step in steps {
+            let duration = (ended_at - step.started_at).num_milliseconds().max(0) as u64;
+            self.logs.record_scoped_event(
+                Some(run),
+                EventType::StepFinished,
+                format!("[{}] Step finished: {}", ended_at, step.path),
+                Some(EventAttributes::Step(StepAttributes {
+                    step_name: step.name.clone(),
+                    step_path: step.path.clone(),
+                    duration: Some(duration),
+                })),
+                Some(ended_at),
+            );
+        }
+
+        self.steps.retain(|_, stack| !stack.is_empty());
+        self.sync_active_steps();
+    }
+
+    /// Tells the process watcher the innermost running step of each run.
+    fn sync_active_steps(&mut self) {
+        let current_run_id = self.current_run.as_ref().map(|run| &run.id);
+        let active_steps = self
+            .steps
+            .iter()
+            .filter_map(|(run_id, stack)| {
+                let run = (Some(run_id) != current_run_id).then(|| run_id.clone());
+                Some((run, stack.path()?.to_string()))
+            })
+            .collect();
+
+        self.process_watcher.set_active_steps(active_steps);
+    }
+
+    /// Writes the summary of a run that ended to the workflow directory.
+    fn summarize_run(&mut self, run: &RunMetadata) -> RunSummary {
+        self.end_open_steps(&run.recording_run());
+        self.tally_events();
+        let summary = self
+            .run_tallies
+            .remove(&run.id)
+            .unwrap_or_default()
+            .summarize(&run.recording_run(), run.start_time, Utc::now());
+
+        if let Err(error) = summary.write_to(Path::new(&self.workflow_directory)) {
+            eprintln!("[{}] Failed to write run summary: {:#}", Utc::now(), error);
+        }
+        summary
     }
 
     pub async fn poll_syslog(&mut self) -> Result<()> {
@@ -268,6 +865,26 @@ impl TracerClient {
             .await
     }
 
+    pub fn render_prometheus_metrics(&mut self) -> String {
+        let system_metrics =
+            SystemMetricsCollector::gather_metrics_object_attributes(&mut self.system);
+        let tools = self
+            .process_watcher
+            .tracked_process_properties(&self.system);
+        let run_id = self.current_run.as_ref().map(|run| run.id.as_str());
+
+        render_metrics(&json!(system_metrics), &tools, run_id)
+    }
+
+    /// Events recorded since the last submission, and a receiver for the ones to come.
+    pub fn subscribe_events(&self) -> (Vec<Event>, broadcast::Receiver<Event>) {
+        (self.logs.get_events().to_vec(), self.logs.subscribe())
+    }
+
+    pub fn tracked_processes(&self) -> Vec<TrackedProcess> {
+        self.process_watcher.tracked_processes(&self.system)
+    }
+
     pub fn refresh_sysinfo(&mut self) {
         self.system.refresh_all();
     }
@@ -7,6 +7,7 @@ use std::fs;
 use std::path::Path;
 
 use crate::debug_log::Logger;
+use crate::offline_archive::record_offline_upload;
 use crate::upload::upload_to_signed_url::upload_file_to_signed_url_s3;
 
 pub async fn upload_from_file_path(
@@ -61,6 +62,16 @@ pub async fn upload_from_file_path(
         .log(&format!("File size: {} bytes", file_size), None)
         .await;
 
+    if record_offline_upload(path, file_name)? {
+        logger
+            .log(
+                &format!("File '{}' stored in the offline archive", file_name),
+                None,
+            )
+            .await;
+        return Ok(());
+    }
+
     // Step #4: Request the upload URL
     let signed_url = request_presigned_url(service_url, api_key, file_name).await?;
 
@@ -82,24 +93,32 @@ pub async fn upload_from_file_path(
 #[cfg(test)]
 mod tests {
     use crate::config_manager::ConfigManager;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
 
     use super::*;
     use std::fs::File;
     use std::io::Write;
+    use tempfile::tempdir;
 
     #[tokio::test]
     async fn test_upload_from_file_path() -> Result<()> {
-        // Use an existing file in your project
-        let file_path = "log_outgoing_http_calls.txt";
-        let config = ConfigManager::load_default_config();
+        let service = MockService::start().await;
+        let directory = tempdir()?;
+        let file_path = directory.path().join("upload_test_file.txt");
+        fs::write(&file_path, "uploaded content")?;
+        let file_path = file_path.to_str().unwrap();
 
         // Ensure the file exists before running the test
         assert!(Path::new(file_path).exists(), "Test file does not exist");
 
-        let result =
-            upload_from_file_path(&config.service_url, &config.api_key, file_path, None).await;
+        let result = upload_from_file_path(service.url(), MOCK_API_KEY, file_path, None).await;
         assert!(result.is_ok(), "Upload failed: {:?}", result.err());
 
+        assert_eq!(
+            service.uploaded_file("upload_test_file.txt"),
+            Some(b"uploaded content".to_vec())
+        );
+
         Ok(())
     }
 
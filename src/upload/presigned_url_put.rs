@@ -58,25 +58,23 @@ pub async fn request_presigned_url(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config_manager::ConfigManager;
+    use crate::mock_service::{MockService, MOCK_API_KEY};
 
     #[tokio::test]
     async fn test_request_presigned_url() -> Result<()> {
-        // Load the configuration
-        let config = ConfigManager::load_default_config();
-        let api_key = config.api_key.clone();
+        let service = MockService::start().await;
 
         // Test file name
         let file_name = "log_outgoing_http_calls.txt";
 
         // Call the function
-        let presigned_url = request_presigned_url(&config.service_url, &api_key, file_name).await?;
+        let presigned_url = request_presigned_url(service.url(), MOCK_API_KEY, file_name).await?;
 
         // Validate the returned presigned URL
         let url = Url::parse(&presigned_url)?;
 
         // Check if the URL is valid
-        assert!(url.scheme() == "https", "URL scheme should be https");
+        assert!(url.scheme() == "http", "URL scheme should be http");
         assert!(url.host_str().is_some(), "URL should have a host");
 
         // Check if the URL contains the file name
@@ -92,6 +90,10 @@ mod tests {
             "URL should contain X-Amz-Signature"
         );
 
+        // Check that the file name was passed to the service
+        let requests = service.requests_to("/upload/presigned-put");
+        assert_eq!(requests[0].query["fileName"], file_name);
+
         Ok(())
     }
 }
@@ -82,26 +82,26 @@ pub async fn upload_file_to_signed_url_s3(
 #[cfg(test)]
 mod tests {
 
-    use crate::{config_manager::ConfigManager, upload::presigned_url_put::request_presigned_url};
+    use crate::{
+        mock_service::{MockService, MOCK_API_KEY},
+        upload::presigned_url_put::request_presigned_url,
+    };
 
     use super::*;
+    use tempfile::tempdir;
 
     #[tokio::test]
     async fn test_upload_file_to_s3_execution() {
         // Initialize the logger for tests
         let _ = env_logger::builder().is_test(true).try_init();
 
-        // Use the existing file path
-        let file_path = "log_outgoing_http_calls.txt";
+        let service = MockService::start().await;
+        let directory = tempdir().unwrap();
+        let file_path = directory.path().join("s3_test_file.txt");
+        std::fs::write(&file_path, "s3 content").unwrap();
+        let file_path = file_path.to_str().unwrap();
 
-        // Check if the file exists
-        if !std::path::Path::new(file_path).exists() {
-            panic!("The test file '{}' does not exist. Please ensure the file is present before running the test.", file_path);
-        }
-
-        let config = ConfigManager::load_default_config();
-
-        let signed_url = request_presigned_url(&config.service_url, &config.api_key, &file_path)
+        let signed_url = request_presigned_url(service.url(), MOCK_API_KEY, "s3_test_file.txt")
             .await
             .unwrap();
 
@@ -109,5 +109,9 @@ mod tests {
 
         // Assert the result
         assert!(result.is_ok());
+        assert_eq!(
+            service.uploaded_file("s3_test_file.txt"),
+            Some(b"s3 content".to_vec())
+        );
     }
 }